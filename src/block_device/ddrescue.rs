@@ -0,0 +1,169 @@
+use crate::block_device::{BlockCount, BlockDevice, BlockNumber, BlockSize};
+use std::io;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// A mapfile written by GNU ddrescue, which records the state of each run of
+/// bytes of the device it copied: `+` for those read, and `?`, `*`, `/` or
+/// `-` for those that were not, or not yet.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct DdrescueMap {
+    /// In order, each with its status.
+    pub blocks: Vec<(Range<u64>, char)>,
+}
+
+impl DdrescueMap {
+    /// The runs of bytes that were never read, in order.
+    pub fn unreadable(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.blocks
+            .iter()
+            .filter(|(_, status)| *status != '+')
+            .map(|(range, _)| range.clone())
+    }
+}
+
+/// Parses a number as ddrescue writes them in its mapfiles.
+pub fn parse_hex(s: &str) -> Result<u64, String> {
+    s.strip_prefix("0x")
+        .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| format!("invalid number in map: {s}"))
+}
+
+impl FromStr for DdrescueMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        // The first line is the state of ddrescue itself.
+        lines.next().ok_or("map has no status line")?;
+
+        let mut blocks: Vec<(Range<u64>, char)> = Vec::new();
+        for line in lines {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [start, size, status] = fields[..] else {
+                return Err(format!("invalid line in map: {line}"));
+            };
+            let start = parse_hex(start)?;
+            let end = start
+                .checked_add(parse_hex(size)?)
+                .ok_or_else(|| format!("invalid line in map: {line}"))?;
+            let status = match status {
+                "?" | "*" | "/" | "-" | "+" => status.chars().next().unwrap_or('?'),
+                _ => return Err(format!("unsupported status in map: {status}")),
+            };
+            if blocks.last().is_some_and(|(last, _)| last.end > start) {
+                return Err(format!("map is out of order at 0x{start:08X}"));
+            }
+            blocks.push((start..end, status));
+        }
+        Ok(Self { blocks })
+    }
+}
+
+/// Wraps an image copied by GNU ddrescue, and fails to read the blocks that
+/// ddrescue could not read, rather than returning the zeros it left in
+/// their place. An array can then recover them from the other members.
+#[derive(Debug)]
+pub struct DdrescueBlockDevice<D: BlockDevice> {
+    device: D,
+
+    /// The byte ranges that were never read, in order.
+    unreadable: Rc<Vec<Range<u64>>>,
+}
+
+impl<D: BlockDevice> DdrescueBlockDevice<D> {
+    pub fn new(device: D, map: &DdrescueMap) -> Self {
+        Self {
+            device,
+            unreadable: Rc::new(map.unreadable().collect()),
+        }
+    }
+
+    /// Reads every block of `device`.
+    pub fn without_map(device: D) -> Self {
+        Self {
+            device,
+            unreadable: Rc::new(Vec::new()),
+        }
+    }
+
+    fn is_readable(&self, bytes: Range<u64>) -> bool {
+        let index = self
+            .unreadable
+            .partition_point(|range| range.end <= bytes.start);
+        self.unreadable
+            .get(index)
+            .is_none_or(|range| range.start >= bytes.end)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for DdrescueBlockDevice<D> {
+    fn block_size(&self) -> io::Result<BlockSize> {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> io::Result<BlockCount> {
+        self.device.block_count()
+    }
+
+    fn read_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> io::Result<usize> {
+        let block_size = self.device.block_size()?;
+        let start = block_number
+            .byte_pos(block_size)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        if !self.is_readable(start..start + u64::from(block_size)) {
+            Err(io::ErrorKind::InvalidData)?;
+        }
+        self.device.read_block(block_number, buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            device: self.device.try_clone()?,
+            unreadable: self.unreadable.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{
+        BlockDevice, BlockNumber, BlockSize, DdrescueBlockDevice, DdrescueMap, InMemoryBlockDevice,
+    };
+
+    #[test]
+    fn fails_unread_blocks() {
+        let map = "# Mapfile. Created by GNU ddrescue version 1.27\n\
+                   # current_pos  current_status  current_pass\n\
+                   0x00000600     +               1\n\
+                   #      pos        size  status\n\
+                   0x00000000  0x00000300  +\n\
+                   0x00000300  0x00000100  -\n\
+                   0x00000400  0x00000400  +\n\
+                   0x00000800  0x00000200  ?\n"
+            .parse::<DdrescueMap>()
+            .unwrap();
+        assert_eq!(
+            map.unreadable().collect::<Vec<_>>(),
+            vec![0x300..0x400, 0x800..0xa00]
+        );
+
+        let mut device = DdrescueBlockDevice::new(
+            InMemoryBlockDevice::new(vec![1u8; 6 * 512], BlockSize(512)),
+            &map,
+        );
+        let mut buf = vec![0u8; 512];
+        let readable = (0..6)
+            .map(|block| device.read_block(BlockNumber(block), &mut buf).is_ok())
+            .collect::<Vec<_>>();
+        assert_eq!(readable, vec![true, false, true, true, false, true]);
+
+        assert!("0x0 + 1\n0x0 0x200 +\n0x100 0x200 +\n"
+            .parse::<DdrescueMap>()
+            .is_err());
+    }
+}
@@ -2,15 +2,24 @@ mod block_count;
 mod block_device;
 mod block_number;
 mod block_size;
+mod ddrescue;
 mod file;
 mod in_memory;
 mod internal;
 mod native;
+mod overlay;
 mod reader;
 
 #[allow(unused_imports)]
 pub use self::{
-    block_count::BlockCount, block_device::BlockDevice, block_number::BlockNumber,
-    block_size::BlockSize, file::FileBlockDevice, in_memory::InMemoryBlockDevice,
-    native::NativeBlockDevice, reader::BlockDeviceReader,
+    block_count::BlockCount,
+    block_device::BlockDevice,
+    block_number::BlockNumber,
+    block_size::BlockSize,
+    ddrescue::{parse_hex, DdrescueBlockDevice, DdrescueMap},
+    file::FileBlockDevice,
+    in_memory::InMemoryBlockDevice,
+    native::NativeBlockDevice,
+    overlay::OverlayBlockDevice,
+    reader::BlockDeviceReader,
 };
@@ -0,0 +1,290 @@
+use crate::block_device::{BlockCount, BlockDevice, BlockNumber, BlockSize};
+use byteorder::{ByteOrder, LittleEndian};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::rc::Rc;
+
+/// The blocks written to an overlay, kept in a file of their own: a header
+/// of the magic and the block size, then each block written, after its
+/// block number. A block written again is overwritten where it is.
+#[derive(Debug)]
+struct OverlayDelta {
+    file: File,
+    block_size: BlockSize,
+
+    /// Where the data of each block written is in the file.
+    blocks: BTreeMap<BlockNumber, u64>,
+}
+
+impl OverlayDelta {
+    const MAGIC: &'static [u8; 8] = b"MDOVRLAY";
+    const HEADER_SIZE: u64 = 16;
+
+    fn open(path: &Path, block_size: BlockSize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        // Another overlay of the same delta file would not see the blocks
+        // written through this one, so only one may have it open.
+        file.try_lock().map_err(|error| match error {
+            TryLockError::WouldBlock => io::ErrorKind::ResourceBusy.into(),
+            TryLockError::Error(error) => error,
+        })?;
+        let mut delta = Self {
+            file,
+            block_size,
+            blocks: BTreeMap::new(),
+        };
+        let length = delta.file.metadata()?.len();
+        if length == 0 {
+            delta.discard()?;
+            return Ok(delta);
+        }
+
+        let mut header = [0u8; Self::HEADER_SIZE as usize];
+        delta.file.read_exact_at(&mut header, 0)?;
+        if &header[..8] != Self::MAGIC
+            || LittleEndian::read_u32(&header[8..]) != u32::from(block_size)
+        {
+            Err(io::ErrorKind::InvalidData)?;
+        }
+        let record_size = 8 + u64::from(block_size);
+        let record_count = (length - Self::HEADER_SIZE) / record_size;
+        let mut block_number = [0u8; 8];
+        for index in 0..record_count {
+            let offset = Self::HEADER_SIZE + index * record_size;
+            delta.file.read_exact_at(&mut block_number, offset)?;
+            delta.blocks.insert(
+                BlockNumber(LittleEndian::read_u64(&block_number)),
+                offset + 8,
+            );
+        }
+        // A record cut short, as by a crash while it was written, is dropped.
+        delta
+            .file
+            .set_len(Self::HEADER_SIZE + record_count * record_size)?;
+        Ok(delta)
+    }
+
+    fn write_block(&mut self, block_number: BlockNumber, buf: &[u8]) -> io::Result<()> {
+        let buf = &buf[..usize::from(self.block_size)];
+        match self.blocks.get(&block_number) {
+            Some(offset) => self.file.write_all_at(buf, *offset),
+            None => {
+                let offset = self.file.metadata()?.len();
+                let mut record = u64::from(block_number).to_le_bytes().to_vec();
+                record.extend_from_slice(buf);
+                self.file.write_all_at(&record, offset)?;
+                self.blocks.insert(block_number, offset + 8);
+                Ok(())
+            }
+        }
+    }
+
+    fn discard(&mut self) -> io::Result<()> {
+        let mut header = [0u8; Self::HEADER_SIZE as usize];
+        header[..8].copy_from_slice(Self::MAGIC);
+        LittleEndian::write_u32(&mut header[8..], u32::from(self.block_size));
+        self.file.set_len(0)?;
+        self.file.write_all_at(&header, 0)?;
+        self.blocks.clear();
+        Ok(())
+    }
+}
+
+/// Stacks the changes kept in a delta file over a device that is never
+/// written to, as a device-mapper snapshot does. Every clone of an overlay
+/// sees the changes made through any of them. Without a delta file, the
+/// device is read as it is, and cannot be written.
+#[derive(Debug)]
+pub struct OverlayBlockDevice<D: BlockDevice> {
+    device: D,
+    delta: Option<Rc<RefCell<OverlayDelta>>>,
+}
+
+impl<D: BlockDevice> OverlayBlockDevice<D> {
+    /// Opens the delta file at `path`, creating it if there is none. Fails
+    /// if another overlay has it open.
+    pub fn open(device: D, path: &Path) -> io::Result<Self> {
+        let delta = OverlayDelta::open(path, device.block_size()?)?;
+        Ok(Self {
+            device,
+            delta: Some(Rc::new(RefCell::new(delta))),
+        })
+    }
+
+    pub fn without_overlay(device: D) -> Self {
+        Self {
+            device,
+            delta: None,
+        }
+    }
+
+    pub fn write_block(&mut self, block_number: BlockNumber, buf: &[u8]) -> io::Result<usize> {
+        let block_size = usize::from(self.device.block_size()?);
+        if buf.len() < block_size {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        if block_number >= self.device.block_count()? {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        self.delta
+            .as_ref()
+            .ok_or(io::ErrorKind::PermissionDenied)?
+            .borrow_mut()
+            .write_block(block_number, buf)?;
+        Ok(block_size)
+    }
+
+    /// Writes `buf` at a byte offset, which need not be on a block boundary.
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let block_size = u64::from(self.device.block_size()?);
+        let mut block = vec![0u8; block_size as usize];
+        let mut position = offset;
+        let end = offset + buf.len() as u64;
+        while position < end {
+            let block_number = BlockNumber(position / block_size);
+            let start_in_block = (position % block_size) as usize;
+            let length = (block_size as usize - start_in_block).min((end - position) as usize);
+            if length < block_size as usize {
+                self.read_block(block_number, &mut block)?;
+            }
+            let start_in_buf = (position - offset) as usize;
+            block[start_in_block..][..length].copy_from_slice(&buf[start_in_buf..][..length]);
+            self.write_block(block_number, &block)?;
+            position += length as u64;
+        }
+        Ok(())
+    }
+
+    /// The blocks that have been written, in order.
+    pub fn changes(&self) -> Vec<BlockNumber> {
+        self.delta
+            .as_ref()
+            .map(|delta| delta.borrow().blocks.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets every block written.
+    pub fn discard(&mut self) -> io::Result<()> {
+        match &self.delta {
+            Some(delta) => delta.borrow_mut().discard(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes every block written to `target`, which is to be a writable
+    /// copy of the device, or the device itself, and then forgets them.
+    pub fn commit<W: Write + Seek>(&mut self, target: &mut W) -> io::Result<()> {
+        let Some(delta) = &self.delta else {
+            return Ok(());
+        };
+        let mut delta = delta.borrow_mut();
+        let block_size = delta.block_size;
+        let mut block = vec![0u8; usize::from(block_size)];
+        for (block_number, offset) in &delta.blocks {
+            delta.file.read_exact_at(&mut block, *offset)?;
+            target.seek(SeekFrom::Start(
+                block_number
+                    .byte_pos(block_size)
+                    .ok_or(io::ErrorKind::InvalidInput)?,
+            ))?;
+            target.write_all(&block)?;
+        }
+        target.flush()?;
+        delta.discard()
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for OverlayBlockDevice<D> {
+    fn block_size(&self) -> io::Result<BlockSize> {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> io::Result<BlockCount> {
+        self.device.block_count()
+    }
+
+    fn read_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(delta) = &self.delta {
+            let delta = delta.borrow();
+            if let Some(offset) = delta.blocks.get(&block_number) {
+                let block_size = usize::from(delta.block_size);
+                if buf.len() < block_size {
+                    Err(io::ErrorKind::InvalidInput)?;
+                }
+                delta.file.read_exact_at(&mut buf[..block_size], *offset)?;
+                return Ok(block_size);
+            }
+        }
+        self.device.read_block(block_number, buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            device: self.device.try_clone()?,
+            delta: self.delta.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{
+        BlockDevice, BlockDeviceReader, BlockNumber, BlockSize, InMemoryBlockDevice,
+        OverlayBlockDevice,
+    };
+    use crate::ext::ReadAll;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn overlay() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("md-recover-overlay-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let original = vec![1u8; 8 * 512];
+        let device = InMemoryBlockDevice::new(original.clone(), BlockSize(512));
+
+        let mut overlay = OverlayBlockDevice::open(device.clone(), &path)?;
+        overlay.write_block(BlockNumber(2), &[2u8; 512])?;
+        overlay.write_at(3 * 512 + 256, &[3u8; 512])?;
+        assert!(overlay.write_block(BlockNumber(8), &[0u8; 512]).is_err());
+        assert_eq!(
+            overlay.changes(),
+            vec![BlockNumber(2), BlockNumber(3), BlockNumber(4)]
+        );
+
+        // The changes are seen through clones, and kept in the delta file.
+        let mut expected = original.clone();
+        expected[2 * 512..3 * 512].fill(2);
+        expected[3 * 512 + 256..4 * 512 + 256].fill(3);
+        let clone = overlay.try_clone()?;
+        assert_eq!(BlockDeviceReader::new(clone).read_all()?, expected);
+        let changes = overlay.changes();
+        assert!(OverlayBlockDevice::open(device.clone(), &path).is_err());
+        drop(overlay);
+        let mut overlay = OverlayBlockDevice::open(device.clone(), &path)?;
+        assert_eq!(overlay.changes(), changes);
+
+        let mut target = Cursor::new(original.clone());
+        overlay.commit(&mut target)?;
+        assert_eq!(target.into_inner(), expected);
+        assert!(overlay.changes().is_empty());
+
+        overlay.write_block(BlockNumber(0), &[0u8; 512])?;
+        overlay.discard()?;
+        assert!(overlay.changes().is_empty());
+        assert_eq!(BlockDeviceReader::new(overlay).read_all()?, original);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -20,6 +20,10 @@ impl<D: BlockDevice> BlockDeviceReader<D> {
             byte_pos: 0,
         }
     }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
 }
 
 impl<D: BlockDevice> BlockDevice for BlockDeviceReader<D> {
@@ -104,7 +108,7 @@ mod test {
 
     fn reader() -> BlockDeviceReader<InMemoryBlockDevice> {
         BlockDeviceReader::new(InMemoryBlockDevice::new(
-            (0..=255).into_iter().collect_vec(),
+            (0..=255).collect_vec(),
             BlockSize(32),
         ))
     }
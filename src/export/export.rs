@@ -0,0 +1,307 @@
+use crate::block_device::{BlockDevice, BlockNumber};
+use crate::export::{ExportMap, ExportStatus};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// A block device that can tell how each block it reads was come by.
+pub trait ExportSource: BlockDevice {
+    /// Reads a block, or fills it with zeros if it is lost.
+    fn export_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> ExportStatus {
+        match self.read_block(block_number, buf) {
+            Ok(_) => ExportStatus::Read,
+            Err(_) => {
+                buf.fill(0);
+                ExportStatus::Lost
+            }
+        }
+    }
+}
+
+trait WriteSeek: Write + Seek {}
+
+impl<W: Write + Seek> WriteSeek for W {}
+
+/// Copies a block device, such as an assembled array, to an image file.
+/// Blocks that cannot be read are left as zeros in the image, and recorded
+/// in a map, which also lets an interrupted export carry on where it
+/// stopped.
+#[derive(Clone, Debug)]
+pub struct Export {
+    /// Leave runs of zeros unwritten, as holes in the image.
+    pub sparse: bool,
+
+    /// How many blocks to write at a time.
+    pub blocks_per_write: usize,
+
+    /// How often to save the map and report progress.
+    pub save_interval: Duration,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            sparse: false,
+            blocks_per_write: 2048,
+            save_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Export {
+    /// Exports `source` to the file or device at `path`, or only maps it if
+    /// there is none, resuming from the map at `map_path` if there is one,
+    /// after trying again what it records as lost. Holes are only left in
+    /// regular files, as a device may hold old data where they would be.
+    pub fn run<D: ExportSource>(
+        &self,
+        source: &D,
+        path: Option<&Path>,
+        map_path: &Path,
+        mut progress: impl FnMut(&ExportMap),
+    ) -> io::Result<ExportMap> {
+        let size = source
+            .block_count()?
+            .size_bytes(source.block_size()?)
+            .ok_or(io::ErrorKind::InvalidData)?;
+        let resumed = match fs::read_to_string(map_path) {
+            Ok(map) => Some(
+                map.parse::<ExportMap>()
+                    .or(Err(io::ErrorKind::InvalidData))?,
+            ),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => Err(error)?,
+        };
+        if resumed.as_ref().is_some_and(|map| map.size != size) {
+            // The map is of some other source.
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+
+        // What was copied before is kept when resuming.
+        let mut file = match path {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?,
+            ),
+            None => None,
+        };
+        let mut is_file = false;
+        if let Some(file) = &file {
+            is_file = file.metadata()?.is_file();
+            if is_file {
+                if resumed.is_none() {
+                    file.set_len(0)?;
+                }
+                file.set_len(size)?;
+            }
+        }
+        let mut empty = io::empty();
+        let output: &mut dyn WriteSeek = match &mut file {
+            Some(file) => file,
+            None => &mut empty,
+        };
+
+        let export = Self {
+            sparse: self.sparse && is_file,
+            ..self.clone()
+        };
+        let mut map = resumed.unwrap_or_else(|| ExportMap::new(size));
+        let save_map = |map: &ExportMap| {
+            let temporary_path = map_path.with_extension("tmp");
+            fs::write(&temporary_path, map.to_string())?;
+            fs::rename(temporary_path, map_path)
+        };
+        save_map(&map)?;
+        progress(&map);
+
+        let mut last_save = Instant::now();
+        let mut written = |map: &ExportMap| {
+            if last_save.elapsed() >= self.save_interval {
+                save_map(map)?;
+                progress(map);
+                last_save = Instant::now();
+            }
+            Ok(())
+        };
+        export.retry(source, output, &mut map, &mut written)?;
+        export.write(source, output, &mut map, &mut written)?;
+        if let Some(file) = &file {
+            file.sync_all()?;
+        }
+        save_map(&map)?;
+        progress(&map);
+        Ok(map)
+    }
+
+    /// Copies `source` to `output` from the position in `map` onwards,
+    /// calling `written` after each write with the map brought up to date.
+    pub fn write<D: ExportSource, W: Write + Seek + ?Sized>(
+        &self,
+        source: &D,
+        output: &mut W,
+        map: &mut ExportMap,
+        mut written: impl FnMut(&ExportMap) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let block_size = usize::from(source.block_size()?);
+        let mut source = source.try_clone()?;
+        let mut buf = vec![0u8; block_size * self.blocks_per_write.max(1)];
+        let mut block = vec![0u8; block_size];
+        while !map.is_finished() {
+            let start = map.position;
+            let length = usize::try_from(map.size - start)
+                .unwrap_or(usize::MAX)
+                .min(buf.len());
+            let buf = &mut buf[..length];
+
+            let mut statuses = Vec::new();
+            for (index, chunk) in buf.chunks_mut(block_size).enumerate() {
+                let block_number = BlockNumber(start / block_size as u64 + index as u64);
+                statuses.push(source.export_block(block_number, &mut block));
+                chunk.copy_from_slice(&block[..chunk.len()]);
+            }
+
+            if self.sparse && buf.iter().all(|byte| *byte == 0) {
+                output.seek(SeekFrom::Start(start + length as u64))?;
+            } else {
+                output.seek(SeekFrom::Start(start))?;
+                output.write_all(buf)?;
+            }
+
+            for (index, status) in statuses.into_iter().enumerate() {
+                let end = start + ((index + 1) * block_size) as u64;
+                map.advance(end.min(map.size), status);
+            }
+            written(map)?;
+        }
+        output.flush()
+    }
+
+    /// Reads again the blocks that `map` records as lost, and copies those
+    /// that can now be read or reconstructed, such as after a member was
+    /// replaced with a better copy.
+    pub fn retry<D: ExportSource, W: Write + Seek + ?Sized>(
+        &self,
+        source: &D,
+        output: &mut W,
+        map: &mut ExportMap,
+        mut written: impl FnMut(&ExportMap) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let block_size = u64::from(source.block_size()?);
+        let mut source = source.try_clone()?;
+        let mut block = vec![0u8; block_size as usize];
+        for range in map.bad_ranges.clone() {
+            for start in (range.start..range.end).step_by(block_size as usize) {
+                let status = source.export_block(BlockNumber(start / block_size), &mut block);
+                if status == ExportStatus::Lost {
+                    continue;
+                }
+                let end = (start + block_size).min(map.size);
+                output.seek(SeekFrom::Start(start))?;
+                output.write_all(&block[..(end - start) as usize])?;
+                map.recover(start..end, status);
+            }
+            written(map)?;
+        }
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockCount, BlockDevice, BlockNumber, BlockSize};
+    use crate::export::{Export, ExportMap, ExportSource};
+    use std::io;
+    use std::io::Cursor;
+
+    /// A device on which some blocks cannot be read.
+    #[derive(Clone)]
+    struct FaultyBlockDevice {
+        data: Vec<u8>,
+        bad_blocks: Vec<u64>,
+    }
+
+    impl BlockDevice for FaultyBlockDevice {
+        fn block_size(&self) -> io::Result<BlockSize> {
+            Ok(BlockSize(512))
+        }
+
+        fn block_count(&self) -> io::Result<BlockCount> {
+            Ok(BlockCount(self.data.len() as u64 / 512))
+        }
+
+        fn read_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> io::Result<usize> {
+            if self.bad_blocks.contains(&u64::from(block_number)) {
+                Err(io::ErrorKind::InvalidData)?;
+            }
+            let offset = u64::from(block_number) as usize * 512;
+            buf[..512].copy_from_slice(&self.data[offset..][..512]);
+            Ok(512)
+        }
+
+        fn try_clone(&self) -> io::Result<Self> {
+            Ok(self.clone())
+        }
+    }
+
+    impl ExportSource for FaultyBlockDevice {}
+
+    #[test]
+    fn export_with_bad_blocks() -> io::Result<()> {
+        let device = FaultyBlockDevice {
+            data: (0..64 * 512).map(|index| (index / 512 + 1) as u8).collect(),
+            bad_blocks: vec![5, 6, 40],
+        };
+        let export = Export {
+            blocks_per_write: 8,
+            ..Export::default()
+        };
+
+        // Stop after the first two writes, then resume.
+        let mut output = Cursor::new(Vec::new());
+        let mut map = ExportMap::new(64 * 512);
+        let mut writes = 0;
+        let interrupted = export.write(&device, &mut output, &mut map, |_| {
+            writes += 1;
+            if writes == 2 {
+                Err(io::ErrorKind::Interrupted)?;
+            }
+            Ok(())
+        });
+        assert!(interrupted.is_err());
+        assert_eq!(map.position, 16 * 512);
+        let mut map = map.to_string().parse::<ExportMap>().unwrap();
+        export.write(&device, &mut output, &mut map, |_| Ok(()))?;
+
+        assert!(map.is_finished());
+        assert_eq!(map.bad_ranges, vec![5 * 512..7 * 512, 40 * 512..41 * 512]);
+        let image = output.into_inner();
+        assert_eq!(image.len(), 64 * 512);
+        for (index, sector) in image.chunks(512).enumerate() {
+            let expected = if [5, 6, 40].contains(&index) {
+                0
+            } else {
+                index as u8 + 1
+            };
+            assert!(sector.iter().all(|byte| *byte == expected));
+        }
+
+        // A better copy of the device can read more of it.
+        let device = FaultyBlockDevice {
+            bad_blocks: vec![40],
+            ..device
+        };
+        let mut output = Cursor::new(image);
+        export.retry(&device, &mut output, &mut map, |_| Ok(()))?;
+        assert_eq!(map.bad_ranges, vec![40 * 512..41 * 512]);
+        assert!(output.into_inner()[5 * 512..7 * 512]
+            .iter()
+            .all(|byte| *byte != 0));
+        Ok(())
+    }
+}
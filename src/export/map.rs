@@ -0,0 +1,248 @@
+use crate::block_device::{parse_hex, DdrescueMap};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// How a block of the source was come by.
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Debug)]
+pub enum ExportStatus {
+    Read,
+    /// Worked out from parity, as the member holding it could not be read.
+    Reconstructed,
+    /// Neither read nor reconstructed, and left as zeros.
+    Lost,
+}
+
+/// How far an export got, and which parts of the source were reconstructed
+/// or lost, in bytes. It is stored in the mapfile format of GNU ddrescue, so
+/// the tools written for that can show it, with the reconstructed parts
+/// marked as read, and listed in comments.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ExportMap {
+    pub size: u64,
+
+    /// Everything before this has been tried.
+    pub position: u64,
+
+    /// In order, and never adjacent.
+    pub reconstructed_ranges: Vec<Range<u64>>,
+
+    /// In order, and never adjacent.
+    pub bad_ranges: Vec<Range<u64>>,
+}
+
+/// Adds `range` to `ranges`, merging it with those it touches.
+fn insert_range(ranges: &mut Vec<Range<u64>>, range: Range<u64>) {
+    let start = ranges.partition_point(|other| other.end < range.start);
+    let end = ranges.partition_point(|other| other.start <= range.end);
+    let merged = ranges[start..end].iter().fold(range, |merged, other| {
+        merged.start.min(other.start)..merged.end.max(other.end)
+    });
+    ranges.splice(start..end, [merged]);
+}
+
+/// Takes `range` out of `ranges`, splitting those it falls within.
+fn remove_range(ranges: &mut Vec<Range<u64>>, range: Range<u64>) {
+    *ranges = ranges
+        .iter()
+        .flat_map(|other| {
+            [
+                other.start..other.end.min(range.start),
+                other.start.max(range.end)..other.end,
+            ]
+        })
+        .filter(|other| other.start < other.end)
+        .collect();
+}
+
+impl ExportMap {
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            position: 0,
+            reconstructed_ranges: Vec::new(),
+            bad_ranges: Vec::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.size
+    }
+
+    pub fn reconstructed_byte_count(&self) -> u64 {
+        self.reconstructed_ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    pub fn bad_byte_count(&self) -> u64 {
+        self.bad_ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    /// Records how the bytes from the current position up to `end` were
+    /// come by.
+    pub fn advance(&mut self, end: u64, status: ExportStatus) {
+        if end <= self.position {
+            return;
+        }
+        match status {
+            ExportStatus::Read => {}
+            ExportStatus::Reconstructed => {
+                insert_range(&mut self.reconstructed_ranges, self.position..end)
+            }
+            ExportStatus::Lost => insert_range(&mut self.bad_ranges, self.position..end),
+        }
+        self.position = end;
+    }
+
+    /// Records that bytes found lost before were read or reconstructed after
+    /// all.
+    pub fn recover(&mut self, range: Range<u64>, status: ExportStatus) {
+        match status {
+            ExportStatus::Read => remove_range(&mut self.bad_ranges, range),
+            ExportStatus::Reconstructed => {
+                remove_range(&mut self.bad_ranges, range.clone());
+                insert_range(&mut self.reconstructed_ranges, range);
+            }
+            ExportStatus::Lost => {}
+        }
+    }
+
+    /// The runs of bytes that were copied, could not be read, or have not
+    /// been tried yet, in order, covering the whole source.
+    fn blocks(&self) -> Vec<(Range<u64>, char)> {
+        let mut blocks = Vec::new();
+        let mut start = 0;
+        for range in &self.bad_ranges {
+            if start < range.start {
+                blocks.push((start..range.start, '+'));
+            }
+            blocks.push((range.clone(), '-'));
+            start = range.end;
+        }
+        if start < self.position {
+            blocks.push((start..self.position, '+'));
+        }
+        if self.position < self.size {
+            blocks.push((self.position..self.size, '?'));
+        }
+        blocks
+    }
+}
+
+impl Display for ExportMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# Mapfile. Created by md-recover")?;
+        for range in &self.reconstructed_ranges {
+            writeln!(
+                f,
+                "# reconstructed  0x{:08X}  0x{:08X}",
+                range.start,
+                range.end - range.start
+            )?;
+        }
+        writeln!(f, "# current_pos  current_status  current_pass")?;
+        writeln!(
+            f,
+            "0x{:08X}     {}               1",
+            self.position,
+            if self.is_finished() { '+' } else { '?' }
+        )?;
+        writeln!(f, "#      pos        size  status")?;
+        for (range, status) in self.blocks() {
+            writeln!(
+                f,
+                "0x{:08X}  0x{:08X}  {status}",
+                range.start,
+                range.end - range.start
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ExportMap {
+    type Err = String;
+
+    /// Reads a map written by an earlier export. The blocks must cover the
+    /// source from its start, with everything tried before anything that
+    /// was not, as an export leaves them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = ExportMap::new(0);
+        for (range, status) in s.parse::<DdrescueMap>()?.blocks {
+            if range.start != map.size {
+                return Err(format!("map has a gap at 0x{:08X}", map.size));
+            }
+            match status {
+                '+' if map.position == map.size => map.advance(range.end, ExportStatus::Read),
+                '-' if map.position == map.size => map.advance(range.end, ExportStatus::Lost),
+                '?' => {}
+                '+' | '-' => {
+                    return Err(format!(
+                        "map has untried bytes before 0x{:08X}",
+                        range.start
+                    ))
+                }
+                _ => return Err(format!("unsupported status in map: {status}")),
+            }
+            map.size = range.end;
+        }
+
+        for line in s.lines() {
+            let Some(range) = line.trim().strip_prefix("# reconstructed") else {
+                continue;
+            };
+            let [start, size] = range.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(format!("invalid line in map: {line}"));
+            };
+            let start = parse_hex(start)?;
+            let end = start
+                .checked_add(parse_hex(size)?)
+                .ok_or_else(|| format!("invalid line in map: {line}"))?;
+            insert_range(&mut map.reconstructed_ranges, start..end);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::export::{ExportMap, ExportStatus};
+
+    #[test]
+    fn round_trip() {
+        let mut map = ExportMap::new(0x10000);
+        map.advance(0x1000, ExportStatus::Read);
+        map.advance(0x1200, ExportStatus::Lost);
+        map.advance(0x1400, ExportStatus::Lost);
+        map.advance(0x1600, ExportStatus::Reconstructed);
+        map.advance(0x8000, ExportStatus::Read);
+        assert_eq!(map.bad_ranges, vec![0x1000..0x1400]);
+        assert_eq!(map.reconstructed_ranges, vec![0x1400..0x1600]);
+        assert_eq!(
+            map.to_string(),
+            "# Mapfile. Created by md-recover\n\
+             # reconstructed  0x00001400  0x00000200\n\
+             # current_pos  current_status  current_pass\n\
+             0x00008000     ?               1\n\
+             #      pos        size  status\n\
+             0x00000000  0x00001000  +\n\
+             0x00001000  0x00000400  -\n\
+             0x00001400  0x00006C00  +\n\
+             0x00008000  0x00008000  ?\n"
+        );
+        assert_eq!(map.to_string().parse(), Ok(map.clone()));
+
+        map.recover(0x1200..0x1400, ExportStatus::Reconstructed);
+        assert_eq!(map.bad_ranges, vec![0x1000..0x1200]);
+        assert_eq!(map.reconstructed_ranges, vec![0x1200..0x1600]);
+
+        assert!("0x0 ? 1\n0x0 0x1000 ?\n0x1000 0x1000 +\n"
+            .parse::<ExportMap>()
+            .is_err());
+    }
+}
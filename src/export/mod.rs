@@ -0,0 +1,8 @@
+mod export;
+mod map;
+
+#[allow(unused_imports)]
+pub use self::{
+    export::{Export, ExportSource},
+    map::{ExportMap, ExportStatus},
+};
@@ -20,8 +20,8 @@ mod test {
     #[test]
     fn test() {
         assert_eq!(
-            ext4_crc32c(EXT4_CRC32C_INITIAL, &[0x12, 0x34]),
-            ext4_crc32c(ext4_crc32c(EXT4_CRC32C_INITIAL, &[0x12]), &[0x34])
+            ext4_crc32c(EXT4_CRC32C_INITIAL, [0x12, 0x34]),
+            ext4_crc32c(ext4_crc32c(EXT4_CRC32C_INITIAL, [0x12]), [0x34])
         );
     }
 }
@@ -74,7 +74,7 @@ mod test {
         );
         assert_eq!(dir_entry.inode(), InodeNumber(2));
 
-        assert_eq!(rest, &[]);
+        assert_eq!(rest, &[0u8; 0]);
     }
 
     #[test]
@@ -123,6 +123,8 @@ binary_layout!(layout, LittleEndian, {
 pub struct Superblock<S: AsRef<[u8]>>(S);
 
 impl<S: AsRef<[u8]>> Superblock<S> {
+    pub const SIZE: usize = layout::SIZE.unwrap();
+
     pub fn new(storage: S) -> Self {
         Self(storage)
     }
@@ -316,6 +318,26 @@ impl<S: AsRef<[u8]>> Superblock<S> {
         self.view().into_block_group_number().read()
     }
 
+    /// Returns true if the given block group starts with a backup copy of
+    /// the superblock. With sparse superblocks, only groups 1 and the powers
+    /// of 3, 5 and 7 carry a backup.
+    pub fn has_backup_superblock(&self, group: u32) -> bool {
+        fn is_power_of(mut n: u32, base: u32) -> bool {
+            while n.is_multiple_of(base) {
+                n /= base;
+            }
+            n == 1
+        }
+
+        group > 0
+            && (!self
+                .read_only_compatible_features()
+                .contains(ReadOnlyCompatibleFeatures::SPARSE_SUPERBLOCKS)
+                || is_power_of(group, 3)
+                || is_power_of(group, 5)
+                || is_power_of(group, 7))
+    }
+
     pub fn compatible_features(&self) -> CompatibleFeatures {
         self.view().into_compatible_features().read()
     }
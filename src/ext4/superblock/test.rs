@@ -437,3 +437,14 @@ fn checksum_ext2() {
 fn expected_checksum() {
     assert_eq!(Superblock::new(EXT4_1).expected_checksum(), 0x42350b17)
 }
+
+#[test]
+fn has_backup_superblock() {
+    let superblock = Superblock::new(EXT4_1);
+    assert_eq!(
+        (0..50)
+            .filter(|&group| superblock.has_backup_superblock(group))
+            .collect::<Vec<_>>(),
+        vec![1, 3, 5, 7, 9, 25, 27, 49]
+    );
+}
@@ -5,43 +5,1065 @@ extern crate arrayref;
 #[macro_use]
 extern crate bitflags;
 
-use crate::md::{MdArray, MdDevice};
-use clap::Parser;
+use crate::block_device::{
+    BlockDevice, BlockDeviceReader, DdrescueBlockDevice, DdrescueMap, NativeBlockDevice,
+    OverlayBlockDevice,
+};
+use crate::export::{Export, ExportMap};
+use crate::ext4::Ext4Superblock;
+use crate::md::{
+    scan_for_superblocks, DeviceCount, DeviceNumber, MdAlgorithm, MdArray, MdArrayFixture,
+    MdChunkSizeAnalysis, MdCreateCommand, MdDevice, MdExt4RaidHints, MdFixtureSuperblock, MdFormat,
+    MdGeometry, MdGeometrySearch, MdParityInference, MdSuperblockKind, MdSuperblockRepair,
+    SectorCount, SectorNumber,
+};
+use clap::{ArgGroup, Parser, ValueEnum};
 use itertools::Itertools;
 use os_display::Quotable;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::BufWriter;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
 
 mod block_device;
+mod export;
 mod ext;
 mod ext4;
 mod ioctl;
 mod md;
 
+/// A device given on the command line, which fails to read what ddrescue
+/// could not copy to it, if given a map, and is read through its overlay, if
+/// given one.
+type Device = OverlayBlockDevice<DdrescueBlockDevice<NativeBlockDevice>>;
+
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, group(ArgGroup::new("action").multiple(false)))]
 struct Options {
     devices: Vec<PathBuf>,
 
+    /// Copy the assembled array to an image file or device. Regions that
+    /// cannot be read or reconstructed are left as zeros, and recorded with
+    /// the progress of the copy in the map, from which an interrupted copy
+    /// resumes, trying the lost regions again.
     #[arg(short, long)]
-    output: Vec<PathBuf>,
+    output: Option<PathBuf>,
+
+    /// Where to keep the map of the array, in the mapfile format of GNU
+    /// ddrescue, which records which regions were read directly,
+    /// reconstructed from parity, or lost [default: OUTPUT.map]. Without
+    /// --output, the array is read to write the map alone.
+    #[arg(long, value_name = "MAPFILE")]
+    map: Option<PathBuf>,
+
+    /// Leave runs of zeros unwritten, as holes in the image file.
+    #[arg(long, requires = "output")]
+    sparse: bool,
+
+    /// Write an image of the missing member with the given role number to
+    /// OUTPUT instead of the array, worked out from the parity of the
+    /// others. It is laid out like the other members, with its own
+    /// superblock, if they have version 0.90 or 1 superblocks, or else
+    /// holds the data alone.
+    #[arg(
+        long,
+        value_name = "ROLE",
+        requires = "output",
+        conflicts_with = "sparse"
+    )]
+    rebuild: Option<u32>,
+
+    /// How to print the diagnosis of the array.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Use the superblock of the given format (0.90, 1.0, 1.1, 1.2, imsm or
+    /// ddf) on DEVICE, instead of the first one found. An IMSM container
+    /// holds a superblock for each of its volumes, chosen with imsm:VOLUME,
+    /// counting from 0.
+    #[arg(long, value_name = "DEVICE=FORMAT", value_parser = parse_superblock_choice)]
+    superblock: Vec<(PathBuf, MdSuperblockKind, Option<usize>)>,
+
+    /// The drive serial number of DEVICE, by which IMSM metadata tells which
+    /// of the disks in its container DEVICE is. Without it, the role of an
+    /// IMSM member is unknown.
+    #[arg(long, value_name = "DEVICE=SERIAL", value_parser = parse_serial_choice)]
+    serial: Vec<(PathBuf, String)>,
+
+    /// Treat the ranges that GNU ddrescue could not read, according to
+    /// MAPFILE, as unreadable on DEVICE, an image copied by ddrescue, so
+    /// that they are recovered from the other members rather than read as
+    /// zeros.
+    #[arg(long, value_name = "DEVICE=MAPFILE", value_parser = parse_ddrescue_map_choice)]
+    ddrescue_map: Vec<(PathBuf, PathBuf)>,
+
+    /// Read DEVICE with the changes kept in DELTA, a file that is created if
+    /// there is none, over it. DEVICE itself is never written, unless the
+    /// changes are committed.
+    #[arg(long, value_name = "DEVICE=DELTA", value_parser = parse_overlay_choice)]
+    overlay: Vec<(PathBuf, PathBuf)>,
+
+    /// List, commit to the device, or discard the changes in each overlay.
+    #[arg(long, value_enum, requires = "overlay")]
+    overlay_action: Option<OverlayAction>,
+
+    /// Search every sector of each device for md superblocks, including any
+    /// that are no longer where md expects them.
+    #[arg(long)]
+    scan: bool,
+
+    /// Ignore superblocks, and rank possible geometries of the array by how
+    /// well they fit the data on the devices.
+    #[arg(long, group = "action")]
+    search: bool,
+
+    /// Ignore superblocks, and infer the layout and member order of the
+    /// array from the parity of the data on the devices alone.
+    #[arg(long, group = "action")]
+    parity: bool,
+
+    /// Estimate the chunk size of the array from the contents of the
+    /// devices, which need not include every member.
+    #[arg(long, group = "action")]
+    guess_chunk_size: bool,
+
+    /// Check that the parity of every stripe of the array matches its data.
+    /// Stripes missing more members than the array has parities are
+    /// reported and left unchecked.
+    #[arg(long, group = "action", conflicts_with_all = ["output", "map"])]
+    scrub: bool,
+
+    /// Print the `mdadm --create --assume-clean` command that recreates the
+    /// array, as found in the superblocks or given with --level, with the
+    /// same data layout, and the members whose superblocks differ from it.
+    #[arg(long, group = "action", conflicts_with_all = ["output", "map"])]
+    create_command: bool,
+
+    /// Print the ARRAY line of mdadm.conf for the array, as found in the
+    /// superblocks, so that it is assembled at boot.
+    #[arg(long, group = "action", conflicts_with_all = ["output", "map"])]
+    conf: bool,
+
+    /// Raise the event count in the superblock of every member to the
+    /// highest among them, writing the superblocks to their overlays.
+    #[arg(long, requires = "overlay", conflicts_with_all = ["output", "map"])]
+    sync_event_counts: bool,
+
+    /// Forget any reshape in progress in the superblock of every member,
+    /// writing the superblocks to their overlays.
+    #[arg(long, requires = "overlay", conflicts_with_all = ["output", "map"])]
+    clear_reshape: bool,
+
+    /// Record in the superblock of every member the role it has in the
+    /// array, as assembled with --order or found in the superblocks,
+    /// writing the superblocks to their overlays.
+    #[arg(long, requires = "overlay", conflicts_with_all = ["output", "map"])]
+    fix_roles: bool,
+
+    /// Show when the array was created, and when each device last wrote its
+    /// superblock.
+    #[arg(long, group = "action")]
+    timeline: bool,
+
+    /// Lay out the data in the one file given as the members of a new
+    /// array, with the geometry given under Geometry, and write an image of
+    /// each member, with its superblock, to DIRECTORY as member0.img,
+    /// member1.img and so on. The data is padded with zeros to a whole
+    /// number of stripes.
+    #[arg(
+        long,
+        value_name = "DIRECTORY",
+        requires_all = ["level", "raid_devices"],
+        conflicts_with_all = ["output", "map"]
+    )]
+    generate: Option<PathBuf>,
+
+    /// Ignore superblocks, and assemble the devices as an array of the
+    /// given RAID level, with the geometry given by the other options here.
+    #[arg(long, help_heading = "Geometry")]
+    level: Option<u32>,
+
+    /// The layout of the array, by its mdadm name or number [default:
+    /// left-symmetric].
+    #[arg(long, help_heading = "Geometry", requires = "level")]
+    layout: Option<String>,
+
+    /// The chunk size, in KiB unless suffixed with K, M or G [default: 512K].
+    #[arg(long, help_heading = "Geometry", requires = "level", value_parser = parse_size)]
+    chunk: Option<u64>,
+
+    /// The members of the array in order, by path or file name, with
+    /// "missing" in place of each missing member [default: the devices in
+    /// the order given].
+    #[arg(
+        long,
+        help_heading = "Geometry",
+        requires = "level",
+        value_delimiter = ','
+    )]
+    order: Vec<String>,
+
+    /// The data offset of every member, or of each member in order, in KiB
+    /// unless suffixed with K, M or G [default: the data offset in the
+    /// superblock of each member, if any, or 0].
+    #[arg(
+        long,
+        help_heading = "Geometry",
+        requires = "level",
+        value_delimiter = ',',
+        value_parser = parse_size
+    )]
+    data_offset: Vec<u64>,
+
+    /// The number of members of an array written with --generate.
+    #[arg(long, help_heading = "Geometry", requires = "generate")]
+    raid_devices: Option<u32>,
+
+    /// The superblock written to each member with --generate: 0.90, 1.0,
+    /// 1.1 or 1.2 [default: 1.2]. The data offset defaults to 1M for
+    /// versions 1.1 and 1.2, and to 0 otherwise.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        help_heading = "Geometry",
+        requires = "generate"
+    )]
+    metadata: Option<MdFixtureSuperblock>,
+}
+
+#[derive(ValueEnum, Eq, PartialEq, Clone, Copy, Debug)]
+enum OverlayAction {
+    /// List the blocks changed.
+    List,
+    /// Write the changed blocks to the device, and empty the overlay.
+    Commit,
+    /// Empty the overlay.
+    Discard,
+}
+
+#[derive(ValueEnum, Eq, PartialEq, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// A report in plain language.
+    Text,
+    /// The diagnosis and the superblock of every device, as JSON.
+    Json,
+}
+
+fn parse_superblock_choice(s: &str) -> Result<(PathBuf, MdSuperblockKind, Option<usize>), String> {
+    let (path, kind) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected DEVICE=FORMAT: {s}"))?;
+    let (kind, volume) = match kind.split_once(':') {
+        Some((kind, volume)) => (
+            kind,
+            Some(
+                volume
+                    .parse()
+                    .map_err(|_| format!("invalid volume number: {volume}"))?,
+            ),
+        ),
+        None => (kind, None),
+    };
+    Ok((PathBuf::from(path), kind.parse()?, volume))
+}
+
+fn parse_serial_choice(s: &str) -> Result<(PathBuf, String), String> {
+    let (path, serial) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected DEVICE=SERIAL: {s}"))?;
+    Ok((PathBuf::from(path), serial.to_string()))
+}
+
+fn parse_ddrescue_map_choice(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    let (path, map_path) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected DEVICE=MAPFILE: {s}"))?;
+    Ok((PathBuf::from(path), PathBuf::from(map_path)))
+}
+
+fn parse_overlay_choice(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    let (path, delta_path) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected DEVICE=DELTA: {s}"))?;
+    Ok((PathBuf::from(path), PathBuf::from(delta_path)))
+}
+
+/// Lists, commits or discards the changes in an overlay.
+fn act_on_overlay(action: OverlayAction, path: &Path, delta_path: &Path) -> io::Result<String> {
+    let mut overlay = OverlayBlockDevice::open(NativeBlockDevice::open_path(path)?, delta_path)?;
+    let changes = overlay.changes();
+    match action {
+        OverlayAction::List if changes.is_empty() => Ok("no changes".to_string()),
+        OverlayAction::List => Ok(format!(
+            "{} blocks changed: {}",
+            changes.len(),
+            changes.iter().map(|block| block.0).join(", ")
+        )),
+        OverlayAction::Commit => {
+            overlay.commit(&mut OpenOptions::new().write(true).open(path)?)?;
+            Ok(format!("{} blocks written", changes.len()))
+        }
+        OverlayAction::Discard => {
+            overlay.discard()?;
+            Ok(format!("{} blocks discarded", changes.len()))
+        }
+    }
+}
+
+/// Opens a device, with the ddrescue map and the overlay given for it, if
+/// any.
+fn open_device(options: &Options, path: &Path) -> io::Result<MdDevice<Device>> {
+    let device = NativeBlockDevice::open_path(path)?;
+    let device = match options
+        .ddrescue_map
+        .iter()
+        .find(|(choice, _)| choice == path)
+    {
+        Some((_, map_path)) => DdrescueBlockDevice::new(
+            device,
+            &fs::read_to_string(map_path)?
+                .parse::<DdrescueMap>()
+                .map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: {error}", map_path.maybe_quote()),
+                    )
+                })?,
+        ),
+        None => DdrescueBlockDevice::without_map(device),
+    };
+    let device = match options.overlay.iter().find(|(choice, _)| choice == path) {
+        Some((_, delta_path)) => OverlayBlockDevice::open(device, delta_path)?,
+        None => OverlayBlockDevice::without_overlay(device),
+    };
+    let serial = options
+        .serial
+        .iter()
+        .find(|(choice, _)| choice == path)
+        .map(|(_, serial)| serial.as_bytes());
+    MdDevice::from_block_device_with_serial(device, Some(path), serial)
+}
+
+/// Parses a size the way mdadm does, in KiB unless suffixed, and returns it
+/// in sectors.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, shift) = match s.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&s[..index], 10),
+        Some((index, 'M' | 'm')) => (&s[..index], 20),
+        Some((index, 'G' | 'g')) => (&s[..index], 30),
+        _ => (s, 10),
+    };
+    let bytes = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size: {s}"))?;
+    if bytes % 512 == 0 {
+        Ok(bytes / 512)
+    } else {
+        Err(format!("size is not a multiple of 512 bytes: {s}"))
+    }
+}
+
+/// Lays out the data in the one file given as the members of a new array,
+/// and writes their images to `directory`.
+fn generate_members(options: &Options, level: u32, directory: &Path) -> Result<String, String> {
+    let [path] = &options.devices[..] else {
+        return Err("expected one file of data to lay out".to_string());
+    };
+    let layout = options.layout.as_deref().unwrap_or("left-symmetric");
+    let algorithm = MdAlgorithm::from_level_and_layout_name(level, layout)
+        .ok_or_else(|| format!("unsupported layout for RAID{level}: {layout}"))?;
+    let chunk_size = u32::try_from(options.chunk.unwrap_or(1024))
+        .map_err(|_| "chunk size too large".to_string())?;
+    let superblock = options.metadata.unwrap_or(MdFixtureSuperblock::Version1_2);
+    let data_offset = match options.data_offset[..] {
+        [] => superblock.default_data_offset(),
+        [data_offset] => SectorNumber(data_offset),
+        _ => return Err("expected one data offset".to_string()),
+    };
+    if !superblock.allows_data_offset(data_offset) {
+        return Err(format!(
+            "version {superblock} superblocks cannot have the data at sector {}",
+            u64::from(data_offset)
+        ));
+    }
+    let fixture = MdArrayFixture {
+        superblock: Some(superblock),
+        data_offset,
+        ..MdArrayFixture::new(
+            algorithm,
+            options.raid_devices.ok_or("expected --raid-devices")?,
+            chunk_size,
+        )
+    };
+    let stripe_size = fixture
+        .stripe_size()
+        .map_err(|_| "too few members for the level, or a chunk size of 0".to_string())?;
+    let mut data = fs::read(path).map_err(|error| format!("{}: {error}", path.maybe_quote()))?;
+    data.resize(
+        (data.len() as u64).max(1).next_multiple_of(stripe_size) as usize,
+        0,
+    );
+    let paths = fixture
+        .write_member_images(&data, directory)
+        .map_err(|error| format!("{}: {error}", directory.maybe_quote()))?;
+    Ok(format!(
+        "{} members written: {}",
+        paths.len(),
+        paths.iter().map(|path| path.maybe_quote()).join(", ")
+    ))
+}
+
+fn assemble_array<D: BlockDevice>(
+    options: &Options,
+    level: u32,
+    devices: Vec<MdDevice<D>>,
+) -> Result<MdArray<D>, String> {
+    let layout = options.layout.as_deref().unwrap_or("left-symmetric");
+    let algorithm = MdAlgorithm::from_level_and_layout_name(level, layout)
+        .ok_or_else(|| format!("unsupported layout for RAID{level}: {layout}"))?;
+    let chunk_size = u32::try_from(options.chunk.unwrap_or(1024))
+        .map(SectorCount)
+        .map_err(|_| "chunk size too large".to_string())?;
+
+    let devices = devices.into_iter().map(Rc::new).collect_vec();
+    let order = if options.order.is_empty() {
+        (0..devices.len()).map(Some).collect_vec()
+    } else {
+        options
+            .order
+            .iter()
+            .map(|name| {
+                if name == "missing" {
+                    return Ok(None);
+                }
+                options
+                    .devices
+                    .iter()
+                    .position(|path| {
+                        path.as_os_str() == name.as_str()
+                            || path
+                                .file_name()
+                                .is_some_and(|file_name| file_name == name.as_str())
+                    })
+                    .map(Some)
+                    .ok_or_else(|| format!("not among the devices given: {name}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if order.iter().flatten().duplicates().next().is_some() {
+        return Err("a device appears more than once in the order".to_string());
+    }
+
+    let members = order
+        .iter()
+        .enumerate()
+        .map(|(slot, index)| {
+            let Some(index) = *index else {
+                return Ok(None);
+            };
+            let device = devices[index].clone();
+            let data_offset = match options.data_offset[..] {
+                [] => device
+                    .superblock
+                    .as_option()
+                    .map_or(SectorNumber(0), |superblock| superblock.data_offset()),
+                [data_offset] => SectorNumber(data_offset),
+                _ => SectorNumber(
+                    *options
+                        .data_offset
+                        .get(slot)
+                        .filter(|_| options.data_offset.len() == order.len())
+                        .ok_or("expected one data offset, or one for each member")?,
+                ),
+            };
+            Ok(Some((device, data_offset)))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let available_sectors = members
+        .iter()
+        .flatten()
+        .map(|(device, data_offset)| {
+            // The data ends at the superblock when it is kept at the end of
+            // the device, as with v1.0 and v0.90, and the superblock may
+            // record a smaller data size.
+            let end = device
+                .superblock_location()
+                .map(|location| location.offset >> 9)
+                .filter(|&superblock_sector| superblock_sector > u64::from(*data_offset))
+                .or_else(|| {
+                    device
+                        .block_count()
+                        .ok()
+                        .and_then(|count| count.size_bytes(device.block_size().ok()?))
+                        .map(|size| size >> 9)
+                });
+            let data_size = device
+                .superblock
+                .as_option()
+                .filter(|superblock| superblock.data_offset() == *data_offset)
+                .and_then(|superblock| superblock.data_size())
+                .filter(|data_size| data_size.0 > 0);
+            end.and_then(|end| end.checked_sub(u64::from(*data_offset)))
+                .map(|sectors| data_size.map_or(sectors, |data_size| sectors.min(data_size.0)))
+                .ok_or_else(|| format!("{}: data offset beyond the end of the device", device.id))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .min()
+        .ok_or("no members given")?;
+    // The superblocks record how much of each member the array uses. Without
+    // one, use whole chunks of each member, as mdadm does.
+    let recorded_sectors = members
+        .iter()
+        .flatten()
+        .filter_map(|(device, _)| device.superblock.as_option())
+        .max_by_key(|superblock| superblock.event_count())
+        .map(|superblock| u64::from(superblock.sectors_per_device()))
+        .filter(|&sectors| sectors > 0);
+    let sectors_per_device = match recorded_sectors {
+        Some(sectors) => sectors,
+        None => {
+            let chunk_size = u64::from(chunk_size);
+            available_sectors
+                .checked_div(chunk_size)
+                .ok_or("the chunk size cannot be zero")?
+                * chunk_size
+        }
+    };
+    let format = MdFormat {
+        algorithm,
+        device_count: DeviceCount(order.len() as u32),
+        sectors_per_device: SectorCount(sectors_per_device),
+        chunk_size,
+    };
+    MdArray::assemble(format, members).map_err(|error| error.to_string())
+}
+
+/// Describes an assembled array, and whether an ext4 filesystem can be found
+/// at its start, as a quick check of its geometry.
+fn describe_array(array: &MdArray<Device>) -> String {
+    let Some(format) = array.format() else {
+        return "unknown format".to_string();
+    };
+    let mut superblock = vec![0u8; Ext4Superblock::<&[u8]>::SIZE];
+    let filesystem = array
+        .try_clone()
+        .map(BlockDeviceReader::new)
+        .and_then(|mut reader| {
+            reader.seek(SeekFrom::Start(1024))?;
+            reader.read_exact(&mut superblock)
+        })
+        .map(|_| Ext4Superblock::new(superblock.as_slice()));
+    format!(
+        "{}, {} devices, chunk size {}, {} per device: {}",
+        format.algorithm,
+        format.device_count,
+        format.chunk_size,
+        format.sectors_per_device,
+        match filesystem {
+            Ok(superblock) if superblock.valid_magic() => {
+                match MdExt4RaidHints::from_superblock(&superblock) {
+                    Some(hints) if hints.matches(format) => {
+                        "ext4 filesystem found, RAID hints match"
+                    }
+                    Some(_) => "ext4 filesystem found, RAID hints differ",
+                    None => "ext4 filesystem found",
+                }
+                .to_string()
+            }
+            Ok(_) => "no ext4 filesystem found".to_string(),
+            Err(error) => format!("cannot read the start of the array: {error}"),
+        }
+    )
+}
+
+/// Copies an assembled array to `path`, or only maps it, showing the
+/// progress as it goes.
+fn export_array(
+    array: &MdArray<Device>,
+    path: Option<&Path>,
+    map_path: &Path,
+    sparse: bool,
+) -> Result<ExportMap, String> {
+    let export = Export {
+        sparse,
+        ..Export::default()
+    };
+    let map = export
+        .run(array, path, map_path, |map| {
+            eprint!(
+                "\r{} of {} MiB read, {} MiB reconstructed, {} MiB lost",
+                map.position >> 20,
+                map.size >> 20,
+                map.reconstructed_byte_count() >> 20,
+                map.bad_byte_count() >> 20
+            );
+        })
+        .map_err(|error| format!("{}: {error}", path.unwrap_or(map_path).maybe_quote()))?;
+    eprintln!();
+    Ok(map)
+}
+
+/// Writes an image of a missing member to `path`.
+fn rebuild_member(
+    array: &MdArray<Device>,
+    device_number: DeviceNumber,
+    path: &Path,
+) -> Result<String, String> {
+    File::create(path)
+        .map(BufWriter::new)
+        .and_then(|mut output| array.rebuild_member(device_number, &mut output))
+        .map(|_| format!("Wrote {device_number} to {}.", path.maybe_quote()))
+        .map_err(|error| format!("{}: {error}", path.maybe_quote()))
+}
+
+/// Writes the repaired superblock of every member to its overlay.
+fn repair_superblocks(
+    array: &MdArray<Device>,
+    repair: &MdSuperblockRepair,
+) -> Result<String, String> {
+    let superblocks = array
+        .repair_superblocks(repair)
+        .map_err(|error| error.to_string())?;
+    Ok(superblocks
+        .iter()
+        .map(|superblock| {
+            let id = &superblock.device.id;
+            match superblock
+                .device
+                .block_device()
+                .try_clone()
+                .and_then(|mut device| device.write_at(superblock.offset, &superblock.bytes))
+            {
+                Ok(()) => format!("{id}: superblock written to the overlay"),
+                Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                    format!("{id}: no overlay to write the superblock to")
+                }
+                Err(error) => format!("{id}: {error}"),
+            }
+        })
+        .join("\n"))
+}
+
+/// Describes the outcome of an export, with the regions that could not be
+/// copied.
+fn describe_export(map: &ExportMap) -> String {
+    let mut description = format!(
+        "Read {} bytes, {} of them reconstructed from parity and {} lost.",
+        map.size,
+        map.reconstructed_byte_count(),
+        map.bad_byte_count()
+    );
+    for range in &map.bad_ranges {
+        description += &format!(
+            "\n- array sectors {}-{} left as zeros",
+            range.start / 512,
+            range.end.div_ceil(512) - 1
+        );
+    }
+    description
+}
+
+/// Describes the command that recreates an array, with a warning for each
+/// member whose superblock does not match it.
+fn describe_create_command(command: &MdCreateCommand) -> String {
+    let mut description = command.to_string();
+    for (id, mismatches) in &command.mismatches {
+        description += &format!(
+            "\nwarning: the superblock of {id} differs in the {}",
+            mismatches.join(", ")
+        );
+    }
+    description
+}
+
+/// Does what the options ask of an assembled array, if anything beyond
+/// describing it.
+fn act_on_array(options: &Options, array: &MdArray<Device>) -> Option<Result<String, String>> {
+    let output = options.output.as_deref();
+    if let Some(role) = options.rebuild {
+        return Some(match output {
+            Some(output) => rebuild_member(array, DeviceNumber(role), output),
+            None => Err("--rebuild needs --output".to_string()),
+        });
+    }
+    if options.conf {
+        return Some(
+            array
+                .conf_array()
+                .map(|line| line.to_string())
+                .map_err(|error| error.to_string()),
+        );
+    }
+    let repair = MdSuperblockRepair {
+        sync_event_counts: options.sync_event_counts,
+        clear_reshape: options.clear_reshape,
+        fix_roles: options.fix_roles,
+    };
+    if repair.sync_event_counts || repair.clear_reshape || repair.fix_roles {
+        return Some(repair_superblocks(array, &repair));
+    }
+    if options.create_command {
+        return Some(
+            array
+                .create_command()
+                .map(|command| describe_create_command(&command))
+                .map_err(|error| error.to_string()),
+        );
+    }
+    let map_path = options.map.clone().or_else(|| {
+        let mut map_path = OsString::from(output?);
+        map_path.push(".map");
+        Some(PathBuf::from(map_path))
+    });
+    if let Some(map_path) = map_path {
+        return Some(
+            export_array(array, output, &map_path, options.sparse).map(|map| describe_export(&map)),
+        );
+    }
+    if options.scrub {
+        return Some(
+            array
+                .scrub()
+                .map(|report| report.to_string().trim_end().to_string())
+                .map_err(|error| error.to_string()),
+        );
+    }
+    None
+}
+
+fn describe_geometry<D: BlockDevice>(geometry: &MdGeometry, devices: &[MdDevice<D>]) -> String {
+    format!(
+        "{}, chunk size {}, data offset {}, order {}",
+        geometry.format.algorithm,
+        geometry.format.chunk_size,
+        geometry.data_offset,
+        geometry
+            .order
+            .iter()
+            .map(|member| match member {
+                Some(member) => devices[*member].id.to_string(),
+                None => "missing".to_string(),
+            })
+            .join(", ")
+    )
+}
+
+/// Reports errors on stderr, each with the device it concerns, if any, and
+/// as a JSON object with `--format json`, and returns the exit code for
+/// them.
+fn report_errors<'a>(
+    format: OutputFormat,
+    errors: impl IntoIterator<Item = (Option<&'a Path>, String)>,
+) -> ExitCode {
+    let errors = errors.into_iter().collect_vec();
+    if errors.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    match format {
+        OutputFormat::Text => {
+            for (path, error) in &errors {
+                match path {
+                    Some(path) => eprintln!("{}: {error}", path.maybe_quote()),
+                    None => eprintln!("{error}"),
+                }
+            }
+        }
+        OutputFormat::Json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "errors": errors
+                    .iter()
+                    .map(|(path, error)| serde_json::json!({
+                        "device": path.map(|path| path.to_string_lossy()),
+                        "message": error,
+                    }))
+                    .collect_vec(),
+            })
+        ),
+    }
+    ExitCode::FAILURE
+}
+
+fn report_error(format: OutputFormat, error: impl ToString) -> ExitCode {
+    report_errors(format, [(None, error.to_string())])
 }
 
-fn main() {
+fn main() -> ExitCode {
     let options = Options::parse();
 
+    if let Some(action) = options.overlay_action {
+        let mut errors = Vec::new();
+        for (path, delta_path) in &options.overlay {
+            match act_on_overlay(action, path, delta_path) {
+                Ok(message) => println!("{}: {message}", path.maybe_quote()),
+                Err(error) => errors.push((Some(path.as_path()), error.to_string())),
+            }
+        }
+        return report_errors(options.format, errors);
+    }
+
+    if let (Some(directory), Some(level)) = (&options.generate, options.level) {
+        return match generate_members(&options, level, directory) {
+            Ok(message) => {
+                println!("{message}");
+                ExitCode::SUCCESS
+            }
+            Err(error) => report_error(options.format, error),
+        };
+    }
+
     let (devices, device_errors): (Vec<_>, Vec<_>) = options
         .devices
         .iter()
-        .map(|path| MdDevice::open_path(path).map_err(|err| (path, err)))
+        .map(|path| {
+            open_device(&options, path)
+                .and_then(|mut device| {
+                    for (_, kind, volume) in options
+                        .superblock
+                        .iter()
+                        .filter(|(choice, _, _)| choice == path)
+                    {
+                        device.select_superblock(*kind, *volume)?;
+                    }
+                    Ok(device)
+                })
+                .map_err(|err| (path, err))
+        })
         .partition_result();
+    if !device_errors.is_empty() {
+        return report_errors(
+            options.format,
+            device_errors
+                .into_iter()
+                .map(|(path, error)| (Some(path.as_path()), error.to_string())),
+        );
+    }
 
-    if device_errors.is_empty() {
-        let array = MdArray::open(devices);
-        let diagnosis = array.diagnose();
-        println!("{diagnosis:#?}");
+    if options.scan {
+        let mut errors = Vec::new();
+        for (path, device) in options.devices.iter().zip(devices) {
+            match scan_for_superblocks(&device) {
+                Ok(scan) => {
+                    for hit in scan.hits {
+                        println!("{}: {}", device.id, hit);
+                    }
+                    errors.extend(scan.unreadable.into_iter().map(|range| {
+                        (
+                            Some(path.as_path()),
+                            format!(
+                                "bytes {}-{} could not be read, and were not searched",
+                                range.start,
+                                range.end - 1
+                            ),
+                        )
+                    }));
+                }
+                Err(error) => errors.push((Some(path.as_path()), error.to_string())),
+            }
+        }
+        report_errors(options.format, errors)
+    } else if options.search {
+        match MdGeometrySearch::default().run(&devices) {
+            Ok(candidates) => {
+                for (rank, candidate) in candidates.iter().enumerate() {
+                    println!(
+                        "{}. {}: {}",
+                        rank + 1,
+                        describe_geometry(&candidate.geometry, &devices),
+                        candidate.score
+                    );
+                }
+                ExitCode::SUCCESS
+            }
+            Err(error) => report_error(options.format, error),
+        }
+    } else if options.parity {
+        match MdParityInference::default().run(&devices) {
+            Ok(report) => {
+                println!(
+                    "{} rows checked, confidence {:.0}%",
+                    report.rows_checked,
+                    report.confidence * 100.0
+                );
+                for (rank, candidate) in report.candidates.iter().enumerate() {
+                    println!(
+                        "{}. {}/{} rows consistent with:",
+                        rank + 1,
+                        candidate.rows_consistent,
+                        report.rows_checked
+                    );
+                    for geometry in &candidate.geometries {
+                        println!("    {}", describe_geometry(geometry, &devices));
+                    }
+                }
+                ExitCode::SUCCESS
+            }
+            Err(error) => report_error(options.format, error),
+        }
+    } else if options.guess_chunk_size {
+        match MdChunkSizeAnalysis::default().run(&devices) {
+            Ok(estimates) => {
+                for estimate in estimates {
+                    println!(
+                        "{}: boundary contrast {:.3}{}",
+                        estimate.chunk_size,
+                        estimate.boundary_contrast,
+                        match estimate.q_rotation_consistent {
+                            Some(true) => ", consistent with Q rotation",
+                            Some(false) => ", inconsistent with Q rotation",
+                            None => "",
+                        }
+                    );
+                }
+                ExitCode::SUCCESS
+            }
+            Err(error) => report_error(options.format, error),
+        }
+    } else if options.timeline {
+        print!("{}", MdArray::open(devices).timeline());
+        ExitCode::SUCCESS
+    } else if let Some(level) = options.level {
+        match assemble_array(&options, level, devices) {
+            Ok(array) => match act_on_array(&options, &array) {
+                Some(Ok(message)) => {
+                    println!("{message}");
+                    ExitCode::SUCCESS
+                }
+                Some(Err(error)) => report_error(options.format, error),
+                None => {
+                    println!("{}", describe_array(&array));
+                    ExitCode::SUCCESS
+                }
+            },
+            Err(error) => report_error(options.format, error),
+        }
     } else {
-        for (path, error) in device_errors {
-            println!("{}: {}", path.maybe_quote(), error);
+        let array = MdArray::open(devices);
+        match act_on_array(&options, &array) {
+            Some(Ok(message)) => {
+                println!("{message}");
+                ExitCode::SUCCESS
+            }
+            Some(Err(error)) => report_error(options.format, error),
+            None => match options.format {
+                OutputFormat::Text => {
+                    print!("{}", array.diagnose());
+                    ExitCode::SUCCESS
+                }
+                OutputFormat::Json => match serde_json::to_string_pretty(&array.json_report()) {
+                    Ok(json) => {
+                        println!("{json}");
+                        ExitCode::SUCCESS
+                    }
+                    Err(error) => report_error(options.format, error),
+                },
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockDeviceReader, BlockSize, InMemoryBlockDevice};
+    use crate::ext::ReadAll;
+    use crate::md::{MdAlgorithm, MdArrayFixture, MdDevice, SectorCount, SectorNumber};
+    use crate::{assemble_array, parse_ddrescue_map_choice, parse_overlay_choice, Options};
+    use clap::{CommandFactory, Parser};
+    use itertools::Itertools;
+    use std::path::PathBuf;
+
+    #[test]
+    fn actions_conflict() {
+        Options::command().debug_assert();
+        let parse =
+            |args: &[&str]| Options::try_parse_from(["md-recover", "member0"].iter().chain(args));
+        assert!(parse(&["--scrub"]).is_ok());
+        assert!(parse(&["--scrub", "--conf"]).is_err());
+        assert!(parse(&["--timeline", "--search"]).is_err());
+        assert!(parse(&["--parity", "--guess-chunk-size"]).is_err());
+        assert!(parse(&["--create-command", "--scrub"]).is_err());
+        assert!(parse(&["--scrub", "--output", "array.img"]).is_err());
+        assert!(parse(&["--scrub", "--map", "array.map"]).is_err());
+    }
+
+    #[test]
+    fn device_choices_split_at_the_last_equals_sign() {
+        assert_eq!(
+            parse_ddrescue_map_choice("/dev/disk/by-label/a=b=rescue.map"),
+            Ok((
+                PathBuf::from("/dev/disk/by-label/a=b"),
+                PathBuf::from("rescue.map")
+            ))
+        );
+        assert_eq!(
+            parse_overlay_choice("/dev/disk/by-label/a=b=sda.delta"),
+            Ok((
+                PathBuf::from("/dev/disk/by-label/a=b"),
+                PathBuf::from("sda.delta")
+            ))
+        );
+        assert!(parse_overlay_choice("sda").is_err());
+    }
+
+    fn left_symmetric_raid5() -> MdAlgorithm {
+        MdAlgorithm::from_level_and_layout_name(5, "left-symmetric").unwrap()
+    }
+
+    /// Assembles, as with --level 5 --chunk 4K, the members of a RAID5 array
+    /// with more sectors on each than the array uses.
+    fn assemble_oversized_members(
+        fixture: &MdArrayFixture,
+        extra_sectors: usize,
+    ) -> anyhow::Result<SectorCount<u64>> {
+        let data = (0..64 * 1024)
+            .map(|index| (index % 251) as u8)
+            .collect_vec();
+        let devices = fixture
+            .member_images(&data)?
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, mut image)| {
+                image.resize(image.len() + extra_sectors * 512, 0);
+                MdDevice::from_block_device(
+                    InMemoryBlockDevice::new(image, BlockSize(512)),
+                    Some(format!("member{index}")),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let options = Options::try_parse_from(["md-recover", "--level", "5", "--chunk", "4K"])?;
+        let array = assemble_array(&options, 5, devices).map_err(anyhow::Error::msg)?;
+        let sectors_per_device = array.format().unwrap().sectors_per_device;
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+        Ok(sectors_per_device)
+    }
+
+    #[test]
+    fn assemble_whole_chunks_of_members() -> anyhow::Result<()> {
+        let fixture = MdArrayFixture {
+            superblock: None,
+            data_offset: SectorNumber(0),
+            ..MdArrayFixture::new(left_symmetric_raid5(), 3, 8)
+        };
+        // Not a whole chunk.
+        assert_eq!(assemble_oversized_members(&fixture, 3)?, SectorCount(64));
+        Ok(())
+    }
+
+    #[test]
+    fn assemble_with_recorded_sectors_per_device() -> anyhow::Result<()> {
+        let fixture = MdArrayFixture::new(left_symmetric_raid5(), 3, 8);
+        // More than a chunk, which the superblocks say the array does not use.
+        assert_eq!(assemble_oversized_members(&fixture, 11)?, SectorCount(64));
+        Ok(())
+    }
+}
@@ -1,8 +1,43 @@
 use crate::md::raid5::Raid5Algorithm;
 use crate::md::raid6::Raid6Algorithm;
 use crate::md::units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber};
+use std::fmt::{Display, Formatter};
 use std::io;
 
+/// Where a sector of an array is stored: the sector within each member, the
+/// member holding the data, and the members holding the parity for that
+/// row.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct MdSectorLocation {
+    pub sector_in_device: SectorNumber,
+    pub data_device_number: DeviceNumber,
+    pub p_device_number: DeviceNumber,
+    pub q_device_number: Option<DeviceNumber>,
+}
+
+/// The names mdadm gives to RAID5 and RAID6 layouts, canonical names first.
+const LAYOUT_NAMES: [(&str, u32); 19] = [
+    ("left-asymmetric", 0),
+    ("right-asymmetric", 1),
+    ("left-symmetric", 2),
+    ("right-symmetric", 3),
+    ("parity-first", 4),
+    ("parity-last", 5),
+    ("ddf-zero-restart", 8),
+    ("ddf-N-restart", 9),
+    ("ddf-N-continue", 10),
+    ("left-asymmetric-6", 16),
+    ("right-asymmetric-6", 17),
+    ("left-symmetric-6", 18),
+    ("right-symmetric-6", 19),
+    ("parity-first-6", 20),
+    ("la", 0),
+    ("ra", 1),
+    ("ls", 2),
+    ("rs", 3),
+    ("default", 2),
+];
+
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub enum MdAlgorithm {
     Unsupported { level: u32, layout: u32 },
@@ -10,6 +45,15 @@ pub enum MdAlgorithm {
     Raid6(Raid6Algorithm),
 }
 
+impl Display for MdAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.layout_name() {
+            Some(name) => write!(f, "RAID{} {}", self.level(), name),
+            None => write!(f, "RAID{} layout {}", self.level(), self.layout()),
+        }
+    }
+}
+
 impl MdAlgorithm {
     pub fn from_level_and_layout(level: u32, layout: u32) -> Self {
         match level {
@@ -20,6 +64,46 @@ impl MdAlgorithm {
         .unwrap_or(Self::Unsupported { level, layout })
     }
 
+    /// Accepts the layout names used by mdadm, or a layout number.
+    pub fn from_level_and_layout_name(level: u32, layout: &str) -> Option<Self> {
+        let layout = LAYOUT_NAMES
+            .iter()
+            .find(|(name, _)| *name == layout)
+            .map(|(_, layout)| *layout)
+            .or_else(|| layout.parse().ok())?;
+        match Self::from_level_and_layout(level, layout) {
+            Self::Unsupported { .. } => None,
+            algorithm => Some(algorithm),
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        match self {
+            MdAlgorithm::Unsupported { level, .. } => *level,
+            MdAlgorithm::Raid5(_) => 5,
+            MdAlgorithm::Raid6(_) => 6,
+        }
+    }
+
+    pub fn layout(&self) -> u32 {
+        match self {
+            MdAlgorithm::Unsupported { layout, .. } => *layout,
+            MdAlgorithm::Raid5(algorithm) => algorithm.layout(),
+            MdAlgorithm::Raid6(algorithm) => algorithm.layout(),
+        }
+    }
+
+    /// The name mdadm gives to the layout.
+    pub fn layout_name(&self) -> Option<&'static str> {
+        match self {
+            MdAlgorithm::Unsupported { .. } => None,
+            _ => LAYOUT_NAMES
+                .iter()
+                .find(|(_, layout)| *layout == self.layout())
+                .map(|(name, _)| *name),
+        }
+    }
+
     pub fn parity_device_count(&self) -> Option<DeviceCount> {
         match self {
             MdAlgorithm::Unsupported { .. } => None,
@@ -28,6 +112,39 @@ impl MdAlgorithm {
         }
     }
 
+    pub fn compute_sector(
+        &self,
+        sector_number: SectorNumber,
+        sectors_per_chunk: SectorCount<u32>,
+        raid_device_count: DeviceCount,
+    ) -> Option<MdSectorLocation> {
+        match self {
+            MdAlgorithm::Unsupported { .. } => None,
+            MdAlgorithm::Raid5(algorithm) => algorithm
+                .compute_sector(sector_number, sectors_per_chunk, raid_device_count)
+                .map(
+                    |(sector_in_device, p_device_number, data_device_number)| MdSectorLocation {
+                        sector_in_device,
+                        data_device_number,
+                        p_device_number,
+                        q_device_number: None,
+                    },
+                ),
+            MdAlgorithm::Raid6(algorithm) => algorithm
+                .compute_sector(sector_number, sectors_per_chunk, raid_device_count)
+                .map(
+                    |(sector_in_device, p_device_number, q_device_number, data_device_number)| {
+                        MdSectorLocation {
+                            sector_in_device,
+                            data_device_number,
+                            p_device_number,
+                            q_device_number: Some(q_device_number),
+                        }
+                    },
+                ),
+        }
+    }
+
     pub(in crate::md) fn read_sector<F>(
         &self,
         sector_number: SectorNumber,
@@ -39,6 +156,12 @@ impl MdAlgorithm {
         F: FnMut(DeviceNumber, SectorNumber, &mut [u8]) -> io::Result<usize>,
     {
         match self {
+            MdAlgorithm::Raid5(algorithm) => algorithm.read_sector(
+                sector_number,
+                sectors_per_chunk,
+                raid_device_count,
+                read_sector_of_device,
+            ),
             MdAlgorithm::Raid6(algorithm) => algorithm.read_sector(
                 sector_number,
                 sectors_per_chunk,
@@ -49,3 +172,48 @@ impl MdAlgorithm {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+
+    #[test]
+    fn from_level_and_layout_name() {
+        assert_eq!(
+            MdAlgorithm::from_level_and_layout_name(5, "left-symmetric"),
+            Some(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric))
+        );
+        assert_eq!(
+            MdAlgorithm::from_level_and_layout_name(5, "ra"),
+            Some(MdAlgorithm::Raid5(Raid5Algorithm::RightAsymmetric))
+        );
+        assert_eq!(
+            MdAlgorithm::from_level_and_layout_name(6, "parity-first-6"),
+            Some(MdAlgorithm::Raid6(Raid6Algorithm::Parity06))
+        );
+        assert_eq!(
+            MdAlgorithm::from_level_and_layout_name(6, "9"),
+            Some(MdAlgorithm::Raid6(Raid6Algorithm::RotatingNRestart))
+        );
+        assert_eq!(
+            MdAlgorithm::from_level_and_layout_name(5, "parity-first-6"),
+            None
+        );
+        assert_eq!(MdAlgorithm::from_level_and_layout_name(1, "default"), None);
+        assert_eq!(MdAlgorithm::from_level_and_layout_name(5, "sideways"), None);
+        assert_eq!(
+            MdAlgorithm::Raid6(Raid6Algorithm::RightSymmetric6).to_string(),
+            "RAID6 right-symmetric-6"
+        );
+        assert_eq!(
+            MdAlgorithm::Unsupported {
+                level: 1,
+                layout: 0
+            }
+            .to_string(),
+            "RAID1 layout 0"
+        );
+    }
+}
@@ -1,26 +1,92 @@
 use crate::block_device::{BlockCount, BlockDevice, BlockDeviceReader, BlockNumber, BlockSize};
+use crate::export::{ExportSource, ExportStatus};
 use crate::ext::MultiMap;
+use crate::md::conf::{conf_array, MdConfArray};
+use crate::md::create_command::{create_command, MdCreateCommand};
 use crate::md::definition::MdArrayDefinition;
-use crate::md::diagnosis::Diagnosis;
+use crate::md::diagnosis::{Diagnosis, MdJsonReport};
 use crate::md::format::MdFormat;
-use crate::md::units::SectorNumber;
+use crate::md::rebuild::rebuild_member;
+use crate::md::repair::{repair_superblocks, MdRepairedSuperblock, MdSuperblockRepair};
+use crate::md::scrub::{scrub, MdScrubReport};
+use crate::md::timeline::MdTimeline;
+use crate::md::units::{DeviceNumber, SectorCount, SectorNumber};
 use crate::md::MdDevice;
 use itertools::{Either, EitherOrBoth, Itertools};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::rc::Rc;
 
 pub struct MdArray<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     definition: Rc<MdArrayDefinition<D>>,
+    members: RefCell<HashMap<DeviceNumber, MdMemberReader<D>>>,
+}
+
+/// A member of an array as it is read, a chunk at a time.
+struct MdMemberReader<D>
+where
+    D: BlockDevice,
+{
+    reader: BlockDeviceReader<MdDevice<D>>,
+    data_offset: SectorNumber,
+
+    /// The first sector of the chunk read last, counted from the data
+    /// offset, with the chunk, or `None` if some sector of it could not be
+    /// read.
+    chunk: Option<(SectorNumber, Option<Vec<u8>>)>,
+}
+
+impl<D> MdMemberReader<D>
+where
+    D: BlockDevice,
+{
+    fn read_at(&mut self, sector_number: SectorNumber, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(
+            u64::from(self.data_offset)
+                .checked_add(u64::from(sector_number))
+                .and_then(|sector_number| sector_number.checked_mul(512))
+                .ok_or(io::ErrorKind::InvalidInput)?,
+        ))?;
+        self.reader.read_exact(buf)
+    }
+
+    fn read_sector(
+        &mut self,
+        sector_number: SectorNumber,
+        chunk_size: SectorCount<u32>,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        let chunk_size = u64::from(chunk_size).max(1);
+        let chunk_start = SectorNumber(u64::from(sector_number) / chunk_size * chunk_size);
+        if self
+            .chunk
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != chunk_start)
+        {
+            let mut chunk = vec![0u8; (chunk_size * 512) as usize];
+            let chunk = self.read_at(chunk_start, &mut chunk).ok().map(|()| chunk);
+            self.chunk = Some((chunk_start, chunk));
+        }
+        if let Some((_, Some(chunk))) = &self.chunk {
+            let offset = ((u64::from(sector_number) - u64::from(chunk_start)) * 512) as usize;
+            buf[..512].copy_from_slice(&chunk[offset..][..512]);
+            return Ok(());
+        }
+        // Around a sector that cannot be read, the others are read one by
+        // one.
+        self.read_at(sector_number, &mut buf[..512])
+    }
 }
 
 impl<D> MdArray<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     pub fn open(devices: impl IntoIterator<Item = impl Into<Rc<MdDevice<D>>>>) -> Self {
         let devices = devices.into_iter().map(Into::into).collect_vec();
@@ -99,37 +165,144 @@ where
                 new_format,
                 devices,
                 inactive_devices,
+                data_offsets: HashMap::new(),
             }),
+            members: RefCell::new(HashMap::new()),
         }
     }
 
+    /// Assembles an array with the given format, ignoring any superblocks on
+    /// the devices. `members` lists each member in order, with its data
+    /// offset, or `None` if it is missing.
+    pub fn assemble(
+        format: MdFormat,
+        members: impl IntoIterator<Item = Option<(Rc<MdDevice<D>>, SectorNumber)>>,
+    ) -> io::Result<Self> {
+        let members = members.into_iter().collect_vec();
+        if members.len() != usize::from(format.device_count) {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        let (devices, data_offsets) = members
+            .into_iter()
+            .zip(0..)
+            .filter_map(|(member, index)| {
+                member.map(|(device, data_offset)| {
+                    (
+                        (DeviceNumber(index), device),
+                        (DeviceNumber(index), data_offset),
+                    )
+                })
+            })
+            .unzip();
+        Ok(Self {
+            definition: Rc::new(MdArrayDefinition {
+                format: Some(format),
+                new_format: None,
+                devices,
+                inactive_devices: Vec::new(),
+                data_offsets,
+            }),
+            members: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn format(&self) -> Option<&MdFormat> {
+        self.definition
+            .new_format
+            .as_ref()
+            .or(self.definition.format.as_ref())
+    }
+
     pub fn diagnose(&self) -> Diagnosis {
         self.definition.diagnose()
     }
+
+    /// Checks the parity of every stripe. Every member must be present.
+    pub fn scrub(&self) -> io::Result<MdScrubReport> {
+        scrub(&self.definition)
+    }
+
+    /// Writes an image of the missing member `device_number` to `output`,
+    /// worked out from the parity of the others.
+    pub fn rebuild_member(
+        &self,
+        device_number: DeviceNumber,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        rebuild_member(self, &self.definition, device_number, output)
+    }
+
+    /// The `mdadm --create --assume-clean` command that recreates the array
+    /// with the same data layout.
+    pub fn create_command(&self) -> io::Result<MdCreateCommand> {
+        create_command(&self.definition)
+    }
+
+    /// The ARRAY line of mdadm.conf that assembles the array at boot.
+    pub fn conf_array(&self) -> io::Result<MdConfArray> {
+        conf_array(&self.definition)
+    }
+
+    /// The superblock of every member with the given repairs, for writing
+    /// back to the member, or better to an overlay of it.
+    pub fn repair_superblocks(
+        &self,
+        repair: &MdSuperblockRepair,
+    ) -> io::Result<Vec<MdRepairedSuperblock<D>>> {
+        repair_superblocks(&self.definition, repair)
+    }
+
+    pub fn timeline(&self) -> MdTimeline {
+        MdTimeline::new(self.definition.all_devices().map(AsRef::as_ref))
+    }
+
+    /// The diagnosis, with the details of the superblock of every device.
+    pub fn json_report(&self) -> MdJsonReport {
+        MdJsonReport::new(
+            &self.diagnose(),
+            self.definition.all_devices().map(AsRef::as_ref),
+        )
+    }
 }
 
-impl<D> BlockDevice for MdArray<D>
+impl<D> MdArray<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
-    fn block_size(&self) -> io::Result<BlockSize> {
-        Ok(BlockSize(512))
-    }
+    fn read_member_sector(
+        &self,
+        device_number: DeviceNumber,
+        sector_number: SectorNumber,
+        chunk_size: SectorCount<u32>,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if buf.len() < 512 {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
 
-    fn block_count(&self) -> io::Result<BlockCount> {
-        // FIXME: Handle the boundary between the new format and the old.
-        Ok(self
-            .definition
-            .new_format
-            .as_ref()
-            .or(self.definition.format.as_ref())
-            .ok_or(io::ErrorKind::InvalidData)?
-            .data_sector_count()
-            .ok_or(io::ErrorKind::InvalidData)?
-            .as_block_count())
+        let mut members = self.members.borrow_mut();
+        let member = match members.entry(device_number) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let device = self
+                    .definition
+                    .devices
+                    .get(&device_number)
+                    .ok_or(io::ErrorKind::InvalidInput)?;
+                entry.insert(MdMemberReader {
+                    reader: BlockDeviceReader::new(device.as_ref().try_clone()?),
+                    data_offset: self.definition.data_offset(device_number),
+                    chunk: None,
+                })
+            }
+        };
+        member.read_sector(sector_number, chunk_size, buf)?;
+        Ok(512)
     }
 
-    fn read_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> io::Result<usize> {
+    /// Reads a sector, and tells whether it was read from the member
+    /// holding it, or reconstructed from the others.
+    fn read_sector(&self, sector_number: SectorNumber, buf: &mut [u8]) -> io::Result<ExportStatus> {
         if buf.len() < 512 {
             Err(io::ErrorKind::InvalidInput)?;
         }
@@ -142,39 +315,526 @@ where
             .or(self.definition.format.as_ref())
             .ok_or(io::ErrorKind::InvalidData)?;
 
+        let mut failed = Vec::new();
         let block = format.algorithm.read_sector(
-            SectorNumber::from_block_number(block_number),
+            sector_number,
             format.chunk_size,
             format.device_count,
             |device_number, sector_number, buf| {
-                if buf.len() < 512 {
-                    Err(io::ErrorKind::InvalidInput)?;
+                let result =
+                    self.read_member_sector(device_number, sector_number, format.chunk_size, buf);
+                if result.is_err() {
+                    failed.push(device_number);
                 }
-
-                let device = self
-                    .definition
-                    .devices
-                    .get(&device_number)
-                    .ok_or(io::ErrorKind::InvalidInput)?
-                    .as_ref()
-                    .try_clone()?;
-                let mut reader = BlockDeviceReader::new(device);
-                reader.seek(SeekFrom::Start(
-                    u64::from(sector_number)
-                        .checked_mul(512)
-                        .ok_or(io::ErrorKind::InvalidInput)?,
-                ))?;
-                reader.read_exact(&mut buf[..512])?;
-                Ok(512)
+                result
             },
         )?;
-        buf.copy_from_slice(&block);
+        buf[..512].copy_from_slice(&block);
+
+        let location = format
+            .algorithm
+            .compute_sector(sector_number, format.chunk_size, format.device_count)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        if failed.contains(&location.data_device_number) {
+            Ok(ExportStatus::Reconstructed)
+        } else {
+            Ok(ExportStatus::Read)
+        }
+    }
+}
+
+impl<D> ExportSource for MdArray<D>
+where
+    D: BlockDevice,
+{
+    fn export_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> ExportStatus {
+        self.read_sector(SectorNumber::from_block_number(block_number), buf)
+            .unwrap_or_else(|_| {
+                buf.fill(0);
+                ExportStatus::Lost
+            })
+    }
+}
+
+impl<D> BlockDevice for MdArray<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> io::Result<BlockSize> {
+        Ok(BlockSize(512))
+    }
+
+    fn block_count(&self) -> io::Result<BlockCount> {
+        // FIXME: Handle the boundary between the new format and the old.
+        Ok(self
+            .definition
+            .new_format
+            .as_ref()
+            .or(self.definition.format.as_ref())
+            .ok_or(io::ErrorKind::InvalidData)?
+            .data_sector_count()
+            .ok_or(io::ErrorKind::InvalidData)?
+            .as_block_count())
+    }
+
+    fn read_block(&mut self, block_number: BlockNumber, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_sector(SectorNumber::from_block_number(block_number), buf)?;
         Ok(512)
     }
 
     fn try_clone(&self) -> io::Result<Self> {
         Ok(Self {
             definition: self.definition.clone(),
+            members: RefCell::new(HashMap::new()),
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{
+        BlockDevice, BlockDeviceReader, BlockSize, DdrescueBlockDevice, DdrescueMap,
+        InMemoryBlockDevice,
+    };
+    use crate::export::{Export, ExportMap};
+    use crate::ext::ReadAll;
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::diagnosis::{MdAction, MdArrayHealth, MdRisk};
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::{
+        open_member_images, pseudo_random_data, raid5_fixture, update_superblock, CHUNK_SIZE,
+        DATA_OFFSET,
+    };
+    use crate::md::format::MdFormat;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+    use crate::md::{MdArray, MdArrayFixture, MdDevice, MdGeometrySearch};
+    use byteorder::{ByteOrder, LittleEndian};
+    use itertools::Itertools;
+    use std::collections::HashMap;
+    use std::io;
+    use std::rc::Rc;
+
+    #[test]
+    fn read_raid5() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 1);
+        let array = MdArray::open(raid5_fixture([1; 16]).open(&data));
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn read_nested_raid5() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 2);
+        let outer_devices = raid5_fixture([2; 16])
+            .present_member_images(&data)
+            .into_iter()
+            .enumerate()
+            .map(|(index, image)| {
+                let inner_array = MdArray::open(raid5_fixture([3 + index as u8; 16]).open(&image));
+                MdDevice::from_block_device(inner_array, Some(format!("inner{index}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let array = MdArray::open(outer_devices);
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn read_raid5_with_ranges_unread_by_ddrescue() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 17);
+        let mut images = raid5_fixture([17; 16]).present_member_images(&data);
+        // ddrescue leaves zeros where it could not read.
+        images[1][(DATA_OFFSET as usize + 4) * 512..][..4 * 512].fill(0);
+        let map = format!(
+            "0x0 + 1\n0x0 {:#x} +\n{:#x} 0x800 -\n{:#x} {:#x} +\n",
+            (DATA_OFFSET + 4) * 512,
+            (DATA_OFFSET + 4) * 512,
+            (DATA_OFFSET + 8) * 512,
+            images[1].len() as u64 - (DATA_OFFSET + 8) * 512
+        )
+        .parse::<DdrescueMap>()
+        .unwrap();
+        let devices = images
+            .into_iter()
+            .enumerate()
+            .map(|(index, image)| {
+                let device = InMemoryBlockDevice::new(image, BlockSize(512));
+                let device = if index == 1 {
+                    DdrescueBlockDevice::new(device, &map)
+                } else {
+                    DdrescueBlockDevice::without_map(device)
+                };
+                MdDevice::from_block_device(device, Some(format!("member{index}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let array = MdArray::open(devices);
+        assert_eq!(BlockDeviceReader::new(array.try_clone()?).read_all()?, data);
+
+        // The sectors that ddrescue could not read hold the second chunk of
+        // the first stripe.
+        let mut map = ExportMap::new(data.len() as u64);
+        Export::default().write(&array, &mut io::empty(), &mut map, |_| Ok(()))?;
+        assert_eq!(map.reconstructed_ranges, vec![12 * 512..16 * 512]);
+        assert!(map.bad_ranges.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn read_raid5_with_stale_parity() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 20);
+        let mut images = raid5_fixture([20; 16]).present_member_images(&data);
+        // As after an unclean shutdown, the parity of the first stripe, on
+        // the last member, no longer matches the data.
+        images[2][DATA_OFFSET as usize * 512..][..CHUNK_SIZE as usize * 512].fill(0);
+        let array = MdArray::open(open_member_images(images));
+        assert_eq!(BlockDeviceReader::new(array.try_clone()?).read_all()?, data);
+
+        let mut map = ExportMap::new(data.len() as u64);
+        Export::default().write(&array, &mut io::empty(), &mut map, |_| Ok(()))?;
+        assert!(map.reconstructed_ranges.is_empty());
+        assert!(map.bad_ranges.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn diagnose_ext4_raid_hints() -> anyhow::Result<()> {
+        let mut data = pseudo_random_data(64 * 1024, 3);
+        let superblock = &mut data[1024..2048];
+        superblock.fill(0);
+        LittleEndian::write_u16(&mut superblock[56..], 0xef53);
+        // A stride of 4KiB on a filesystem with 1KiB blocks, over two data
+        // devices, as for this array.
+        LittleEndian::write_u16(&mut superblock[356..], 4);
+        LittleEndian::write_u32(&mut superblock[368..], 8);
+        let diagnosis = MdArray::open(raid5_fixture([4; 16]).open(&data)).diagnose();
+        assert_eq!(diagnosis.ext4_raid_hints_problem, None);
+
+        // A stride of 32KiB.
+        LittleEndian::write_u16(&mut data[1024 + 356..], 32);
+        LittleEndian::write_u32(&mut data[1024 + 368..], 64);
+        let diagnosis = MdArray::open(raid5_fixture([4; 16]).open(&data)).diagnose();
+        let problem = diagnosis.ext4_raid_hints_problem.unwrap();
+        assert_eq!(problem.len(), 1);
+        let (id, hints) = problem.into_iter().next().unwrap();
+        assert_eq!(id.to_string(), "member0");
+        assert_eq!(hints.chunk_size, Some(SectorCount(64)));
+        assert_eq!(hints.data_device_count, Some(DeviceCount(2)));
+
+        // A stride of 4KiB over three data devices, with the first chunk on
+        // member1 as parity comes first.
+        LittleEndian::write_u16(&mut data[1024 + 356..], 4);
+        LittleEndian::write_u32(&mut data[1024 + 368..], 12);
+        let fixture = MdArrayFixture {
+            algorithm: MdAlgorithm::Raid5(Raid5Algorithm::Parity0),
+            ..raid5_fixture([4; 16])
+        };
+        let diagnosis = MdArray::open(fixture.open(&data)).diagnose();
+        let problem = diagnosis.ext4_raid_hints_problem.unwrap();
+        assert_eq!(problem.len(), 1);
+        let (id, hints) = problem.into_iter().next().unwrap();
+        assert_eq!(id.to_string(), "member1");
+        assert_eq!(hints.chunk_size, Some(SectorCount(8)));
+        assert_eq!(hints.data_device_count, Some(DeviceCount(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn read_assembled_arrays_with_missing_members() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 5);
+        let format = |algorithm, device_count, data_device_count| MdFormat {
+            algorithm,
+            device_count: DeviceCount(device_count),
+            sectors_per_device: SectorCount(data.len() as u64 / 512 / data_device_count),
+            chunk_size: SectorCount(CHUNK_SIZE),
+        };
+        let member = |image: &Vec<u8>| {
+            Some((
+                Rc::new(
+                    MdDevice::from_block_device(
+                        InMemoryBlockDevice::new(image.clone(), BlockSize(512)),
+                        None::<&str>,
+                    )
+                    .unwrap(),
+                ),
+                SectorNumber(DATA_OFFSET),
+            ))
+        };
+
+        let images = raid5_fixture([6; 16]).present_member_images(&data);
+        let array = MdArray::assemble(
+            format(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric), 3, 2),
+            [member(&images[0]), None, member(&images[2])],
+        )?;
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+
+        let algorithm = MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric);
+        let images = member_images(&data, &algorithm, 4, CHUNK_SIZE, DATA_OFFSET)?;
+        let array = MdArray::assemble(
+            format(algorithm, 4, 2),
+            [
+                member(&images[0]),
+                member(&images[1]),
+                None,
+                member(&images[3]),
+            ],
+        )?;
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+
+        // RAID6 with any two members missing, in every layout.
+        let data = &data[..48 * 1024];
+        for algorithm in MdGeometrySearch::default()
+            .algorithms
+            .into_iter()
+            .filter(|algorithm| matches!(algorithm, MdAlgorithm::Raid6(_)))
+        {
+            let images = member_images(data, &algorithm, 5, CHUNK_SIZE, DATA_OFFSET)?;
+            for missing in (0..5).combinations(2) {
+                let array = MdArray::assemble(
+                    MdFormat {
+                        algorithm: algorithm.clone(),
+                        device_count: DeviceCount(5),
+                        sectors_per_device: SectorCount(data.len() as u64 / 512 / 3),
+                        chunk_size: SectorCount(CHUNK_SIZE),
+                    },
+                    (0..5)
+                        .map(|index| (!missing.contains(&index)).then(|| member(&images[index]))?)
+                        .collect_vec(),
+                )?;
+                assert_eq!(
+                    BlockDeviceReader::new(array).read_all()?,
+                    data,
+                    "{algorithm} without members {missing:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn diagnosis_report() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 8);
+        let diagnosis = MdArray::open(raid5_fixture([8; 16]).open(&data)).diagnose();
+        assert_eq!(diagnosis.health, MdArrayHealth::Assemblable);
+        assert_eq!(
+            diagnosis.to_string(),
+            "Array health: assemblable\nNo problems found.\nRecommendations, safest first:\n\
+             - [safe] Assemble the array. The superblocks agree, and every member is present and up to date.\n"
+        );
+
+        let mut devices = raid5_fixture([8; 16]).open(&data);
+        devices.remove(1);
+        let diagnosis = MdArray::open(devices).diagnose();
+        assert_eq!(diagnosis.health, MdArrayHealth::Degraded);
+        assert_eq!(
+            diagnosis.to_string(),
+            "Array health: degraded\nProblems:\n- No device was found for md device #1.\n\
+             Recommendations, safest first:\n\
+             - [safe] Find the devices for md device #1. Adding them back restores the redundancy of the array.\n\
+             - [medium risk] Assemble the array without md device #1. Parity makes up for the missing members, but leaves no redundancy, so a single read error loses data. Copy the devices first.\n"
+        );
+
+        let mut devices = raid5_fixture([8; 16]).open(&data);
+        devices.push(raid5_fixture([9; 16]).open(&data).remove(1));
+        devices.remove(1);
+        devices.remove(0);
+        let diagnosis = MdArray::open(devices).diagnose();
+        assert_eq!(diagnosis.health, MdArrayHealth::NotAssemblable);
+        let report = diagnosis.to_string();
+        assert!(report.starts_with("Array health: not assemblable\nProblems:\n"));
+        assert!(report.contains(&format!(
+            "- The devices belong to different arrays:\n    array UUID {}: member2\n    array UUID {}: member1\n",
+            "08".repeat(16),
+            "09".repeat(16)
+        )));
+        Ok(())
+    }
+
+    /// Opens the members of a three-device RAID5 with the given event counts,
+    /// leaving out those without one.
+    fn raid5_devices_with_event_counts(
+        data: &[u8],
+        event_counts: [Option<u64>; 3],
+    ) -> Vec<MdDevice<InMemoryBlockDevice>> {
+        open_member_images(
+            raid5_fixture([11; 16])
+                .present_member_images(data)
+                .into_iter()
+                .zip(event_counts)
+                .map(|(mut image, event_count)| {
+                    let event_count = event_count?;
+                    update_superblock(&mut image, |superblock| {
+                        LittleEndian::write_u64(&mut superblock[200..], event_count);
+                    });
+                    Some(image)
+                }),
+        )
+    }
+
+    #[test]
+    fn diagnosis_health_with_stale_members() {
+        let data = pseudo_random_data(64 * 1024, 11);
+        let health = |event_counts| {
+            MdArray::open(raid5_devices_with_event_counts(&data, event_counts))
+                .diagnose()
+                .health
+        };
+        assert_eq!(
+            health([Some(10), Some(10), Some(10)]),
+            MdArrayHealth::Assemblable
+        );
+        assert_eq!(
+            health([Some(10), Some(10), Some(7)]),
+            MdArrayHealth::Degraded
+        );
+        assert_eq!(
+            health([Some(10), None, Some(8)]),
+            MdArrayHealth::NotAssemblable
+        );
+    }
+
+    #[test]
+    fn diagnosis_recommendations() {
+        let data = pseudo_random_data(64 * 1024, 11);
+        let recommend = |event_counts| {
+            MdArray::open(raid5_devices_with_event_counts(&data, event_counts))
+                .diagnose()
+                .recommendations
+                .into_iter()
+                .map(|recommendation| (recommendation.risk, recommendation.action.to_string()))
+                .collect_vec()
+        };
+
+        assert_eq!(
+            recommend([Some(10), Some(10), Some(7)]),
+            [(
+                MdRisk::Medium,
+                "Leave out member2 when assembling.".to_string()
+            )]
+        );
+        assert_eq!(
+            recommend([Some(10), None, Some(8)]),
+            [
+                (
+                    MdRisk::Safe,
+                    "Find the devices for md device #1.".to_string()
+                ),
+                (
+                    MdRisk::Medium,
+                    "Assemble with --force, which marks member2 as up to date.".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            recommend([Some(100), None, Some(8)])[1],
+            (
+                MdRisk::High,
+                "Assemble with --force, which marks member2 as up to date.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn diagnose_superblock_field_mismatches() {
+        let data = pseudo_random_data(64 * 1024, 12);
+        let mut images = raid5_fixture([12; 16]).present_member_images(&data);
+        update_superblock(&mut images[1], |superblock| {
+            // A bitmap, and a bad block log.
+            LittleEndian::write_u32(&mut superblock[8..], 9);
+            LittleEndian::write_u64(&mut superblock[128..], DATA_OFFSET + 8);
+            LittleEndian::write_u64(&mut superblock[136..], 4);
+        });
+        update_superblock(&mut images[2], |superblock| {
+            LittleEndian::write_u64(&mut superblock[144..], 0);
+        });
+        let diagnosis = MdArray::open(open_member_images(images)).diagnose();
+
+        assert_eq!(diagnosis.superblock_version_problem, None);
+        assert_eq!(
+            diagnosis.data_offset_problem.as_ref().map(HashMap::len),
+            Some(1)
+        );
+        assert_eq!(
+            diagnosis.data_size_problem.as_ref().map(HashMap::len),
+            Some(1)
+        );
+        assert_eq!(
+            diagnosis.super_offset_problem.as_ref().map(HashMap::len),
+            Some(1)
+        );
+        let report = diagnosis.to_string();
+        assert!(report.contains(
+            "- The devices disagree on the optional features of the array:\n    \
+             none: member0, member2\n    bitmap, bad block log: member1\n"
+        ));
+        assert!(report.contains(
+            "- The data runs past the end of some members, from where their superblocks say it starts:\n    \
+             sector #24: member1\n"
+        ));
+        assert!(report.contains(
+            "- Some superblocks record a different place than where they were found:\n    \
+             recorded at sector #0: member2\n"
+        ));
+        assert!(report.contains("data offset"));
+    }
+
+    #[test]
+    fn diagnose_members_of_different_sizes() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 13);
+        let mut images = raid5_fixture([13; 16]).present_member_images(&data);
+        let sectors_per_device = images[0].len() as u64 / 512 - DATA_OFFSET;
+        // A larger replacement, with its data area filling the device.
+        images[2].extend_from_slice(&[0; 128 * 512]);
+        update_superblock(&mut images[2], |superblock| {
+            LittleEndian::write_u64(&mut superblock[136..], sectors_per_device + 128);
+        });
+        let array = MdArray::open(open_member_images(images));
+        let diagnosis = array.diagnose();
+
+        assert!(!diagnosis.has_problems(), "{diagnosis}");
+        assert_eq!(diagnosis.data_offsets, None);
+        assert_eq!(
+            diagnosis
+                .recommendations
+                .iter()
+                .map(|recommendation| &recommendation.action)
+                .collect_vec(),
+            [&MdAction::Assemble]
+        );
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn diagnose_per_device_data_offsets() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 14);
+        let mut images = raid5_fixture([14; 16]).present_member_images(&data);
+        // Move the data of member1 8 sectors further in, as mdadm does when
+        // a member is added with a different data offset.
+        let data_start = DATA_OFFSET as usize * 512;
+        images[1].splice(data_start..data_start, [0; 8 * 512]);
+        update_superblock(&mut images[1], |superblock| {
+            LittleEndian::write_u64(&mut superblock[128..], DATA_OFFSET + 8);
+        });
+        let array = MdArray::open(open_member_images(images));
+        let diagnosis = array.diagnose();
+
+        assert!(!diagnosis.has_problems(), "{diagnosis}");
+        assert_eq!(diagnosis.data_offsets.as_ref().map(HashMap::len), Some(2));
+        assert_eq!(
+            diagnosis
+                .recommendations
+                .iter()
+                .map(|recommendation| &recommendation.action)
+                .collect_vec(),
+            [&MdAction::Assemble]
+        );
+        assert!(diagnosis.to_string().contains(
+            "Notes:\n- The data starts at different places on the members:\n    \
+             sector #16: member0, member2\n    sector #24: member1\n"
+        ));
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+        Ok(())
+    }
+}
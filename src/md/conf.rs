@@ -0,0 +1,154 @@
+use crate::block_device::BlockDevice;
+use crate::md::create_command::{md_device_path, trim_array_name};
+use crate::md::definition::MdArrayDefinition;
+use crate::md::superblock::ArrayUuid;
+use crate::md::units::DeviceCount;
+use crate::md::MdSuperblockKind;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// The ARRAY line of mdadm.conf that identifies an array at boot, as
+/// `mdadm --detail --scan --verbose` writes it, but without the members,
+/// whose names may change.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdConfArray {
+    pub device: PathBuf,
+    pub level: u32,
+    pub device_count: DeviceCount,
+    pub metadata: MdSuperblockKind,
+    pub spare_count: usize,
+    pub name: Option<OsString>,
+    pub array_uuid: ArrayUuid,
+}
+
+impl Display for MdConfArray {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ARRAY {} level=raid{} num-devices={} metadata={}",
+            self.device.display(),
+            self.level,
+            u32::from(self.device_count),
+            self.metadata
+        )?;
+        if self.spare_count > 0 {
+            write!(f, " spares={}", self.spare_count)?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " name={}", name.to_string_lossy())?;
+        }
+        let array_uuid = match self.metadata {
+            MdSuperblockKind::Version0_90 => self.array_uuid.to_mdadm_string_of_words(),
+            _ => self.array_uuid.to_mdadm_string(),
+        };
+        write!(f, " UUID={array_uuid}")
+    }
+}
+
+/// Works out the ARRAY line of an array from the superblock of its most up
+/// to date member, counting as spares the devices of the same array that
+/// their superblocks say are.
+pub(in crate::md) fn conf_array<D: BlockDevice>(
+    definition: &MdArrayDefinition<D>,
+) -> io::Result<MdConfArray> {
+    let format = definition
+        .format
+        .as_ref()
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let (metadata, superblock) = definition
+        .newest_superblock()
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let array_uuid = superblock.array_uuid();
+    let spare_count = definition
+        .inactive_devices
+        .iter()
+        .filter_map(|device| device.superblock.as_option())
+        .filter(|spare| {
+            spare.array_uuid() == array_uuid
+                && spare
+                    .device_roles()
+                    .get(spare.device_role_index())
+                    .is_some_and(|role| role.is_spare())
+        })
+        .count();
+    let name = match metadata {
+        MdSuperblockKind::Version0_90 => None,
+        _ => superblock.array_name().and_then(trim_array_name),
+    };
+    Ok(MdConfArray {
+        device: md_device_path(name.as_deref()),
+        level: format.algorithm.level(),
+        device_count: format.device_count,
+        metadata,
+        spare_count,
+        name,
+        array_uuid,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::test::{
+        open_member_images, pseudo_random_data, raid5_fixture, update_superblock,
+    };
+    use crate::md::fixture::{MdArrayFixture, MdFixtureSuperblock};
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::superblock::{MdDeviceRole, Superblock, SuperblockMut, SuperblockVersion0};
+    use crate::md::units::SectorNumber;
+    use crate::md::{MdArray, MdDevice};
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn conf_array_with_spare() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 18);
+        let mut images = raid5_fixture([0x18; 16]).present_member_images(&data);
+        let mut spare = images[0].clone();
+        update_superblock(&mut spare, |superblock| {
+            LittleEndian::write_u32(&mut superblock[160..], 3);
+            LittleEndian::write_u32(&mut superblock[220..], 4);
+            LittleEndian::write_u16(&mut superblock[256 + 2 * 3..], 0xffff);
+        });
+        images.push(spare);
+
+        let array = MdArray::open(open_member_images(images));
+        assert_eq!(
+            array.conf_array()?.to_string(),
+            "ARRAY /dev/md/data level=raid5 num-devices=3 metadata=1.2 spares=1 \
+             name=fixture:data UUID=18181818:18181818:18181818:18181818"
+        );
+
+        // mdadm writes the UUID of version 0.90 superblocks as integers.
+        let fixture = MdArrayFixture {
+            superblock: Some(MdFixtureSuperblock::Version0_90),
+            data_offset: SectorNumber(0),
+            array_uuid: *b"0123456789abcdef",
+            ..MdArrayFixture::new(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric), 3, 8)
+        };
+        let mut devices = fixture.open(&data);
+        let mut spare = fixture.member_images(&data)?.remove(0).unwrap();
+        let offset = spare.len() - 65536;
+        let mut superblock = SuperblockVersion0::read(&spare[offset..])?;
+        let mut device_roles = superblock.device_roles();
+        device_roles.push(MdDeviceRole::spare());
+        superblock.set_device_roles(&device_roles)?;
+        superblock.set_device_role_index(3)?;
+        let superblock = superblock.to_bytes();
+        spare[offset..][..superblock.len()].copy_from_slice(&superblock);
+        devices.push(MdDevice::from_block_device(
+            InMemoryBlockDevice::new(spare, BlockSize(512)),
+            Some("spare".to_string()),
+        )?);
+
+        let array = MdArray::open(devices);
+        assert_eq!(
+            array.conf_array()?.to_string(),
+            "ARRAY /dev/md/recovered level=raid5 num-devices=3 metadata=0.90 spares=1 \
+             UUID=33323130:37363534:62613938:66656463"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use crate::block_device::BlockDevice;
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::definition::MdArrayDefinition;
+use crate::md::superblock::Superblock;
+use crate::md::units::{DeviceNumber, SectorCount, SectorNumber};
+use crate::md::{MdDeviceId, MdSuperblockKind};
+use os_display::Quotable;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// The `mdadm --create --assume-clean` command that writes new superblocks
+/// for an array with the same data layout, so that the kernel can assemble
+/// it without touching the data.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdCreateCommand {
+    /// The md device to create.
+    pub device: PathBuf,
+    pub metadata: MdSuperblockKind,
+    pub algorithm: MdAlgorithm,
+    pub chunk_size: SectorCount<u32>,
+
+    /// How much of each member the array uses, after the data offset.
+    pub size: SectorCount<u64>,
+
+    /// Each member in order, with its data offset, or `None` if it is
+    /// missing.
+    pub members: Vec<Option<(OsString, SectorNumber)>>,
+
+    pub name: Option<OsString>,
+
+    /// The members whose superblocks describe the array otherwise than the
+    /// command would, with what differs.
+    pub mismatches: Vec<(Rc<MdDeviceId>, Vec<&'static str>)>,
+}
+
+/// Writes a size in sectors as mdadm reads it: in MiB or KiB where whole,
+/// or else in sectors.
+fn mdadm_size(sectors: u64) -> String {
+    if sectors.is_multiple_of(2048) {
+        format!("{}M", sectors / 2048)
+    } else if sectors.is_multiple_of(2) {
+        format!("{}K", sectors / 2)
+    } else {
+        format!("{sectors}s")
+    }
+}
+
+/// The name of an array up to the first NUL, as v1 superblocks pad it.
+pub(in crate::md) fn trim_array_name(name: &OsStr) -> Option<OsString> {
+    let name = name.as_bytes().split(|&byte| byte == 0).next()?;
+    (!name.is_empty()).then(|| OsStr::from_bytes(name).to_os_string())
+}
+
+/// The md device that mdadm names after an array, by the part of its name
+/// after the host name, if any.
+pub(in crate::md) fn md_device_path(name: Option<&OsStr>) -> PathBuf {
+    PathBuf::from("/dev/md").join(
+        name.and_then(|name| name.as_bytes().rsplit(|&byte| byte == b':').next())
+            .map_or(OsStr::new("recovered"), OsStr::from_bytes),
+    )
+}
+
+impl MdCreateCommand {
+    /// What in `superblock`, found on the member `device_number` as a
+    /// superblock of the given kind, differs from what the command would
+    /// write there.
+    pub fn check_superblock(
+        &self,
+        device_number: DeviceNumber,
+        kind: MdSuperblockKind,
+        superblock: &dyn Superblock,
+    ) -> Vec<&'static str> {
+        let data_offset = self
+            .members
+            .get(usize::from(device_number))
+            .and_then(|member| member.as_ref())
+            .map(|(_, data_offset)| *data_offset);
+        let role = superblock
+            .device_roles()
+            .get(superblock.device_role_index())
+            .and_then(|role| role.device_number());
+        [
+            (kind == self.metadata, "superblock version"),
+            (superblock.algorithm() == self.algorithm, "level or layout"),
+            (superblock.chunk_size() == self.chunk_size, "chunk size"),
+            (
+                usize::from(superblock.raid_device_count()) == self.members.len(),
+                "number of devices",
+            ),
+            (superblock.sectors_per_device() == self.size, "size"),
+            (Some(superblock.data_offset()) == data_offset, "data offset"),
+            (role == Some(device_number), "role"),
+            (
+                kind == MdSuperblockKind::Version0_90
+                    || superblock.array_name().and_then(trim_array_name) == self.name,
+                "array name",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(matches, what)| (!matches).then_some(what))
+        .collect()
+    }
+}
+
+impl Display for MdCreateCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mdadm --create {} --assume-clean --metadata={} --level={}",
+            self.device.maybe_quote(),
+            self.metadata,
+            self.algorithm.level()
+        )?;
+        match self.algorithm.layout_name() {
+            Some(layout) => write!(f, " --layout={layout}")?,
+            None => write!(f, " --layout={}", self.algorithm.layout())?,
+        }
+        write!(
+            f,
+            " --chunk={} --raid-devices={} --size={}",
+            mdadm_size(self.chunk_size.into()),
+            self.members.len(),
+            mdadm_size(self.size.into())
+        )?;
+
+        // Version 0.90 superblocks have the data at the start of each member,
+        // as version 1.0 superblocks do unless told otherwise.
+        let data_offsets = self
+            .members
+            .iter()
+            .flatten()
+            .map(|(_, data_offset)| *data_offset)
+            .collect::<Vec<_>>();
+        let variable = data_offsets.windows(2).any(|pair| pair[0] != pair[1]);
+        let default = data_offsets
+            .iter()
+            .all(|data_offset| *data_offset == SectorNumber(0))
+            && self.metadata == MdSuperblockKind::Version1_0;
+        if self.metadata != MdSuperblockKind::Version0_90 && !default {
+            match data_offsets.first() {
+                Some(_) if variable => write!(f, " --data-offset=variable")?,
+                Some(data_offset) => {
+                    write!(f, " --data-offset={}", mdadm_size((*data_offset).into()))?
+                }
+                None => {}
+            }
+        }
+        if let Some(name) = &self.name {
+            write!(f, " --name={}", name.maybe_quote())?;
+        }
+
+        for member in &self.members {
+            match member {
+                Some((path, data_offset)) if variable => {
+                    let mut path = path.clone();
+                    path.push(format!(":{}", mdadm_size((*data_offset).into())));
+                    write!(f, " {}", path.maybe_quote())?
+                }
+                Some((path, _)) => write!(f, " {}", path.maybe_quote())?,
+                None => write!(f, " missing")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Works out the command that recreates an array as it is defined, from
+/// its superblocks or from the geometry it was assembled with, and checks
+/// it against the superblock of every member.
+pub(in crate::md) fn create_command<D: BlockDevice>(
+    definition: &MdArrayDefinition<D>,
+) -> io::Result<MdCreateCommand> {
+    if definition.new_format.is_some() {
+        // The array is in the middle of a reshape, which mdadm cannot
+        // create.
+        Err(io::ErrorKind::Unsupported)?;
+    }
+    let format = definition
+        .format
+        .as_ref()
+        .ok_or(io::ErrorKind::InvalidData)?;
+    if format.parity_device_count().is_none() {
+        Err(io::ErrorKind::Unsupported)?;
+    }
+
+    // The most up to date member decides what the superblocks had.
+    let newest = definition.newest_superblock();
+    let members = (0..u32::from(format.device_count))
+        .map(DeviceNumber)
+        .map(|device_number| {
+            definition.devices.get(&device_number).map(|device| {
+                (
+                    device
+                        .id
+                        .user_reference()
+                        .map_or_else(|| device.id.to_string().into(), OsStr::to_os_string),
+                    definition.data_offset(device_number),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let metadata = match newest {
+        Some((kind, _)) => kind,
+        // Without superblocks, the data offset tells where one fits.
+        None if members
+            .iter()
+            .flatten()
+            .all(|(_, data_offset)| *data_offset == SectorNumber(0)) =>
+        {
+            MdSuperblockKind::Version1_0
+        }
+        None => MdSuperblockKind::Version1_2,
+    };
+    match metadata {
+        MdSuperblockKind::Imsm | MdSuperblockKind::Ddf => Err(io::ErrorKind::Unsupported)?,
+        MdSuperblockKind::Version0_90
+            if members
+                .iter()
+                .flatten()
+                .any(|(_, data_offset)| *data_offset != SectorNumber(0)) =>
+        {
+            Err(io::ErrorKind::InvalidData)?
+        }
+        _ => {}
+    }
+    let name = newest
+        .filter(|(kind, _)| *kind != MdSuperblockKind::Version0_90)
+        .and_then(|(_, superblock)| superblock.array_name())
+        .and_then(trim_array_name);
+
+    // mdadm only uses whole chunks of each member.
+    let chunk_size = u64::from(format.chunk_size);
+    let size = u64::from(format.sectors_per_device)
+        .checked_div(chunk_size)
+        .ok_or(io::ErrorKind::InvalidData)?
+        * chunk_size;
+
+    let mut command = MdCreateCommand {
+        device: md_device_path(name.as_deref()),
+        metadata,
+        algorithm: format.algorithm.clone(),
+        chunk_size: format.chunk_size,
+        size: SectorCount(size),
+        members,
+        name,
+        mismatches: Vec::new(),
+    };
+    let mut device_numbers = definition.devices.keys().copied().collect::<Vec<_>>();
+    device_numbers.sort_by_key(|device_number| u32::from(*device_number));
+    for device_number in device_numbers {
+        let device = &definition.devices[&device_number];
+        let (Some(location), Some(superblock)) =
+            (device.superblock_location(), device.superblock.as_option())
+        else {
+            continue;
+        };
+        let mismatches = command.check_superblock(device_number, location.kind, superblock);
+        if !mismatches.is_empty() {
+            command.mismatches.push((device.id.clone(), mismatches));
+        }
+    }
+    Ok(command)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::fixture::test::{pseudo_random_data, raid5_fixture};
+    use crate::md::format::MdFormat;
+    use crate::md::units::{SectorCount, SectorNumber};
+    use crate::md::MdArray;
+    use itertools::Itertools;
+    use std::rc::Rc;
+
+    #[test]
+    fn create_command_for_raid5() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 17);
+        let mut devices = raid5_fixture([17; 16])
+            .open(&data)
+            .into_iter()
+            .map(Rc::new)
+            .collect_vec();
+        devices.remove(1);
+        let array = MdArray::open(devices.clone());
+        let command = array.create_command()?;
+        assert_eq!(
+            command.to_string(),
+            "mdadm --create /dev/md/data --assume-clean --metadata=1.2 --level=5 \
+             --layout=left-symmetric --chunk=4K --raid-devices=3 --size=32K \
+             --data-offset=8K --name=fixture:data member0 missing member2"
+        );
+        assert!(command.mismatches.is_empty());
+
+        // Assembled with another chunk size and data offsets that differ.
+        let format = MdFormat {
+            chunk_size: SectorCount(16),
+            ..array.format().unwrap().clone()
+        };
+        let array = MdArray::assemble(
+            format,
+            [
+                Some((devices[0].clone(), SectorNumber(16))),
+                None,
+                Some((devices[1].clone(), SectorNumber(17))),
+            ],
+        )?;
+        let command = array.create_command()?;
+        assert_eq!(
+            command.to_string(),
+            "mdadm --create /dev/md/data --assume-clean --metadata=1.2 --level=5 \
+             --layout=left-symmetric --chunk=8K --raid-devices=3 --size=32K \
+             --data-offset=variable --name=fixture:data member0:8K missing member2:17s"
+        );
+        assert_eq!(
+            command
+                .mismatches
+                .iter()
+                .map(|(id, what)| (id.to_string(), what.clone()))
+                .collect_vec(),
+            vec![
+                ("member0".to_string(), vec!["chunk size"]),
+                ("member2".to_string(), vec!["chunk size", "data offset"]),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,37 +1,53 @@
 use crate::block_device::BlockDevice;
 use crate::ext::MultiMap;
 use crate::md::algorithm::MdAlgorithm;
-use crate::md::diagnosis::Diagnosis;
+use crate::md::device::{MdSuperblockCandidate, MdSuperblockLocation};
+use crate::md::diagnosis::{Diagnosis, MdAction, MdArrayHealth, MdRecommendation, MdRisk};
 use crate::md::format::MdFormat;
-use crate::md::superblock::{ArrayUuid, MdDeviceRole, ReshapeStatus};
-use crate::md::units::{DeviceCount, DeviceNumber, MetadataEventCount, SectorCount};
-use crate::md::{MdDevice, MdDeviceId, MdDeviceSuperblock};
+use crate::md::raid_hints::MdExt4RaidHints;
+use crate::md::superblock::{
+    ArrayUuid, Features, MdDeviceRole, MdSuperblockVersion, ReshapeStatus, Superblock,
+};
+use crate::md::units::{DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber};
+use crate::md::{MdDevice, MdDeviceId, MdDeviceSuperblock, MdSuperblockKind};
+use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
-use std::io::{Read, Seek};
 use std::rc::Rc;
 
 pub struct MdArrayDefinition<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     pub format: Option<MdFormat>,
     pub new_format: Option<MdFormat>,
     pub devices: HashMap<DeviceNumber, Rc<MdDevice<D>>>,
     pub inactive_devices: Vec<Rc<MdDevice<D>>>,
+
+    /// Data offsets that override those in the superblocks of the devices.
+    pub data_offsets: HashMap<DeviceNumber, SectorNumber>,
 }
 
 impl<D> MdArrayDefinition<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
+    /// A device at most this many events behind the others most likely
+    /// dropped out as the array stopped, as in a crash or power failure, so
+    /// little or nothing was written to the array without it.
+    const FORCE_EVENT_GAP: u64 = 4;
+
     pub fn diagnose(&self) -> Diagnosis {
-        Diagnosis {
+        let mut diagnosis = Diagnosis {
+            health: self.diagnose_health(),
+            missing_member_problem: self.diagnose_missing_member_problem(),
             device_too_small_problem: self.diagnose_device_too_small_problem(),
             missing_superblock_problem: self.diagnose_missing_superblock_problem(),
+            conflicting_superblocks_problem: self.diagnose_conflicting_superblocks_problem(),
             array_uuid_problem: self.diagnose_array_uuid_problem(),
             array_name_problem: self.diagnose_array_name_problem(),
             algorithm_problem: self.diagnose_algorithm_problem(),
+            unsupported_algorithm_problem: self.diagnose_unsupported_algorithm_problem(),
             size_problem: self.diagnose_size_problem(),
             chunk_size_problem: self.diagnose_chunk_size_problem(),
             device_count_problem: self.diagnose_device_count_problem(),
@@ -39,13 +55,101 @@ where
             event_count_problem: self.diagnose_event_count_problem(),
             device_role_index_problem: self.diagnose_device_role_index_problem(),
             device_roles_problem: self.diagnose_device_roles_problem(),
-        }
+            superblock_version_problem: self.diagnose_superblock_version_problem(),
+            data_offset_problem: self.diagnose_data_offset_problem(),
+            data_size_problem: self.diagnose_data_size_problem(),
+            super_offset_problem: self.diagnose_super_offset_problem(),
+            features_problem: self.diagnose_features_problem(),
+            ext4_raid_hints_problem: self.diagnose_ext4_raid_hints_problem(),
+            data_offsets: self.diagnose_data_offsets(),
+            recommendations: Vec::new(),
+        };
+        diagnosis.recommendations = self.recommend(&diagnosis);
+        diagnosis
     }
 
-    fn all_devices(&self) -> impl Iterator<Item = &Rc<MdDevice<D>>> {
+    pub(in crate::md) fn all_devices(&self) -> impl Iterator<Item = &Rc<MdDevice<D>>> {
         self.devices.values().chain(self.inactive_devices.iter())
     }
 
+    /// The data offset of a member: the one given when the array was
+    /// assembled, if any, or else the one in its superblock.
+    pub(in crate::md) fn data_offset(&self, device_number: DeviceNumber) -> SectorNumber {
+        self.data_offsets
+            .get(&device_number)
+            .copied()
+            .or_else(|| {
+                self.devices
+                    .get(&device_number)?
+                    .superblock
+                    .as_option()
+                    .map(|superblock| superblock.data_offset())
+            })
+            .unwrap_or(SectorNumber(0))
+    }
+
+    /// The superblock of the member with the most events, and its kind.
+    pub(in crate::md) fn newest_superblock(&self) -> Option<(MdSuperblockKind, &dyn Superblock)> {
+        self.devices
+            .values()
+            .filter_map(|device| {
+                Some((
+                    device.superblock_location()?.kind,
+                    device.superblock.as_option()?,
+                ))
+            })
+            .max_by_key(|(_, superblock)| superblock.event_count())
+    }
+
+    /// The device numbers of the array for which no device was found.
+    fn missing_members(&self) -> Option<Vec<DeviceNumber>> {
+        self.format.as_ref().map(|format| {
+            (0..u32::from(format.device_count))
+                .map(DeviceNumber)
+                .filter(|device_number| !self.devices.contains_key(device_number))
+                .collect()
+        })
+    }
+
+    fn diagnose_health(&self) -> MdArrayHealth {
+        let (Some(parity_device_count), Some(missing_members)) = (
+            self.format
+                .as_ref()
+                .and_then(|format| format.parity_device_count()),
+            self.missing_members(),
+        ) else {
+            return MdArrayHealth::NotAssemblable;
+        };
+        // Members with fewer events than the newest are left out by md, as
+        // if they were missing.
+        let event_counts = self
+            .devices
+            .values()
+            .filter_map(|device| Some(device.superblock.as_option()?.event_count()))
+            .collect_vec();
+        let newest = event_counts.iter().max();
+        let stale_member_count = event_counts
+            .iter()
+            .filter(|&count| Some(count) != newest)
+            .count();
+        let members_left_out = missing_members.len() + stale_member_count;
+        if self.diagnose_array_uuid_problem().is_some() {
+            // The devices cannot be assembled together.
+            MdArrayHealth::NotAssemblable
+        } else if members_left_out == 0 {
+            MdArrayHealth::Assemblable
+        } else if members_left_out <= usize::from(parity_device_count) {
+            MdArrayHealth::Degraded
+        } else {
+            MdArrayHealth::NotAssemblable
+        }
+    }
+
+    fn diagnose_missing_member_problem(&self) -> Option<Vec<DeviceNumber>> {
+        self.missing_members()
+            .filter(|missing_members| !missing_members.is_empty())
+    }
+
     fn diagnose_device_too_small_problem(&self) -> Option<HashSet<Rc<MdDeviceId>>> {
         let set = HashSet::from_iter(self.all_devices().filter_map(|device| {
             match device.superblock.as_ref() {
@@ -76,6 +180,47 @@ where
         }
     }
 
+    fn diagnose_conflicting_superblocks_problem(
+        &self,
+    ) -> Option<HashMap<Rc<MdDeviceId>, Vec<MdSuperblockLocation>>> {
+        // Candidates that describe the same member of the same array, such
+        // as a leftover copy of the superblock, do not conflict.
+        let conflicting = |candidates: &[MdSuperblockCandidate]| {
+            !candidates
+                .iter()
+                .map(|candidate| {
+                    candidate.superblock.as_option().map(|superblock| {
+                        (
+                            superblock.array_uuid(),
+                            superblock.event_count(),
+                            superblock.device_role_index(),
+                        )
+                    })
+                })
+                .all_equal()
+        };
+        let map = HashMap::from_iter(self.all_devices().filter_map(|device| {
+            if conflicting(&device.superblock_candidates) {
+                Some((
+                    device.id.clone(),
+                    device
+                        .superblock_candidates
+                        .iter()
+                        .map(|candidate| candidate.location)
+                        .collect(),
+                ))
+            } else {
+                None
+            }
+        }));
+
+        if map.is_empty() {
+            None
+        } else {
+            Some(map)
+        }
+    }
+
     fn diagnose_array_uuid_problem(&self) -> Option<HashMap<ArrayUuid, Vec<Rc<MdDeviceId>>>> {
         let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
             device
@@ -122,6 +267,13 @@ where
         }
     }
 
+    fn diagnose_unsupported_algorithm_problem(&self) -> Option<MdAlgorithm> {
+        self.format
+            .as_ref()
+            .map(|format| format.algorithm.clone())
+            .filter(|algorithm| matches!(algorithm, MdAlgorithm::Unsupported { .. }))
+    }
+
     fn diagnose_size_problem(&self) -> Option<HashMap<SectorCount<u64>, Vec<Rc<MdDeviceId>>>> {
         let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
             device
@@ -238,4 +390,385 @@ where
             None
         }
     }
+
+    fn diagnose_superblock_version_problem(
+        &self,
+    ) -> Option<HashMap<MdSuperblockVersion, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            device.superblock.as_option().map(|superblock| {
+                (
+                    MdSuperblockVersion {
+                        major: superblock.major_version(),
+                        minor: superblock.minor_version(),
+                    },
+                    device.id.clone(),
+                )
+            })
+        }));
+
+        if map.len() > 1 {
+            Some(map)
+        } else {
+            None
+        }
+    }
+
+    /// The number of sectors of each member that the array uses.
+    fn used_sectors_per_device(&self, superblock: &dyn Superblock) -> SectorCount<u64> {
+        self.format.as_ref().map_or_else(
+            || superblock.sectors_per_device(),
+            |format| format.sectors_per_device,
+        )
+    }
+
+    /// The sector where the data area of a device has to end: its superblock
+    /// if that comes after the data, as with versions 0.90 and 1.0, or the
+    /// end of the device.
+    fn data_end(device: &MdDevice<D>, data_offset: SectorNumber) -> Option<u64> {
+        device
+            .superblock_location()
+            .map(|location| location.offset / 512)
+            .filter(|&sector| sector > u64::from(data_offset))
+            .or_else(|| {
+                Some(
+                    device
+                        .block_count()
+                        .ok()?
+                        .size_bytes(device.block_size().ok()?)?
+                        / 512,
+                )
+            })
+    }
+
+    fn diagnose_data_offset_problem(&self) -> Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            let superblock = device.superblock.as_option()?;
+            let data_offset = superblock.data_offset();
+            let used = u64::from(self.used_sectors_per_device(superblock));
+            let end = Self::data_end(device, data_offset)?;
+            (u64::from(data_offset) + used > end).then(|| (data_offset, device.id.clone()))
+        }));
+
+        if map.is_empty() {
+            None
+        } else {
+            Some(map)
+        }
+    }
+
+    /// Data offsets differ between members when the array was grown or
+    /// reshaped, or members were replaced by larger ones. This only matters
+    /// if the data then does not fit, which is a data offset problem.
+    fn diagnose_data_offsets(&self) -> Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            device
+                .superblock
+                .as_option()
+                .map(|superblock| (superblock.data_offset(), device.id.clone()))
+        }));
+
+        if map.len() > 1 {
+            Some(map)
+        } else {
+            None
+        }
+    }
+
+    /// The data area of each member may be larger than the array uses, as
+    /// when members are of different sizes, but not smaller.
+    fn diagnose_data_size_problem(&self) -> Option<HashMap<SectorCount<u64>, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            let superblock = device.superblock.as_option()?;
+            let data_size = superblock.data_size()?;
+            (data_size < self.used_sectors_per_device(superblock))
+                .then(|| (data_size, device.id.clone()))
+        }));
+
+        if map.is_empty() {
+            None
+        } else {
+            Some(map)
+        }
+    }
+
+    /// Version 1.0 superblocks sit near the end of each member, so where they
+    /// are differs with the size of the member. Each should record where it
+    /// was actually found.
+    fn diagnose_super_offset_problem(&self) -> Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            let super_offset = device.superblock.as_option()?.super_offset()?;
+            let found = device.superblock_location()?.offset / 512;
+            (u64::from(super_offset) != found).then(|| (super_offset, device.id.clone()))
+        }));
+
+        if map.is_empty() {
+            None
+        } else {
+            Some(map)
+        }
+    }
+
+    /// Only compares the features that every member should have alike.
+    fn diagnose_features_problem(&self) -> Option<HashMap<Features, Vec<Rc<MdDeviceId>>>> {
+        let map = HashMap::from_multi_iter(self.all_devices().filter_map(|device| {
+            device.superblock.as_option().and_then(|superblock| {
+                Some((
+                    superblock
+                        .features()
+                        .map(|features| features & Features::ARRAY_WIDE)?,
+                    device.id.clone(),
+                ))
+            })
+        }));
+
+        if map.len() > 1 {
+            Some(map)
+        } else {
+            None
+        }
+    }
+
+    /// An ext4 filesystem on the array has its superblock in the first
+    /// chunk, at the start of the data area of the member that holds it.
+    fn diagnose_ext4_raid_hints_problem(&self) -> Option<HashMap<Rc<MdDeviceId>, MdExt4RaidHints>> {
+        let format = self.format.as_ref()?;
+        let location = format.algorithm.compute_sector(
+            SectorNumber(0),
+            format.chunk_size,
+            format.device_count,
+        )?;
+        let device = self.devices.get(&location.data_device_number)?;
+        let hints = MdExt4RaidHints::read_from_member(
+            device,
+            self.data_offset(location.data_device_number),
+        )?;
+        if hints.matches(format) {
+            None
+        } else {
+            Some(HashMap::from([(device.id.clone(), hints)]))
+        }
+    }
+
+    /// Suggests steps towards recovering the array from the problems found,
+    /// safest first.
+    fn recommend(&self, diagnosis: &Diagnosis) -> Vec<MdRecommendation> {
+        let mut recommendations = Vec::new();
+
+        if let Some(map) = &diagnosis.array_uuid_problem {
+            // Everything else assumes the devices belong to one array.
+            let largest = map.values().map(Vec::len).max().unwrap_or(0);
+            if map.values().filter(|ids| ids.len() == largest).count() == 1 {
+                recommendations.push(MdRecommendation::new(
+                    MdRisk::Safe,
+                    MdAction::Exclude(
+                        map.values()
+                            .filter(|ids| ids.len() < largest)
+                            .flatten()
+                            .cloned()
+                            .collect(),
+                    ),
+                    "Their array UUID shows that they belong to a different array than most of the devices. Diagnose the array again without them.",
+                ));
+            } else {
+                recommendations.push(MdRecommendation::new(
+                    MdRisk::Safe,
+                    MdAction::ChooseArray,
+                    "The devices are split evenly between arrays with different array UUIDs.",
+                ));
+            }
+            return recommendations;
+        }
+
+        let disagreements = [
+            (
+                diagnosis.algorithm_problem.is_some(),
+                "RAID level or layout",
+            ),
+            (diagnosis.size_problem.is_some(), "size of each member"),
+            (diagnosis.chunk_size_problem.is_some(), "chunk size"),
+            (
+                diagnosis.device_count_problem.is_some(),
+                "number of devices",
+            ),
+            (diagnosis.reshape_problem.is_some(), "reshape in progress"),
+            (
+                diagnosis.superblock_version_problem.is_some(),
+                "superblock version",
+            ),
+            (diagnosis.data_offset_problem.is_some(), "data offset"),
+            // Stale devices are expected to disagree on roles, since they
+            // missed the updates that changed them.
+            (
+                diagnosis.event_count_problem.is_none()
+                    && (diagnosis.device_roles_problem.is_some()
+                        || diagnosis.device_role_index_problem.is_some()),
+                "roles of the devices",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(found, what)| found.then_some(what))
+        .collect_vec();
+        if !disagreements.is_empty() {
+            recommendations.push(MdRecommendation::new(
+                MdRisk::Safe,
+                MdAction::InferGeometry,
+                format!(
+                    "The superblocks disagree on the {}, so they cannot all be trusted. This only reads the devices.",
+                    disagreements.join(", ")
+                ),
+            ));
+        } else if diagnosis.ext4_raid_hints_problem.is_some() {
+            recommendations.push(MdRecommendation::new(
+                MdRisk::Safe,
+                MdAction::InferGeometry,
+                "The ext4 filesystem on the array was created for a different geometry than the superblocks describe. This only reads the devices.",
+            ));
+        }
+
+        let unusable = diagnosis
+            .device_too_small_problem
+            .iter()
+            .chain(diagnosis.missing_superblock_problem.iter())
+            .flatten()
+            .cloned()
+            .collect_vec();
+        if !unusable.is_empty() {
+            recommendations.push(MdRecommendation::new(
+                MdRisk::Safe,
+                MdAction::Exclude(unusable),
+                "They hold no md superblock, so md cannot assemble them. If they are members whose superblocks were overwritten, --scan or --search may still find their place in the array.",
+            ));
+        }
+
+        let parity_device_count = self
+            .format
+            .as_ref()
+            .and_then(|format| format.parity_device_count())
+            .map(usize::from);
+        let missing_members = self.missing_members().unwrap_or_default();
+        let mut stale_members = Vec::new();
+        if let Some(map) = &diagnosis.event_count_problem {
+            let newest = map.keys().max().copied().unwrap_or(MetadataEventCount(0));
+            let events_behind = |count: MetadataEventCount| u64::from(newest) - u64::from(count);
+            // Least stale first.
+            let stale = map
+                .iter()
+                .filter(|(&count, _)| count != newest)
+                .sorted_by(|(a, _), (b, _)| b.cmp(a))
+                .flat_map(|(&count, ids)| ids.iter().sorted().map(move |id| (count, id.clone())))
+                .collect_vec();
+            let describe = |stale: &[(MetadataEventCount, Rc<MdDeviceId>)]| {
+                stale
+                    .iter()
+                    .map(|(count, id)| format!("{id} is {} events behind", events_behind(*count)))
+                    .join(", ")
+            };
+            stale_members = stale
+                .iter()
+                .filter(|(_, id)| self.devices.values().any(|device| device.id == *id))
+                .cloned()
+                .collect_vec();
+            let members_left_out = missing_members.len() + stale_members.len();
+
+            match parity_device_count {
+                Some(parity_device_count) if members_left_out <= parity_device_count => {
+                    recommendations.push(MdRecommendation::new(
+                        if stale_members.is_empty() {
+                            MdRisk::Safe
+                        } else if members_left_out < parity_device_count {
+                            MdRisk::Low
+                        } else {
+                            MdRisk::Medium
+                        },
+                        MdAction::Exclude(stale.iter().map(|(_, id)| id.clone()).collect()),
+                        format!(
+                            "Their superblocks missed updates to the array ({}), so their contents may be out of date. The array can be assembled from the up-to-date devices, so --force is not needed.",
+                            describe(&stale)
+                        ),
+                    ));
+                }
+                Some(parity_device_count) if missing_members.len() <= parity_device_count => {
+                    let needed = &stale_members[..members_left_out - parity_device_count];
+                    let gap = needed.last().map_or(0, |(count, _)| events_behind(*count));
+                    recommendations.push(MdRecommendation::new(
+                        if gap <= Self::FORCE_EVENT_GAP {
+                            MdRisk::Medium
+                        } else {
+                            MdRisk::High
+                        },
+                        MdAction::Force(needed.iter().map(|(_, id)| id.clone()).collect()),
+                        format!(
+                            "Too few members are up to date to assemble the array without the least stale of the others ({}). Anything written to the array after they dropped out may be corrupt{}. Copy the devices first, and check the filesystem read-only after assembling.",
+                            describe(needed),
+                            if gap <= Self::FORCE_EVENT_GAP {
+                                ", though so few events suggest they dropped out as the array stopped"
+                            } else {
+                                ""
+                            }
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if let (false, Some(parity_device_count)) =
+            (missing_members.is_empty(), parity_device_count)
+        {
+            let spare = missing_members.len() < parity_device_count;
+            if stale_members.is_empty() && missing_members.len() <= parity_device_count {
+                recommendations.push(MdRecommendation::new(
+                    if spare { MdRisk::Low } else { MdRisk::Medium },
+                    MdAction::AssembleDegraded(missing_members.clone()),
+                    if spare {
+                        "Parity makes up for the missing members, with redundancy to spare."
+                    } else {
+                        "Parity makes up for the missing members, but leaves no redundancy, so a single read error loses data. Copy the devices first."
+                    },
+                ));
+            }
+            recommendations.push(MdRecommendation::new(
+                MdRisk::Safe,
+                MdAction::FindMembers(missing_members.clone()),
+                if missing_members.len() > parity_device_count {
+                    "More members are missing than parity can make up for, so the array cannot be assembled without them."
+                } else if missing_members.len() + stale_members.len() > parity_device_count {
+                    "With them, the array can be assembled without forcing stale devices back in."
+                } else {
+                    "Adding them back restores the redundancy of the array."
+                },
+            ));
+        }
+
+        let reshape_status = self.all_devices().find_map(|device| {
+            device
+                .superblock
+                .as_option()
+                .and_then(|superblock| superblock.reshape_status())
+        });
+        match reshape_status {
+            Some(status) if diagnosis.reshape_problem.is_none() && status.new_offset == 0 => {
+                recommendations.push(MdRecommendation::new(
+                    MdRisk::Medium,
+                    MdAction::UseBackupFile,
+                    "The array is in the middle of a reshape that rewrites stripes in place. md keeps a copy of the stripes being rewritten in the backup file, and without it they may be lost if the reshape was interrupted by a crash. Only use --invalid-backup if the backup file is gone.",
+                ));
+            }
+            _ if recommendations.is_empty() && diagnosis.health == MdArrayHealth::Assemblable => {
+                recommendations.push(MdRecommendation::new(
+                    MdRisk::Safe,
+                    MdAction::Assemble,
+                    if reshape_status.is_some() {
+                        "The superblocks agree, and every member is present and up to date. The reshape in progress moves the data to a new offset, so no backup file is needed."
+                    } else {
+                        "The superblocks agree, and every member is present and up to date."
+                    },
+                ));
+            }
+            _ => {}
+        }
+
+        recommendations.sort_by_key(|recommendation| recommendation.risk);
+        recommendations
+    }
 }
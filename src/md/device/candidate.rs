@@ -0,0 +1,97 @@
+use crate::md::device::superblock::MdDeviceSuperblock;
+use crate::md::superblock::Superblock;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub enum MdSuperblockKind {
+    Version0_90,
+    Version1_0,
+    Version1_1,
+    Version1_2,
+    Imsm,
+    Ddf,
+}
+
+impl Display for MdSuperblockKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MdSuperblockKind::Version0_90 => "0.90",
+                MdSuperblockKind::Version1_0 => "1.0",
+                MdSuperblockKind::Version1_1 => "1.1",
+                MdSuperblockKind::Version1_2 => "1.2",
+                MdSuperblockKind::Imsm => "imsm",
+                MdSuperblockKind::Ddf => "ddf",
+            }
+        )
+    }
+}
+
+impl FromStr for MdSuperblockKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0.90" => Ok(MdSuperblockKind::Version0_90),
+            "1.0" => Ok(MdSuperblockKind::Version1_0),
+            "1.1" => Ok(MdSuperblockKind::Version1_1),
+            "1.2" => Ok(MdSuperblockKind::Version1_2),
+            "imsm" => Ok(MdSuperblockKind::Imsm),
+            "ddf" => Ok(MdSuperblockKind::Ddf),
+            _ => Err(format!("unknown superblock format: {s}")),
+        }
+    }
+}
+
+/// Where a superblock was found: its format, and the byte offset of the
+/// superblock (or, for external metadata, of its anchor) on the device.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
+pub struct MdSuperblockLocation {
+    pub kind: MdSuperblockKind,
+    pub offset: u64,
+
+    /// The volume described, for container formats that hold several
+    /// arrays in one set of metadata, such as IMSM.
+    pub volume: Option<usize>,
+}
+
+impl Display for MdSuperblockLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.volume {
+            Some(volume) => write!(f, "{} volume {} at byte {}", self.kind, volume, self.offset),
+            None => write!(f, "{} at byte {}", self.kind, self.offset),
+        }
+    }
+}
+
+pub struct MdSuperblockCandidate {
+    pub location: MdSuperblockLocation,
+    pub superblock: Rc<MdDeviceSuperblock>,
+}
+
+impl MdSuperblockCandidate {
+    pub fn new(kind: MdSuperblockKind, offset: u64, superblock: impl Superblock + 'static) -> Self {
+        Self {
+            location: MdSuperblockLocation {
+                kind,
+                offset,
+                volume: None,
+            },
+            superblock: Rc::new(MdDeviceSuperblock::Superblock(Box::new(superblock))),
+        }
+    }
+
+    pub fn with_volume(self, volume: usize) -> Self {
+        Self {
+            location: MdSuperblockLocation {
+                volume: Some(volume),
+                ..self.location
+            },
+            ..self
+        }
+    }
+}
@@ -1,25 +1,31 @@
-use crate::block_device::{BlockCount, BlockDevice, BlockNumber, BlockSize, NativeBlockDevice};
+use crate::block_device::{
+    BlockCount, BlockDevice, BlockDeviceReader, BlockNumber, BlockSize, NativeBlockDevice,
+};
 use crate::md::device::id::MdDeviceId;
 use crate::md::device::superblock::MdDeviceSuperblock;
-use crate::md::superblock::{SuperblockVersion0, SuperblockVersion1};
+use crate::md::device::{MdSuperblockCandidate, MdSuperblockKind, MdSuperblockLocation};
+use crate::md::superblock::{
+    SuperblockDdf, SuperblockImsm, SuperblockMut, SuperblockVersion0, SuperblockVersion1,
+};
 use std::ffi::OsStr;
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Seek, SeekFrom};
 use std::path::Path;
 use std::rc::Rc;
 
 pub struct MdDevice<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     pub id: Rc<MdDeviceId>,
     pub superblock: Rc<MdDeviceSuperblock>,
+    pub superblock_candidates: Rc<Vec<MdSuperblockCandidate>>,
     device: D,
 }
 
 impl<D> MdDevice<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     const MIN_DEVICE_SIZE: u64 = 12288;
     const MIN_SUPERBLOCK_0_DEVICE_SIZE: u64 = 65536;
@@ -34,11 +40,21 @@ impl MdDevice<NativeBlockDevice> {
 
 impl<D> MdDevice<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     pub fn from_block_device<S: AsRef<OsStr>>(
-        mut device: D,
+        device: D,
         user_reference: Option<S>,
+    ) -> io::Result<Self> {
+        Self::from_block_device_with_serial(device, user_reference, None)
+    }
+
+    /// Opens a device whose drive serial number is known, by which IMSM
+    /// metadata identifies the disk among the members of its container.
+    pub fn from_block_device_with_serial<S: AsRef<OsStr>>(
+        device: D,
+        user_reference: Option<S>,
+        serial: Option<&[u8]>,
     ) -> io::Result<Self> {
         let size = device
             .block_count()?
@@ -51,43 +67,120 @@ where
             return Ok(Self {
                 id,
                 superblock: Rc::new(MdDeviceSuperblock::TooSmall),
+                superblock_candidates: Rc::new(Vec::new()),
                 device,
             });
         }
 
-        for (minor_version, offset) in [(2, 8 << 9), (1, 0), (0, (((size >> 9) - 16) & !7) << 9)] {
-            device.seek(SeekFrom::Start(offset))?;
-            if let Ok(superblock) = SuperblockVersion1::read(&mut device, minor_version) {
-                return Ok(Self {
-                    id,
-                    superblock: Rc::new(MdDeviceSuperblock::Superblock(Box::new(superblock))),
-                    device,
-                });
+        let mut reader = BlockDeviceReader::new(device);
+        let mut superblock_candidates = Vec::new();
+
+        for (kind, minor_version, offset) in [
+            (MdSuperblockKind::Version1_2, 2, 8 << 9),
+            (MdSuperblockKind::Version1_1, 1, 0),
+            (
+                MdSuperblockKind::Version1_0,
+                0,
+                (((size >> 9) - 16) & !7) << 9,
+            ),
+        ] {
+            reader.seek(SeekFrom::Start(offset))?;
+            if let Ok(superblock) = SuperblockVersion1::read(&mut reader, minor_version) {
+                superblock_candidates.push(MdSuperblockCandidate::new(kind, offset, superblock));
             }
         }
 
         if size >= Self::MIN_SUPERBLOCK_0_DEVICE_SIZE {
-            device.seek(SeekFrom::Start((size & !65535) - 65536))?;
-            if let Ok(superblock) = SuperblockVersion0::read(&mut device) {
-                return Ok(Self {
-                    id,
-                    superblock: Rc::new(MdDeviceSuperblock::Superblock(Box::new(superblock))),
-                    device,
-                });
+            let offset = (size & !65535) - 65536;
+            reader.seek(SeekFrom::Start(offset))?;
+            if let Ok(superblock) = SuperblockVersion0::read(&mut reader) {
+                superblock_candidates.push(MdSuperblockCandidate::new(
+                    MdSuperblockKind::Version0_90,
+                    offset,
+                    superblock,
+                ));
             }
         }
 
+        for superblock in SuperblockImsm::read(&mut reader, size, serial).unwrap_or_default() {
+            let volume = superblock.volume_index();
+            superblock_candidates.push(
+                MdSuperblockCandidate::new(MdSuperblockKind::Imsm, size - 1024, superblock)
+                    .with_volume(volume),
+            );
+        }
+
+        if let Ok(superblock) = SuperblockDdf::read(&mut reader, size) {
+            superblock_candidates.push(MdSuperblockCandidate::new(
+                MdSuperblockKind::Ddf,
+                size - 512,
+                superblock,
+            ));
+        }
+
         Ok(Self {
             id,
-            superblock: Rc::new(MdDeviceSuperblock::Missing),
-            device,
+            superblock: superblock_candidates
+                .first()
+                .map(|candidate| candidate.superblock.clone())
+                .unwrap_or_else(|| Rc::new(MdDeviceSuperblock::Missing)),
+            superblock_candidates: Rc::new(superblock_candidates),
+            device: reader.into_inner(),
+        })
+    }
+
+    /// Uses the superblock of the given format in place of the one chosen
+    /// by default, which is the first found in the order v1.2, v1.1, v1.0,
+    /// v0.90, IMSM, DDF. For a container format, `volume` chooses the array
+    /// among those it holds, or else the first.
+    pub fn select_superblock(
+        &mut self,
+        kind: MdSuperblockKind,
+        volume: Option<usize>,
+    ) -> io::Result<()> {
+        self.superblock = self
+            .superblock_candidates
+            .iter()
+            .find(|candidate| {
+                candidate.location.kind == kind
+                    && volume.is_none_or(|volume| candidate.location.volume == Some(volume))
+            })
+            .ok_or(io::ErrorKind::NotFound)?
+            .superblock
+            .clone();
+        Ok(())
+    }
+
+    /// Reads the superblock in use again, as one that can be changed and
+    /// written back where it was found.
+    pub fn read_superblock_mut(&self) -> io::Result<Box<dyn SuperblockMut>> {
+        let location = self.superblock_location().ok_or(io::ErrorKind::NotFound)?;
+        let mut reader = BlockDeviceReader::new(self.device.try_clone()?);
+        reader.seek(SeekFrom::Start(location.offset))?;
+        Ok(match location.kind {
+            MdSuperblockKind::Version0_90 => Box::new(SuperblockVersion0::read(reader)?),
+            MdSuperblockKind::Version1_0 => Box::new(SuperblockVersion1::read(reader, 0)?),
+            MdSuperblockKind::Version1_1 => Box::new(SuperblockVersion1::read(reader, 1)?),
+            MdSuperblockKind::Version1_2 => Box::new(SuperblockVersion1::read(reader, 2)?),
+            MdSuperblockKind::Imsm | MdSuperblockKind::Ddf => Err(io::ErrorKind::Unsupported)?,
         })
     }
+
+    pub fn block_device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn superblock_location(&self) -> Option<MdSuperblockLocation> {
+        self.superblock_candidates
+            .iter()
+            .find(|candidate| Rc::ptr_eq(&candidate.superblock, &self.superblock))
+            .map(|candidate| candidate.location)
+    }
 }
 
 impl<D> BlockDevice for MdDevice<D>
 where
-    D: BlockDevice + Read + Seek,
+    D: BlockDevice,
 {
     fn block_size(&self) -> io::Result<BlockSize> {
         self.device.block_size()
@@ -105,7 +198,103 @@ where
         Ok(Self {
             id: self.id.clone(),
             superblock: self.superblock.clone(),
+            superblock_candidates: self.superblock_candidates.clone(),
             device: self.device.try_clone()?,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::fixture::test::raid5_fixture;
+    use crate::md::fixture::MdFixtureSuperblock;
+    use crate::md::superblock::ArrayUuid;
+    use crate::md::units::SectorNumber;
+    use crate::md::{MdArray, MdArrayFixture, MdDevice, MdSuperblockKind};
+
+    /// A device with a v1.2 superblock of array `[1; 16]` in role 0, and a
+    /// v1.0 one of array `uuid` in role `role`, otherwise the same.
+    fn device_with_leftover_superblock(
+        uuid: [u8; 16],
+        role: usize,
+    ) -> anyhow::Result<MdDevice<InMemoryBlockDevice>> {
+        let data = [0; 8192];
+        let mut image = raid5_fixture([1; 16])
+            .present_member_images(&data)
+            .remove(0);
+        image.resize(128 * 512, 0);
+        // Its data comes first, and the superblock after it, at 4KiB.
+        let leftover = MdArrayFixture {
+            superblock: Some(MdFixtureSuperblock::Version1_0),
+            data_offset: SectorNumber(0),
+            ..raid5_fixture(uuid)
+        }
+        .present_member_images(&data)
+        .remove(role);
+        image[112 * 512..][..4096].copy_from_slice(&leftover[4096..8192]);
+        Ok(MdDevice::from_block_device(
+            InMemoryBlockDevice::new(image, BlockSize(512)),
+            Some("member"),
+        )?)
+    }
+
+    fn device_with_stale_superblock() -> anyhow::Result<MdDevice<InMemoryBlockDevice>> {
+        device_with_leftover_superblock([2; 16], 1)
+    }
+
+    #[test]
+    fn keeps_every_superblock_candidate() -> anyhow::Result<()> {
+        let mut device = device_with_stale_superblock()?;
+        assert_eq!(
+            device
+                .superblock_candidates
+                .iter()
+                .map(|candidate| (candidate.location.kind, candidate.location.offset))
+                .collect::<Vec<_>>(),
+            vec![
+                (MdSuperblockKind::Version1_2, 4096),
+                (MdSuperblockKind::Version1_0, 112 * 512),
+            ]
+        );
+        assert_eq!(
+            device.superblock_location().map(|location| location.kind),
+            Some(MdSuperblockKind::Version1_2)
+        );
+        assert_eq!(
+            device.superblock.as_option().map(|s| s.array_uuid()),
+            Some(ArrayUuid::from_u8_16(&[1; 16]))
+        );
+
+        device.select_superblock(MdSuperblockKind::Version1_0, None)?;
+        assert_eq!(
+            device.superblock_location().map(|location| location.kind),
+            Some(MdSuperblockKind::Version1_0)
+        );
+        assert_eq!(
+            device.superblock.as_option().map(|s| s.array_uuid()),
+            Some(ArrayUuid::from_u8_16(&[2; 16]))
+        );
+
+        assert!(device
+            .select_superblock(MdSuperblockKind::Imsm, None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn diagnose_conflicting_superblocks() -> anyhow::Result<()> {
+        let device = device_with_stale_superblock()?;
+        let id = device.id.clone();
+        let diagnosis = MdArray::open([device]).diagnose();
+        let problem = diagnosis.conflicting_superblocks_problem.unwrap();
+        assert_eq!(problem.len(), 1);
+        assert_eq!(problem[&id].len(), 2);
+
+        let device = device_with_leftover_superblock([1; 16], 0)?;
+        assert_eq!(device.superblock_candidates.len(), 2);
+        let diagnosis = MdArray::open([device]).diagnose();
+        assert!(diagnosis.conflicting_superblocks_problem.is_none());
+        Ok(())
+    }
+}
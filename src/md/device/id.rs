@@ -1,4 +1,5 @@
 use os_display::Quotable;
+use std::cmp::Ordering as CmpOrdering;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
@@ -20,6 +21,10 @@ impl MdDeviceId {
         }
     }
 
+    pub fn user_reference(&self) -> Option<&OsStr> {
+        self.user_reference.as_deref()
+    }
+
     fn next_index() -> u64 {
         let index = NEXT_INDEX.fetch_add(1, Ordering::AcqRel);
         if index == u64::MAX {
@@ -35,6 +40,19 @@ impl PartialEq for MdDeviceId {
     }
 }
 
+/// Orders devices as they were opened.
+impl Ord for MdDeviceId {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.internal_index.cmp(&other.internal_index)
+    }
+}
+
+impl PartialOrd for MdDeviceId {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Hash for MdDeviceId {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.internal_index.hash(state)
@@ -1,6 +1,14 @@
+mod candidate;
 mod device;
 mod id;
+mod scan;
 mod superblock;
 
 #[allow(unused_imports)]
-pub use self::{device::MdDevice, id::MdDeviceId, superblock::MdDeviceSuperblock};
+pub use self::{
+    candidate::{MdSuperblockCandidate, MdSuperblockKind, MdSuperblockLocation},
+    device::MdDevice,
+    id::MdDeviceId,
+    scan::{scan_for_superblocks, MdSuperblockScan, MdSuperblockScanHit},
+    superblock::MdDeviceSuperblock,
+};
@@ -0,0 +1,237 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use crate::md::device::{MdSuperblockCandidate, MdSuperblockKind};
+use crate::md::superblock::{Superblock, SuperblockVersion0, SuperblockVersion1};
+use crate::md::units::SectorNumber;
+use std::cmp::min;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// A superblock found by [`scan_for_superblocks`] at an arbitrary sector of
+/// a device.
+pub struct MdSuperblockScanHit {
+    pub candidate: MdSuperblockCandidate,
+
+    /// The byte offset at which a partition would have to start for the
+    /// superblock to be found at its usual position. The position of a
+    /// v0.90 superblock depends on the size of the partition rather than its
+    /// start, so this is `None` for v0.90 superblocks.
+    pub partition_start: Option<u64>,
+}
+
+impl Display for MdSuperblockScanHit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.partition_start {
+            Some(start) => write!(
+                f,
+                "{}, partition starts at byte {start}",
+                self.candidate.location
+            ),
+            None => write!(f, "{}", self.candidate.location),
+        }
+    }
+}
+
+/// What [`scan_for_superblocks`] found on a device.
+pub struct MdSuperblockScan {
+    pub hits: Vec<MdSuperblockScanHit>,
+
+    /// The runs of bytes that could not be read, and so were not searched,
+    /// in order.
+    pub unreadable: Vec<Range<u64>>,
+}
+
+const SCAN_BUFFER_SIZE: usize = 1 << 20;
+
+/// Searches every sector of the device for the md superblock magic number,
+/// and returns each hit that parses as a valid superblock. Parts of the
+/// device that cannot be read are skipped, and recorded.
+pub fn scan_for_superblocks<D: BlockDevice>(device: &D) -> io::Result<MdSuperblockScan> {
+    let size = device
+        .block_count()?
+        .size_bytes(device.block_size()?)
+        .ok_or(io::ErrorKind::InvalidInput)?;
+    let mut reader = BlockDeviceReader::new(device.try_clone()?);
+
+    let mut offsets = Vec::new();
+    let mut unreadable: Vec<Range<u64>> = Vec::new();
+    let mut buf = vec![0u8; SCAN_BUFFER_SIZE];
+    let mut offset = 0u64;
+    while offset < size {
+        let length = min(size - offset, SCAN_BUFFER_SIZE as u64) as usize;
+        reader.seek(SeekFrom::Start(offset))?;
+        if reader.read_exact(&mut buf[..length]).is_err() {
+            // Read what can still be read sector by sector, and leave the
+            // rest as zeros, which hold no magic number.
+            for sector_start in (0..length).step_by(512) {
+                let sector = &mut buf[sector_start..min(sector_start + 512, length)];
+                let sector_offset = offset + sector_start as u64;
+                reader.seek(SeekFrom::Start(sector_offset))?;
+                if reader.read_exact(sector).is_ok() {
+                    continue;
+                }
+                sector.fill(0);
+                let sector_end = sector_offset + sector.len() as u64;
+                match unreadable.last_mut() {
+                    Some(range) if range.end == sector_offset => range.end = sector_end,
+                    _ => unreadable.push(sector_offset..sector_end),
+                }
+            }
+        }
+        offsets.extend(
+            (offset..)
+                .step_by(512)
+                .zip(buf[..length].chunks_exact(512))
+                .filter(|(_, sector)| {
+                    let magic = array_ref![sector, 0, 4];
+                    *magic == SuperblockVersion0::MAGIC.to_le_bytes()
+                        || *magic == SuperblockVersion0::MAGIC.to_be_bytes()
+                })
+                .map(|(offset, _)| offset),
+        );
+        offset += length as u64;
+    }
+
+    let mut hits = Vec::new();
+    for offset in offsets {
+        reader.seek(SeekFrom::Start(offset))?;
+        if let Some(hit) = read_version_1(&mut reader, offset) {
+            hits.push(hit);
+            continue;
+        }
+
+        reader.seek(SeekFrom::Start(offset))?;
+        if let Some(superblock) = SuperblockVersion0::read(&mut reader)
+            .ok()
+            .filter(|superblock| superblock.valid_checksum())
+        {
+            hits.push(MdSuperblockScanHit {
+                candidate: MdSuperblockCandidate::new(
+                    MdSuperblockKind::Version0_90,
+                    offset,
+                    superblock,
+                ),
+                partition_start: None,
+            });
+        }
+    }
+    Ok(MdSuperblockScan { hits, unreadable })
+}
+
+fn read_version_1<R: Read>(reader: R, offset: u64) -> Option<MdSuperblockScanHit> {
+    // The minor version is not recorded in the superblock, so infer it from
+    // super_offset, which is relative to the start of the partition.
+    let superblock = SuperblockVersion1::read(reader, 0).ok()?;
+    if !superblock.valid_checksum() {
+        return None;
+    }
+    let super_offset = superblock.super_offset();
+    let (kind, minor_version) = match super_offset {
+        SectorNumber(0) => (MdSuperblockKind::Version1_1, 1),
+        SectorNumber(8) => (MdSuperblockKind::Version1_2, 2),
+        _ => (MdSuperblockKind::Version1_0, 0),
+    };
+
+    // The data area lies after the superblock, except for v1.0 where it
+    // lies before.
+    let data_offset = u64::from(superblock.data_offset());
+    let consistent = if minor_version == 0 {
+        data_offset
+            .checked_add(superblock.data_size().into())
+            .is_some_and(|data_end| data_end <= u64::from(super_offset))
+    } else {
+        data_offset > u64::from(super_offset)
+    };
+    if !consistent {
+        return None;
+    }
+
+    let partition_start = u64::from(super_offset)
+        .checked_mul(512)
+        .and_then(|super_offset| offset.checked_sub(super_offset))?;
+    Some(MdSuperblockScanHit {
+        candidate: MdSuperblockCandidate::new(
+            kind,
+            offset,
+            SuperblockVersion1::new(superblock.into_buffer(), minor_version),
+        ),
+        partition_start: Some(partition_start),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, DdrescueBlockDevice, DdrescueMap, InMemoryBlockDevice};
+    use crate::md::device::scan::scan_for_superblocks;
+    use crate::md::fixture::test::raid5_fixture;
+    use crate::md::MdSuperblockKind;
+
+    #[test]
+    fn scan_finds_displaced_superblock() -> anyhow::Result<()> {
+        let mut image = vec![0u8; 4096 * 512];
+        let partition_start = 63 * 512;
+        let member = raid5_fixture([1; 16])
+            .present_member_images(&[0; 8192])
+            .remove(0);
+        image[partition_start..][..member.len()].copy_from_slice(&member);
+
+        // A copy of the magic number alone is not a superblock.
+        image[2048 * 512..][..4].copy_from_slice(&0xa92b4efcu32.to_le_bytes());
+
+        let hits = scan_for_superblocks(&InMemoryBlockDevice::new(image, BlockSize(512)))?.hits;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            hits[0].candidate.location.kind,
+            MdSuperblockKind::Version1_2
+        );
+        assert_eq!(
+            hits[0].candidate.location.offset,
+            (partition_start + 4096) as u64
+        );
+        assert_eq!(hits[0].partition_start, Some(partition_start as u64));
+        Ok(())
+    }
+
+    #[test]
+    fn scan_rejects_superblock_with_bad_checksum() -> anyhow::Result<()> {
+        let mut image = raid5_fixture([1; 16])
+            .present_member_images(&[0; 8192])
+            .remove(0);
+        image[4096 + 16] ^= 1;
+        let hits = scan_for_superblocks(&InMemoryBlockDevice::new(image, BlockSize(512)))?.hits;
+        assert!(hits.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_skips_unreadable_sectors() -> anyhow::Result<()> {
+        let member = raid5_fixture([1; 16])
+            .present_member_images(&[0; 8192])
+            .remove(0);
+        let mut image = vec![0u8; 3 << 20];
+        image[63 * 512..][..member.len()].copy_from_slice(&member);
+        image[(2 << 20) + 63 * 512..][..member.len()].copy_from_slice(&member);
+        // One run of bad sectors in the first 1MiB read, and one across the
+        // first two.
+        let map = DdrescueMap {
+            blocks: vec![
+                (0x10000..0x10800, '-'),
+                ((1 << 20) - 0x400..(1 << 20) + 0x400, '-'),
+            ],
+        };
+        let device =
+            DdrescueBlockDevice::new(InMemoryBlockDevice::new(image, BlockSize(512)), &map);
+
+        let scan = scan_for_superblocks(&device)?;
+        assert_eq!(
+            scan.hits
+                .iter()
+                .map(|hit| hit.candidate.location.offset)
+                .collect::<Vec<_>>(),
+            [63 * 512 + 4096, (2 << 20) + 63 * 512 + 4096]
+        );
+        assert_eq!(scan.unreadable, map.unreadable().collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -1,18 +1,28 @@
 use crate::md::algorithm::MdAlgorithm;
-use crate::md::device::MdDeviceId;
-use crate::md::superblock::{ArrayUuid, MdDeviceRole, ReshapeStatus};
-use crate::md::units::{DeviceCount, MetadataEventCount, SectorCount};
+use crate::md::device::{MdDeviceId, MdSuperblockLocation};
+use crate::md::diagnosis::{MdArrayHealth, MdRecommendation};
+use crate::md::raid_hints::MdExt4RaidHints;
+use crate::md::superblock::{
+    ArrayUuid, Features, MdDeviceRole, MdSuperblockVersion, ReshapeStatus,
+};
+use crate::md::units::{DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber};
+use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Diagnosis {
+    pub health: MdArrayHealth,
+    pub missing_member_problem: Option<Vec<DeviceNumber>>,
     pub device_too_small_problem: Option<HashSet<Rc<MdDeviceId>>>,
     pub missing_superblock_problem: Option<HashSet<Rc<MdDeviceId>>>,
+    pub conflicting_superblocks_problem: Option<HashMap<Rc<MdDeviceId>, Vec<MdSuperblockLocation>>>,
     pub array_uuid_problem: Option<HashMap<ArrayUuid, Vec<Rc<MdDeviceId>>>>,
     pub array_name_problem: Option<HashMap<OsString, Vec<Rc<MdDeviceId>>>>,
     pub algorithm_problem: Option<HashMap<MdAlgorithm, Vec<Rc<MdDeviceId>>>>,
+    pub unsupported_algorithm_problem: Option<MdAlgorithm>,
     pub size_problem: Option<HashMap<SectorCount<u64>, Vec<Rc<MdDeviceId>>>>,
     pub chunk_size_problem: Option<HashMap<SectorCount<u32>, Vec<Rc<MdDeviceId>>>>,
     pub device_count_problem: Option<HashMap<DeviceCount, Vec<Rc<MdDeviceId>>>>,
@@ -20,4 +30,286 @@ pub struct Diagnosis {
     pub event_count_problem: Option<HashMap<MetadataEventCount, Vec<Rc<MdDeviceId>>>>,
     pub device_role_index_problem: Option<HashMap<usize, Vec<Rc<MdDeviceId>>>>,
     pub device_roles_problem: Option<HashMap<Vec<MdDeviceRole>, Vec<Rc<MdDeviceId>>>>,
+    pub superblock_version_problem: Option<HashMap<MdSuperblockVersion, Vec<Rc<MdDeviceId>>>>,
+    pub data_offset_problem: Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>>,
+    pub data_size_problem: Option<HashMap<SectorCount<u64>, Vec<Rc<MdDeviceId>>>>,
+    pub super_offset_problem: Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>>,
+    pub features_problem: Option<HashMap<Features, Vec<Rc<MdDeviceId>>>>,
+    pub ext4_raid_hints_problem: Option<HashMap<Rc<MdDeviceId>, MdExt4RaidHints>>,
+
+    /// Where the data starts on each member, when that differs between them.
+    /// mdadm allows this, so it is not a problem in itself.
+    pub data_offsets: Option<HashMap<SectorNumber, Vec<Rc<MdDeviceId>>>>,
+
+    /// Steps towards recovering the array, safest first.
+    pub recommendations: Vec<MdRecommendation>,
+}
+
+/// Names devices in a stable order.
+fn device_list<'a>(ids: impl IntoIterator<Item = &'a Rc<MdDeviceId>>) -> String {
+    ids.into_iter().map(ToString::to_string).sorted().join(", ")
+}
+
+/// Writes a line for each value that the devices disagree on, naming the
+/// devices with each value.
+fn write_values<K, F>(
+    f: &mut Formatter<'_>,
+    summary: &str,
+    map: &HashMap<K, Vec<Rc<MdDeviceId>>>,
+    describe: F,
+) -> std::fmt::Result
+where
+    K: Ord,
+    F: Fn(&K) -> String,
+{
+    writeln!(f, "- {summary}")?;
+    for (value, ids) in map.iter().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+        writeln!(f, "    {}: {}", describe(value), device_list(ids))?;
+    }
+    Ok(())
+}
+
+fn describe_reshape(status: &Option<ReshapeStatus>) -> String {
+    match status {
+        None => "not reshaping".to_string(),
+        Some(status) => format!(
+            "reshaping to {} with chunk size {} and {} more, at {}",
+            status.new_algorithm,
+            status.new_chunk_size,
+            status.delta_devices,
+            status.reshape_position
+        ),
+    }
+}
+
+fn describe_ext4_raid_hints(hints: &MdExt4RaidHints) -> String {
+    match (hints.chunk_size, hints.data_device_count) {
+        (Some(chunk_size), Some(count)) => {
+            format!("a chunk size of {chunk_size} over {count} data devices")
+        }
+        (Some(chunk_size), None) => format!("a chunk size of {chunk_size}"),
+        (None, Some(count)) => format!("{count} data devices"),
+        (None, None) => "no particular geometry".to_string(),
+    }
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Array health: {}", self.health)?;
+        if self.has_problems() {
+            writeln!(f, "Problems:")?;
+            self.write_problems(f)?;
+        } else {
+            writeln!(f, "No problems found.")?;
+        }
+        if let Some(map) = &self.data_offsets {
+            writeln!(f, "Notes:")?;
+            write_values(
+                f,
+                "The data starts at different places on the members:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if !self.recommendations.is_empty() {
+            writeln!(f, "Recommendations, safest first:")?;
+            for recommendation in &self.recommendations {
+                writeln!(f, "- {recommendation}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Diagnosis {
+    fn write_problems(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(device_numbers) = &self.missing_member_problem {
+            writeln!(
+                f,
+                "- No device was found for {}.",
+                device_numbers.iter().sorted().join(", ")
+            )?;
+        }
+        if let Some(ids) = &self.device_too_small_problem {
+            writeln!(
+                f,
+                "- Too small to hold an md superblock: {}.",
+                device_list(ids)
+            )?;
+        }
+        if let Some(ids) = &self.missing_superblock_problem {
+            writeln!(f, "- No md superblock found on {}.", device_list(ids))?;
+        }
+        if let Some(map) = &self.conflicting_superblocks_problem {
+            writeln!(
+                f,
+                "- More than one superblock was found on some devices. The first one found is used."
+            )?;
+            for (id, locations) in map.iter().sorted_by_key(|(id, _)| id.to_string()) {
+                writeln!(f, "    {}: {}", id, locations.iter().join(", "))?;
+            }
+        }
+        if let Some(map) = &self.array_uuid_problem {
+            writeln!(f, "- The devices belong to different arrays:")?;
+            for (uuid, ids) in map.iter().sorted_by_key(|(uuid, _)| uuid.to_string()) {
+                writeln!(f, "    array UUID {}: {}", uuid, device_list(ids))?;
+            }
+        }
+        if let Some(map) = &self.array_name_problem {
+            write_values(f, "The devices disagree on the array name:", map, |name| {
+                name.to_string_lossy().into_owned()
+            })?;
+        }
+        if let Some(map) = &self.algorithm_problem {
+            writeln!(f, "- The devices disagree on the RAID level or layout:")?;
+            for (algorithm, ids) in map
+                .iter()
+                .sorted_by_key(|(algorithm, _)| algorithm.to_string())
+            {
+                writeln!(f, "    {}: {}", algorithm, device_list(ids))?;
+            }
+        }
+        if let Some(algorithm) = &self.unsupported_algorithm_problem {
+            writeln!(
+                f,
+                "- The array is RAID{}, which cannot be read. Only RAID5 and RAID6 arrays are supported.",
+                algorithm.level()
+            )?;
+        }
+        if let Some(map) = &self.size_problem {
+            write_values(
+                f,
+                "The devices disagree on the size of each member:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.chunk_size_problem {
+            write_values(
+                f,
+                "The devices disagree on the chunk size:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.device_count_problem {
+            write_values(
+                f,
+                "The devices disagree on the number of devices in the array:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.reshape_problem {
+            writeln!(
+                f,
+                "- The devices disagree on whether the array is reshaping:"
+            )?;
+            for (status, ids) in map
+                .iter()
+                .sorted_by_key(|(status, _)| describe_reshape(status))
+            {
+                writeln!(f, "    {}: {}", describe_reshape(status), device_list(ids))?;
+            }
+        }
+        if let Some(map) = &self.event_count_problem {
+            writeln!(
+                f,
+                "- The devices disagree on the event count. Those with fewer events have missed updates to the array:"
+            )?;
+            for (count, ids) in map.iter().sorted_by(|(a, _), (b, _)| b.cmp(a)) {
+                writeln!(f, "    {}: {}", count, device_list(ids))?;
+            }
+        }
+        if let Some(map) = &self.device_role_index_problem {
+            write_values(
+                f,
+                "More than one device claims the same place in the array:",
+                map,
+                |index| format!("role index {index}"),
+            )?;
+        }
+        if let Some(map) = &self.device_roles_problem {
+            write_values(
+                f,
+                "The devices disagree on which device plays which role:",
+                map,
+                |roles| roles.iter().join(", "),
+            )?;
+        }
+        if let Some(map) = &self.superblock_version_problem {
+            write_values(
+                f,
+                "The superblocks are of different versions:",
+                map,
+                |version| format!("version {version}"),
+            )?;
+        }
+        if let Some(map) = &self.data_offset_problem {
+            write_values(
+                f,
+                "The data runs past the end of some members, from where their superblocks say it starts:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.data_size_problem {
+            write_values(
+                f,
+                "The data area recorded on some members is smaller than the array uses on each member:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.super_offset_problem {
+            write_values(
+                f,
+                "Some superblocks record a different place than where they were found:",
+                map,
+                |offset| format!("recorded at {offset}"),
+            )?;
+        }
+        if let Some(map) = &self.features_problem {
+            write_values(
+                f,
+                "The devices disagree on the optional features of the array:",
+                map,
+                ToString::to_string,
+            )?;
+        }
+        if let Some(map) = &self.ext4_raid_hints_problem {
+            writeln!(
+                f,
+                "- An ext4 filesystem on the array was created for a different geometry than the superblocks describe:"
+            )?;
+            for (id, hints) in map.iter().sorted_by_key(|(id, _)| id.to_string()) {
+                writeln!(f, "    {}: {}", id, describe_ext4_raid_hints(hints))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_problems(&self) -> bool {
+        self.missing_member_problem.is_some()
+            || self.device_too_small_problem.is_some()
+            || self.missing_superblock_problem.is_some()
+            || self.conflicting_superblocks_problem.is_some()
+            || self.array_uuid_problem.is_some()
+            || self.array_name_problem.is_some()
+            || self.algorithm_problem.is_some()
+            || self.unsupported_algorithm_problem.is_some()
+            || self.size_problem.is_some()
+            || self.chunk_size_problem.is_some()
+            || self.device_count_problem.is_some()
+            || self.reshape_problem.is_some()
+            || self.event_count_problem.is_some()
+            || self.device_role_index_problem.is_some()
+            || self.device_roles_problem.is_some()
+            || self.superblock_version_problem.is_some()
+            || self.data_offset_problem.is_some()
+            || self.data_size_problem.is_some()
+            || self.super_offset_problem.is_some()
+            || self.features_problem.is_some()
+            || self.ext4_raid_hints_problem.is_some()
+    }
 }
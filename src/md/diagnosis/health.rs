@@ -0,0 +1,31 @@
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MdArrayHealth {
+    /// The superblocks agree on the format of the array, and every member is
+    /// present and up to date.
+    Assemblable,
+    /// Some members are missing, or stale with fewer events than the others,
+    /// but no more than parity can make up for.
+    Degraded,
+    /// The superblocks do not agree on a format that can be read, the
+    /// devices belong to different arrays, or more members are missing or
+    /// stale than parity can make up for, so that md needs --force.
+    NotAssemblable,
+}
+
+impl Display for MdArrayHealth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MdArrayHealth::Assemblable => "assemblable",
+                MdArrayHealth::Degraded => "degraded",
+                MdArrayHealth::NotAssemblable => "not assemblable",
+            }
+        )
+    }
+}
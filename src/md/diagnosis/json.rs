@@ -0,0 +1,530 @@
+use crate::block_device::BlockDevice;
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::device::MdSuperblockLocation;
+use crate::md::diagnosis::{Diagnosis, MdAction, MdArrayHealth, MdRecommendation, MdRisk};
+use crate::md::raid_hints::MdExt4RaidHints;
+use crate::md::superblock::{MdDeviceRole, MdSuperblockVersion, ReshapeStatus, Superblock};
+use crate::md::units::{DeviceNumber, Timestamp};
+use crate::md::{MdDevice, MdDeviceId, MdDeviceSuperblock};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The version of the schema of [`MdJsonReport`]. It changes whenever a field
+/// is removed, renamed or changes meaning, but not when a field is added.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// A diagnosis of an array, with the details of the superblock of each of its
+/// devices, to be serialized as JSON. Problems refer to devices by their index
+/// in `devices`, which lists them in the order they were opened. Sizes and
+/// offsets are in sectors of 512 bytes.
+#[derive(Serialize, Debug)]
+pub struct MdJsonReport {
+    schema_version: u32,
+    health: MdArrayHealth,
+    devices: Vec<JsonDevice>,
+    problems: JsonProblems,
+    recommendations: Vec<JsonRecommendation>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonDevice {
+    /// The path the device was opened by, if any.
+    name: Option<String>,
+    superblock_status: JsonSuperblockStatus,
+    superblock_location: Option<JsonLocation>,
+    superblock: Option<JsonSuperblock>,
+
+    /// Every superblock found on the device, including the one used.
+    superblock_candidates: Vec<JsonLocation>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum JsonSuperblockStatus {
+    Present,
+    TooSmall,
+    Missing,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonLocation {
+    format: String,
+    offset_bytes: u64,
+    volume: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonSuperblock {
+    major_version: u32,
+    minor_version: u32,
+    array_uuid: String,
+    array_name: Option<String>,
+    algorithm: JsonAlgorithm,
+    raid_device_count: u32,
+    sectors_per_device: u64,
+    chunk_size_sectors: u32,
+    data_offset_sectors: u64,
+    data_size_sectors: Option<u64>,
+    super_offset_sectors: Option<u64>,
+    /// The raw feature bits of version 1 superblocks.
+    feature_map: Option<u32>,
+    device_role_index: usize,
+    device_roles: Vec<JsonRole>,
+    event_count: u64,
+    creation_time: Option<JsonTimestamp>,
+    update_time: Option<JsonTimestamp>,
+    reshape_status: Option<JsonReshapeStatus>,
+}
+
+/// Since the Unix epoch.
+#[derive(Serialize, Debug)]
+struct JsonTimestamp {
+    seconds: u64,
+    microseconds: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonAlgorithm {
+    level: u32,
+    layout: u32,
+    layout_name: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JsonRole {
+    Active { device_number: u32 },
+    Spare,
+    Faulty,
+    Journal,
+    Invalid,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonReshapeStatus {
+    new_algorithm: JsonAlgorithm,
+    reshape_position_sectors: u64,
+    delta_devices: u32,
+    new_chunk_size_sectors: u32,
+    new_offset: u32,
+}
+
+/// Each problem is `null` if it was not found.
+#[derive(Serialize, Debug)]
+struct JsonProblems {
+    missing_members: Option<Vec<u32>>,
+    device_too_small: Option<Vec<usize>>,
+    missing_superblock: Option<Vec<usize>>,
+    conflicting_superblocks: Option<Vec<JsonConflictingSuperblocks>>,
+    array_uuid: Option<Vec<JsonGroup<String>>>,
+    array_name: Option<Vec<JsonGroup<String>>>,
+    algorithm: Option<Vec<JsonGroup<JsonAlgorithm>>>,
+    unsupported_algorithm: Option<JsonAlgorithm>,
+    sectors_per_device: Option<Vec<JsonGroup<u64>>>,
+    chunk_size_sectors: Option<Vec<JsonGroup<u32>>>,
+    raid_device_count: Option<Vec<JsonGroup<u32>>>,
+    reshape_status: Option<Vec<JsonGroup<Option<JsonReshapeStatus>>>>,
+    event_count: Option<Vec<JsonGroup<u64>>>,
+    device_role_index: Option<Vec<JsonGroup<usize>>>,
+    device_roles: Option<Vec<JsonGroup<Vec<JsonRole>>>>,
+    superblock_version: Option<Vec<JsonGroup<JsonVersion>>>,
+    data_offset_sectors: Option<Vec<JsonGroup<u64>>>,
+    data_size_sectors: Option<Vec<JsonGroup<u64>>>,
+    super_offset_sectors: Option<Vec<JsonGroup<u64>>>,
+    feature_map: Option<Vec<JsonGroup<u32>>>,
+    ext4_raid_hints: Option<Vec<JsonExt4RaidHints>>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonVersion {
+    major: u32,
+    minor: u32,
+}
+
+/// A value that some of the devices agree on.
+#[derive(Serialize, Debug)]
+struct JsonGroup<T> {
+    value: T,
+    devices: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonConflictingSuperblocks {
+    device: usize,
+    locations: Vec<JsonLocation>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonExt4RaidHints {
+    device: usize,
+    chunk_size_sectors: Option<u32>,
+    data_device_count: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonRecommendation {
+    risk: MdRisk,
+    action: JsonAction,
+    /// The action, in plain language.
+    description: String,
+    reason: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JsonAction {
+    Assemble,
+    AssembleDegraded { device_numbers: Vec<u32> },
+    Exclude { devices: Vec<usize> },
+    ChooseArray,
+    Force { devices: Vec<usize> },
+    UseBackupFile,
+    FindMembers { device_numbers: Vec<u32> },
+    InferGeometry,
+}
+
+impl From<&MdSuperblockLocation> for JsonLocation {
+    fn from(location: &MdSuperblockLocation) -> Self {
+        Self {
+            format: location.kind.to_string(),
+            offset_bytes: location.offset,
+            volume: location.volume,
+        }
+    }
+}
+
+impl From<&dyn Superblock> for JsonSuperblock {
+    fn from(superblock: &dyn Superblock) -> Self {
+        Self {
+            major_version: superblock.major_version(),
+            minor_version: superblock.minor_version(),
+            array_uuid: superblock.array_uuid().to_string(),
+            array_name: superblock
+                .array_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            algorithm: (&superblock.algorithm()).into(),
+            raid_device_count: u32::from(superblock.raid_device_count()),
+            sectors_per_device: u64::from(superblock.sectors_per_device()),
+            chunk_size_sectors: u32::from(superblock.chunk_size()),
+            data_offset_sectors: u64::from(superblock.data_offset()),
+            data_size_sectors: superblock.data_size().map(u64::from),
+            super_offset_sectors: superblock.super_offset().map(u64::from),
+            feature_map: superblock.features().map(|features| features.bits()),
+            device_role_index: superblock.device_role_index(),
+            device_roles: superblock.device_roles().iter().map(Into::into).collect(),
+            event_count: u64::from(superblock.event_count()),
+            creation_time: superblock.creation_time().map(Into::into),
+            update_time: superblock.update_time().map(Into::into),
+            reshape_status: superblock.reshape_status().as_ref().map(Into::into),
+        }
+    }
+}
+
+impl From<Timestamp> for JsonTimestamp {
+    fn from(timestamp: Timestamp) -> Self {
+        Self {
+            seconds: timestamp.seconds,
+            microseconds: timestamp.microseconds,
+        }
+    }
+}
+
+impl From<MdSuperblockVersion> for JsonVersion {
+    fn from(version: MdSuperblockVersion) -> Self {
+        Self {
+            major: version.major,
+            minor: version.minor,
+        }
+    }
+}
+
+impl From<&MdAlgorithm> for JsonAlgorithm {
+    fn from(algorithm: &MdAlgorithm) -> Self {
+        Self {
+            level: algorithm.level(),
+            layout: algorithm.layout(),
+            layout_name: algorithm.layout_name(),
+        }
+    }
+}
+
+impl From<&MdDeviceRole> for JsonRole {
+    fn from(role: &MdDeviceRole) -> Self {
+        match role.device_number() {
+            Some(device_number) => JsonRole::Active {
+                device_number: u32::from(device_number),
+            },
+            None if role.is_spare() => JsonRole::Spare,
+            None if role.is_faulty() => JsonRole::Faulty,
+            None if role.is_journal() => JsonRole::Journal,
+            None => JsonRole::Invalid,
+        }
+    }
+}
+
+impl From<&ReshapeStatus> for JsonReshapeStatus {
+    fn from(status: &ReshapeStatus) -> Self {
+        Self {
+            new_algorithm: (&status.new_algorithm).into(),
+            reshape_position_sectors: u64::from(status.reshape_position),
+            delta_devices: u32::from(status.delta_devices),
+            new_chunk_size_sectors: u32::from(status.new_chunk_size),
+            new_offset: status.new_offset,
+        }
+    }
+}
+
+impl From<(usize, &MdExt4RaidHints)> for JsonExt4RaidHints {
+    fn from((device, hints): (usize, &MdExt4RaidHints)) -> Self {
+        Self {
+            device,
+            chunk_size_sectors: hints.chunk_size.map(u32::from),
+            data_device_count: hints.data_device_count.map(u32::from),
+        }
+    }
+}
+
+impl<D: BlockDevice> From<&MdDevice<D>> for JsonDevice {
+    fn from(device: &MdDevice<D>) -> Self {
+        Self {
+            name: device
+                .id
+                .user_reference()
+                .map(|name| name.to_string_lossy().into_owned()),
+            superblock_status: match device.superblock.as_ref() {
+                MdDeviceSuperblock::Superblock(_) => JsonSuperblockStatus::Present,
+                MdDeviceSuperblock::TooSmall => JsonSuperblockStatus::TooSmall,
+                MdDeviceSuperblock::Missing => JsonSuperblockStatus::Missing,
+            },
+            superblock_location: device.superblock_location().as_ref().map(Into::into),
+            superblock: device.superblock.as_option().map(Into::into),
+            superblock_candidates: device
+                .superblock_candidates
+                .iter()
+                .map(|candidate| (&candidate.location).into())
+                .collect(),
+        }
+    }
+}
+
+/// Numbers devices by their place in the report.
+struct DeviceIndex(HashMap<Rc<MdDeviceId>, usize>);
+
+impl DeviceIndex {
+    fn indices<'a>(&self, ids: impl IntoIterator<Item = &'a Rc<MdDeviceId>>) -> Vec<usize> {
+        ids.into_iter()
+            .filter_map(|id| self.0.get(id).copied())
+            .sorted()
+            .collect()
+    }
+
+    fn recommendation(&self, recommendation: &MdRecommendation) -> JsonRecommendation {
+        let device_numbers = |device_numbers: &Vec<DeviceNumber>| {
+            device_numbers
+                .iter()
+                .copied()
+                .map(u32::from)
+                .sorted()
+                .collect()
+        };
+        JsonRecommendation {
+            risk: recommendation.risk,
+            action: match &recommendation.action {
+                MdAction::Assemble => JsonAction::Assemble,
+                MdAction::AssembleDegraded(missing) => JsonAction::AssembleDegraded {
+                    device_numbers: device_numbers(missing),
+                },
+                MdAction::Exclude(ids) => JsonAction::Exclude {
+                    devices: self.indices(ids),
+                },
+                MdAction::ChooseArray => JsonAction::ChooseArray,
+                MdAction::Force(ids) => JsonAction::Force {
+                    devices: self.indices(ids),
+                },
+                MdAction::UseBackupFile => JsonAction::UseBackupFile,
+                MdAction::FindMembers(missing) => JsonAction::FindMembers {
+                    device_numbers: device_numbers(missing),
+                },
+                MdAction::InferGeometry => JsonAction::InferGeometry,
+            },
+            description: recommendation.action.to_string(),
+            reason: recommendation.reason.clone(),
+        }
+    }
+
+    /// Lists the values the devices disagree on, in the order of the first
+    /// device with each.
+    fn groups<K, T>(
+        &self,
+        map: &Option<HashMap<K, Vec<Rc<MdDeviceId>>>>,
+        value: impl Fn(&K) -> T,
+    ) -> Option<Vec<JsonGroup<T>>> {
+        map.as_ref().map(|map| {
+            map.iter()
+                .map(|(key, ids)| JsonGroup {
+                    value: value(key),
+                    devices: self.indices(ids),
+                })
+                .sorted_by(|a, b| a.devices.cmp(&b.devices))
+                .collect()
+        })
+    }
+
+    /// Lists a value for each device, in the order of the devices.
+    fn per_device<V, T>(
+        &self,
+        map: &Option<HashMap<Rc<MdDeviceId>, V>>,
+        value: impl Fn(usize, &V) -> T,
+    ) -> Option<Vec<T>> {
+        map.as_ref().map(|map| {
+            map.iter()
+                .filter_map(|(id, v)| self.0.get(id).map(|&index| (index, v)))
+                .sorted_by_key(|(index, _)| *index)
+                .map(|(index, v)| value(index, v))
+                .collect()
+        })
+    }
+}
+
+impl MdJsonReport {
+    pub fn new<'a, D: BlockDevice + 'a>(
+        diagnosis: &Diagnosis,
+        devices: impl IntoIterator<Item = &'a MdDevice<D>>,
+    ) -> Self {
+        let devices = devices
+            .into_iter()
+            .sorted_by(|a, b| a.id.cmp(&b.id))
+            .collect_vec();
+        let index = DeviceIndex(
+            devices
+                .iter()
+                .enumerate()
+                .map(|(index, device)| (device.id.clone(), index))
+                .collect(),
+        );
+        let problems = JsonProblems {
+            missing_members: diagnosis
+                .missing_member_problem
+                .as_ref()
+                .map(|device_numbers| {
+                    device_numbers
+                        .iter()
+                        .copied()
+                        .map(u32::from)
+                        .sorted()
+                        .collect()
+                }),
+            device_too_small: diagnosis
+                .device_too_small_problem
+                .as_ref()
+                .map(|ids| index.indices(ids)),
+            missing_superblock: diagnosis
+                .missing_superblock_problem
+                .as_ref()
+                .map(|ids| index.indices(ids)),
+            conflicting_superblocks: index.per_device(
+                &diagnosis.conflicting_superblocks_problem,
+                |device, locations| JsonConflictingSuperblocks {
+                    device,
+                    locations: locations.iter().map(Into::into).collect(),
+                },
+            ),
+            array_uuid: index.groups(&diagnosis.array_uuid_problem, ToString::to_string),
+            array_name: index.groups(&diagnosis.array_name_problem, |name| {
+                name.to_string_lossy().into_owned()
+            }),
+            algorithm: index.groups(&diagnosis.algorithm_problem, |algorithm| algorithm.into()),
+            unsupported_algorithm: diagnosis
+                .unsupported_algorithm_problem
+                .as_ref()
+                .map(Into::into),
+            sectors_per_device: index.groups(&diagnosis.size_problem, |&size| u64::from(size)),
+            chunk_size_sectors: index.groups(&diagnosis.chunk_size_problem, |&chunk_size| {
+                u32::from(chunk_size)
+            }),
+            raid_device_count: index
+                .groups(&diagnosis.device_count_problem, |&count| u32::from(count)),
+            reshape_status: index.groups(&diagnosis.reshape_problem, |status| {
+                status.as_ref().map(Into::into)
+            }),
+            event_count: index.groups(&diagnosis.event_count_problem, |&count| u64::from(count)),
+            device_role_index: index.groups(&diagnosis.device_role_index_problem, |&index| index),
+            device_roles: index.groups(&diagnosis.device_roles_problem, |roles| {
+                roles.iter().map(Into::into).collect()
+            }),
+            superblock_version: index.groups(&diagnosis.superblock_version_problem, |&version| {
+                version.into()
+            }),
+            data_offset_sectors: index
+                .groups(&diagnosis.data_offset_problem, |&offset| u64::from(offset)),
+            data_size_sectors: index.groups(&diagnosis.data_size_problem, |&size| u64::from(size)),
+            super_offset_sectors: index
+                .groups(&diagnosis.super_offset_problem, |&offset| u64::from(offset)),
+            feature_map: index.groups(&diagnosis.features_problem, |features| features.bits()),
+            ext4_raid_hints: index
+                .per_device(&diagnosis.ext4_raid_hints_problem, |device, hints| {
+                    (device, hints).into()
+                }),
+        };
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            health: diagnosis.health,
+            devices: devices.into_iter().map(Into::into).collect(),
+            problems,
+            recommendations: diagnosis
+                .recommendations
+                .iter()
+                .map(|recommendation| index.recommendation(recommendation))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::fixture::test::{pseudo_random_data, raid5_fixture};
+    use crate::md::MdArray;
+    use serde_json::json;
+
+    #[test]
+    fn json_report() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 10);
+        let mut devices = raid5_fixture([10; 16]).open(&data);
+        devices.remove(1);
+        let report = serde_json::to_value(MdArray::open(devices).json_report())?;
+
+        assert_eq!(report["schema_version"], json!(1));
+        assert_eq!(report["health"], json!("degraded"));
+        assert_eq!(report["problems"]["missing_members"], json!([1]));
+        assert_eq!(report["problems"]["event_count"], json!(null));
+
+        let devices = report["devices"].as_array().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0]["name"], json!("member0"));
+        assert_eq!(devices[1]["name"], json!("member2"));
+        let superblock = &devices[1]["superblock"];
+        assert_eq!(superblock["array_uuid"], json!("0a".repeat(16)));
+        assert_eq!(
+            superblock["algorithm"],
+            json!({"level": 5, "layout": 2, "layout_name": "left-symmetric"})
+        );
+        assert_eq!(superblock["device_role_index"], json!(2));
+        assert_eq!(
+            superblock["device_roles"],
+            json!([
+                {"kind": "active", "device_number": 0},
+                {"kind": "active", "device_number": 1},
+                {"kind": "active", "device_number": 2},
+            ])
+        );
+        assert_eq!(superblock["reshape_status"], json!(null));
+        assert_eq!(devices[1]["superblock_status"], json!("present"));
+        assert_eq!(
+            devices[1]["superblock_location"],
+            json!({"format": "1.2", "offset_bytes": 4096, "volume": null})
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
 mod diagnosis;
+mod health;
+mod json;
+mod recommendation;
 
 #[allow(unused_imports)]
-pub use diagnosis::Diagnosis;
+pub use self::{
+    diagnosis::Diagnosis,
+    health::MdArrayHealth,
+    json::{MdJsonReport, JSON_SCHEMA_VERSION},
+    recommendation::{MdAction, MdRecommendation, MdRisk},
+};
@@ -0,0 +1,126 @@
+use crate::md::units::DeviceNumber;
+use crate::md::MdDeviceId;
+use itertools::Itertools;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// How likely a step is to lose data if the diagnosis behind it is wrong.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MdRisk {
+    /// Nothing is written, or the array keeps full redundancy.
+    Safe,
+    /// The array keeps some redundancy.
+    Low,
+    /// The array is left without redundancy, so a single read error loses
+    /// data.
+    Medium,
+    /// Data that was written after some devices dropped out may be lost or
+    /// corrupted.
+    High,
+}
+
+impl Display for MdRisk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MdRisk::Safe => "safe",
+                MdRisk::Low => "low risk",
+                MdRisk::Medium => "medium risk",
+                MdRisk::High => "high risk",
+            }
+        )
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum MdAction {
+    /// Assemble the array from every device found.
+    Assemble,
+    /// Assemble the array without the given members, relying on parity.
+    AssembleDegraded(Vec<DeviceNumber>),
+    /// Leave the given devices out when assembling.
+    Exclude(Vec<Rc<MdDeviceId>>),
+    /// Decide which of the arrays the devices belong to is to be recovered.
+    ChooseArray,
+    /// Assemble with `mdadm --assemble --force`, which brings the event count
+    /// of the given stale devices up to date.
+    Force(Vec<Rc<MdDeviceId>>),
+    /// Assemble with `mdadm --assemble --backup-file`, to resume a reshape.
+    UseBackupFile,
+    /// Find the devices holding the given members.
+    FindMembers(Vec<DeviceNumber>),
+    /// Work out the geometry of the array from its data, with `--search` or
+    /// `--parity`.
+    InferGeometry,
+}
+
+/// Names devices in a stable order.
+fn device_list(ids: &[Rc<MdDeviceId>]) -> String {
+    ids.iter().map(ToString::to_string).sorted().join(", ")
+}
+
+impl Display for MdAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MdAction::Assemble => write!(f, "Assemble the array."),
+            MdAction::AssembleDegraded(device_numbers) => write!(
+                f,
+                "Assemble the array without {}.",
+                device_numbers.iter().sorted().join(", ")
+            ),
+            MdAction::Exclude(ids) => {
+                write!(f, "Leave out {} when assembling.", device_list(ids))
+            }
+            MdAction::ChooseArray => write!(
+                f,
+                "Decide which array to recover, and diagnose its devices on their own."
+            ),
+            MdAction::Force(ids) => write!(
+                f,
+                "Assemble with --force, which marks {} as up to date.",
+                device_list(ids)
+            ),
+            MdAction::UseBackupFile => write!(
+                f,
+                "Assemble with --backup-file, naming the backup file the reshape was started with."
+            ),
+            MdAction::FindMembers(device_numbers) => write!(
+                f,
+                "Find the devices for {}.",
+                device_numbers.iter().sorted().join(", ")
+            ),
+            MdAction::InferGeometry => write!(
+                f,
+                "Run with --search or --parity to find which geometry fits the data, before assembling."
+            ),
+        }
+    }
+}
+
+/// A step towards recovering the array, and why it is suggested.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdRecommendation {
+    pub risk: MdRisk,
+    pub action: MdAction,
+    pub reason: String,
+}
+
+impl MdRecommendation {
+    pub fn new(risk: MdRisk, action: MdAction, reason: impl Into<String>) -> Self {
+        Self {
+            risk,
+            action,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for MdRecommendation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {} {}", self.risk, self.action, self.reason)
+    }
+}
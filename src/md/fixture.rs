@@ -0,0 +1,677 @@
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::format::MdFormat;
+use crate::md::raid6::q_syndrome;
+use crate::md::superblock::{
+    MdDeviceRole, NewSuperblock, ReshapeStatus, SuperblockMut, SuperblockVersion0,
+    SuperblockVersion1,
+};
+use crate::md::units::{
+    DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
+};
+use crate::md::MdSuperblockKind;
+use itertools::Itertools;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The superblocks that md keeps for an array itself, which a synthetic
+/// array can be given, unlike external metadata such as IMSM and DDF.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum MdFixtureSuperblock {
+    Version0_90,
+    Version1_0,
+    Version1_1,
+    Version1_2,
+}
+
+impl MdFixtureSuperblock {
+    /// Where mdadm puts the data by default, leaving room for the
+    /// superblock and the bitmap before it with versions 1.1 and 1.2.
+    pub fn default_data_offset(&self) -> SectorNumber {
+        match self {
+            MdFixtureSuperblock::Version0_90 | MdFixtureSuperblock::Version1_0 => SectorNumber(0),
+            MdFixtureSuperblock::Version1_1 | MdFixtureSuperblock::Version1_2 => SectorNumber(2048),
+        }
+    }
+
+    /// Whether the data can start at `data_offset`, clear of the superblock,
+    /// which version 1.1 has in the first 8 sectors, and version 1.2 in the
+    /// first 16. Version 0.90 has the data at the start of each member.
+    pub fn allows_data_offset(&self, data_offset: SectorNumber) -> bool {
+        let data_offset = u64::from(data_offset);
+        match self {
+            MdFixtureSuperblock::Version0_90 => data_offset == 0,
+            MdFixtureSuperblock::Version1_0 => true,
+            MdFixtureSuperblock::Version1_1 => data_offset >= 8,
+            MdFixtureSuperblock::Version1_2 => data_offset >= 16,
+        }
+    }
+}
+
+impl From<MdFixtureSuperblock> for MdSuperblockKind {
+    fn from(superblock: MdFixtureSuperblock) -> Self {
+        match superblock {
+            MdFixtureSuperblock::Version0_90 => MdSuperblockKind::Version0_90,
+            MdFixtureSuperblock::Version1_0 => MdSuperblockKind::Version1_0,
+            MdFixtureSuperblock::Version1_1 => MdSuperblockKind::Version1_1,
+            MdFixtureSuperblock::Version1_2 => MdSuperblockKind::Version1_2,
+        }
+    }
+}
+
+impl Display for MdFixtureSuperblock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", MdSuperblockKind::from(*self))
+    }
+}
+
+impl FromStr for MdFixtureSuperblock {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse()? {
+            MdSuperblockKind::Version0_90 => Ok(MdFixtureSuperblock::Version0_90),
+            MdSuperblockKind::Version1_0 => Ok(MdFixtureSuperblock::Version1_0),
+            MdSuperblockKind::Version1_1 => Ok(MdFixtureSuperblock::Version1_1),
+            MdSuperblockKind::Version1_2 => Ok(MdFixtureSuperblock::Version1_2),
+            kind @ (MdSuperblockKind::Imsm | MdSuperblockKind::Ddf) => {
+                Err(format!("cannot generate {kind} superblocks"))
+            }
+        }
+    }
+}
+
+/// A failure to build into the members of a synthetic array.
+#[derive(Clone, Debug)]
+pub enum MdFixtureFailure {
+    /// The member is left out.
+    MissingMember(DeviceNumber),
+
+    /// The superblock of the member has the given event count in place of
+    /// that of the others.
+    StaleEvents(DeviceNumber, MetadataEventCount),
+
+    /// The given chunk of the member, counted from its data offset, has
+    /// every bit flipped, so that the parity of its stripe no longer
+    /// matches.
+    CorruptedChunk(DeviceNumber, u64),
+
+    /// The array was being reshaped to `new_format` when it stopped at
+    /// `reshape_position`, in sectors of the array. The data below it is
+    /// laid out in the new format, and the rest in the old. The new format
+    /// must have at least as many data devices as the old, and the position
+    /// must fall at the end of a stripe in both.
+    InterruptedReshape {
+        new_format: MdFormat,
+        reshape_position: SectorNumber,
+    },
+}
+
+/// The geometry of a synthetic array, laid out over member images as md
+/// would, for end-to-end tests and for practice with recovery tools.
+#[derive(Clone, Debug)]
+pub struct MdArrayFixture {
+    pub algorithm: MdAlgorithm,
+    pub device_count: u32,
+    pub chunk_size: u32,
+
+    /// The kind of superblock written to every member, if any.
+    pub superblock: Option<MdFixtureSuperblock>,
+
+    /// Must leave room for the superblock, as
+    /// [`MdFixtureSuperblock::allows_data_offset`] tells.
+    pub data_offset: SectorNumber,
+    pub array_uuid: [u8; 16],
+    pub event_count: MetadataEventCount,
+    pub failures: Vec<MdFixtureFailure>,
+}
+
+impl MdArrayFixture {
+    /// An array with v1.2 superblocks, and its data 8KiB into each member.
+    pub fn new(algorithm: MdAlgorithm, device_count: u32, chunk_size: u32) -> Self {
+        Self {
+            algorithm,
+            device_count,
+            chunk_size,
+            superblock: Some(MdFixtureSuperblock::Version1_2),
+            data_offset: SectorNumber(16),
+            array_uuid: [0x50; 16],
+            event_count: MetadataEventCount(20),
+            failures: Vec::new(),
+        }
+    }
+
+    fn data_device_count(&self) -> io::Result<u64> {
+        let parity_device_count = self
+            .algorithm
+            .parity_device_count()
+            .ok_or(io::ErrorKind::Unsupported)?;
+        Ok(u64::from(
+            self.device_count
+                .checked_sub(u32::from(parity_device_count))
+                .filter(|&count| count > 0)
+                .ok_or(io::ErrorKind::InvalidInput)?,
+        ))
+    }
+
+    /// The size of a stripe in bytes, of which the data must be a whole
+    /// number.
+    pub fn stripe_size(&self) -> io::Result<u64> {
+        let stripe_size = self.data_device_count()? * u64::from(self.chunk_size) * 512;
+        if stripe_size == 0 {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        Ok(stripe_size)
+    }
+
+    /// The format md reads the array with, before any reshape.
+    pub fn format(&self, data: &[u8]) -> io::Result<MdFormat> {
+        Ok(MdFormat {
+            algorithm: self.algorithm.clone(),
+            device_count: DeviceCount(self.device_count),
+            sectors_per_device: SectorCount(data.len() as u64 / 512 / self.data_device_count()?),
+            chunk_size: SectorCount(self.chunk_size),
+        })
+    }
+
+    /// Lays out `data`, which must fill a whole number of stripes, over the
+    /// members, with `None` in place of each missing member.
+    pub fn member_images(&self, data: &[u8]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let format = self.format(data)?;
+        let sectors_per_device = u64::from(format.sectors_per_device);
+        let data_offset = u64::from(self.data_offset);
+        let reshape = self.failures.iter().find_map(|failure| match failure {
+            MdFixtureFailure::InterruptedReshape {
+                new_format,
+                reshape_position,
+            } => Some((new_format, *reshape_position)),
+            _ => None,
+        });
+
+        let mut members = member_images(
+            data,
+            &self.algorithm,
+            self.device_count,
+            self.chunk_size,
+            data_offset,
+        )?;
+        let mut reshape_status = None;
+        if let Some((new_format, reshape_position)) = reshape {
+            let new_data_device_count = u64::from(
+                new_format
+                    .data_device_count()
+                    .ok_or(io::ErrorKind::InvalidInput)?,
+            );
+            let reshape_position = u64::from(reshape_position);
+            if new_data_device_count < self.data_device_count()?
+                || [
+                    (self.data_device_count()?, self.chunk_size),
+                    (new_data_device_count, u32::from(new_format.chunk_size)),
+                ]
+                .into_iter()
+                .any(|(data_device_count, chunk_size)| {
+                    !reshape_position.is_multiple_of(data_device_count * u64::from(chunk_size))
+                })
+            {
+                Err(io::ErrorKind::InvalidInput)?;
+            }
+
+            // md moves the data stripe by stripe from the old layout to the
+            // new one, which takes up no more of each member than the old
+            // did, leaving the rest of the old as it was.
+            let mut new_data = data.to_vec();
+            new_data.resize(
+                (sectors_per_device * new_data_device_count * 512) as usize,
+                0,
+            );
+            let new_members = member_images(
+                &new_data,
+                &new_format.algorithm,
+                u32::from(new_format.device_count),
+                u32::from(new_format.chunk_size),
+                data_offset,
+            )?;
+            members.resize_with(new_members.len().max(members.len()), || {
+                vec![0u8; ((data_offset + sectors_per_device) * 512) as usize]
+            });
+            let reshaped_length =
+                ((data_offset + reshape_position / new_data_device_count) * 512) as usize;
+            for (member, new_member) in members.iter_mut().zip(&new_members) {
+                member[..reshaped_length].copy_from_slice(&new_member[..reshaped_length]);
+            }
+            reshape_status = Some(ReshapeStatus {
+                new_algorithm: new_format.algorithm.clone(),
+                reshape_position: SectorNumber(reshape_position),
+                delta_devices: DeviceCount(
+                    u32::from(new_format.device_count)
+                        .checked_sub(self.device_count)
+                        .ok_or(io::ErrorKind::InvalidInput)?,
+                ),
+                new_chunk_size: new_format.chunk_size,
+                new_offset: 0,
+            });
+        }
+
+        for failure in &self.failures {
+            if let MdFixtureFailure::CorruptedChunk(device_number, chunk) = failure {
+                let chunk_size = u64::from(self.chunk_size);
+                let offset = ((data_offset + chunk * chunk_size) * 512) as usize;
+                let member = members
+                    .get_mut(usize::from(*device_number))
+                    .ok_or(io::ErrorKind::InvalidInput)?;
+                for byte in member
+                    .get_mut(offset..offset + (chunk_size * 512) as usize)
+                    .ok_or(io::ErrorKind::InvalidInput)?
+                {
+                    *byte = !*byte;
+                }
+            }
+        }
+
+        let device_roles = (0..members.len() as u32)
+            .map(|device_number| MdDeviceRole::from_device_number(DeviceNumber(device_number)))
+            .collect::<Vec<_>>();
+        (0..)
+            .map(DeviceNumber)
+            .zip(members)
+            .map(|(device_number, member)| {
+                let missing = self.failures.iter().any(|failure| match failure {
+                    MdFixtureFailure::MissingMember(missing) => *missing == device_number,
+                    _ => false,
+                });
+                if missing {
+                    return Ok(None);
+                }
+                let event_count = self
+                    .failures
+                    .iter()
+                    .find_map(|failure| match failure {
+                        MdFixtureFailure::StaleEvents(stale, event_count)
+                            if *stale == device_number =>
+                        {
+                            Some(*event_count)
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or(self.event_count);
+                let new = NewSuperblock {
+                    array_uuid: self.array_uuid,
+                    array_name: b"fixture:data".to_vec(),
+                    format: format.clone(),
+                    creation_time: Timestamp {
+                        seconds: 1_700_000_000,
+                        microseconds: 0,
+                    },
+                    update_time: Timestamp {
+                        seconds: 1_700_000_000 + u64::from(event_count),
+                        microseconds: 0,
+                    },
+                    event_count,
+                    reshape_status: reshape_status.clone(),
+                    device_roles: device_roles.clone(),
+                    device_role_index: usize::from(device_number),
+                    device_uuid: [u32::from(device_number) as u8; 16],
+                    data_offset: self.data_offset,
+                    data_size: format.sectors_per_device,
+                    super_offset: SectorNumber(0),
+                };
+                self.add_superblock(member, new).map(Some)
+            })
+            .collect()
+    }
+
+    /// Writes the superblock where md looks for one of its kind, growing
+    /// the member to make room at the end for versions 1.0 and 0.90.
+    fn add_superblock(&self, mut member: Vec<u8>, mut new: NewSuperblock) -> io::Result<Vec<u8>> {
+        if self
+            .superblock
+            .is_some_and(|superblock| !superblock.allows_data_offset(self.data_offset))
+        {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        let (offset, superblock) = match self.superblock {
+            None => return Ok(member),
+            Some(MdFixtureSuperblock::Version0_90) => {
+                let offset = member.len().next_multiple_of(65536);
+                member.resize(offset + 65536, 0);
+                let superblock = SuperblockVersion0::create(&new, false)?;
+                (offset, superblock.to_bytes())
+            }
+            Some(MdFixtureSuperblock::Version1_0) => {
+                let offset = member.len().next_multiple_of(4096);
+                new.super_offset = SectorNumber(offset as u64 / 512);
+                member.resize(offset + 8192, 0);
+                let superblock = SuperblockVersion1::create(&new, 0)?;
+                (offset, superblock.to_bytes())
+            }
+            Some(MdFixtureSuperblock::Version1_1) => {
+                let superblock = SuperblockVersion1::create(&new, 1)?;
+                (0, superblock.to_bytes())
+            }
+            Some(MdFixtureSuperblock::Version1_2) => {
+                new.super_offset = SectorNumber(8);
+                let superblock = SuperblockVersion1::create(&new, 2)?;
+                (4096, superblock.to_bytes())
+            }
+        };
+        member[offset..][..superblock.len()].copy_from_slice(&superblock);
+        Ok(member)
+    }
+
+    /// Writes the member images that are present to `directory`, as
+    /// `member{N}.img` after their role, and returns their paths.
+    pub fn write_member_images(&self, data: &[u8], directory: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::create_dir_all(directory)?;
+        self.member_images(data)?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, image)| Some((index, image?)))
+            .map(|(index, image)| {
+                let path = directory.join(format!("member{index}.img"));
+                fs::write(&path, image)?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+/// Lays out `data` over the members of an array without superblocks,
+/// computing P and, for RAID6, Q for every row.
+pub(in crate::md) fn member_images(
+    data: &[u8],
+    algorithm: &MdAlgorithm,
+    device_count: u32,
+    chunk_size: u32,
+    data_offset: u64,
+) -> io::Result<Vec<Vec<u8>>> {
+    let data_device_count = u64::from(
+        device_count
+            .checked_sub(u32::from(
+                algorithm
+                    .parity_device_count()
+                    .ok_or(io::ErrorKind::Unsupported)?,
+            ))
+            .filter(|&count| count > 0)
+            .ok_or(io::ErrorKind::InvalidInput)?,
+    );
+    let stripe_size = data_device_count * u64::from(chunk_size) * 512;
+    if stripe_size == 0 || !(data.len() as u64).is_multiple_of(stripe_size) {
+        Err(io::ErrorKind::InvalidInput)?;
+    }
+    let sectors_per_device = (data.len() / 512) as u64 / data_device_count;
+    let mut images =
+        vec![vec![0u8; ((data_offset + sectors_per_device) * 512) as usize]; device_count as usize];
+    for sector_in_device in 0..sectors_per_device {
+        let stripe = sector_in_device / u64::from(chunk_size);
+        let offset = ((data_offset + sector_in_device) * 512) as usize;
+        let locations = (0..data_device_count)
+            .map(|data_index| {
+                let sector_number = (stripe * data_device_count + data_index)
+                    * u64::from(chunk_size)
+                    + sector_in_device % u64::from(chunk_size);
+                let location = algorithm
+                    .compute_sector(
+                        SectorNumber(sector_number),
+                        SectorCount(chunk_size),
+                        DeviceCount(device_count),
+                    )
+                    .ok_or(io::ErrorKind::InvalidInput)?;
+                images[usize::from(location.data_device_number)][offset..][..512]
+                    .copy_from_slice(&data[sector_number as usize * 512..][..512]);
+                Ok(location)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let blocks = locations
+            .iter()
+            .map(|location| &images[usize::from(location.data_device_number)][offset..][..512])
+            .collect_vec();
+        let p = blocks.iter().fold(vec![0u8; 512], |p, block| {
+            p.iter().zip(block.iter()).map(|(a, b)| a ^ b).collect()
+        });
+        let q = q_syndrome(&blocks);
+        images[usize::from(locations[0].p_device_number)][offset..][..512].copy_from_slice(&p);
+        if let Some(q_device_number) = locations[0].q_device_number {
+            images[usize::from(q_device_number)][offset..][..512].copy_from_slice(&q);
+        }
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+pub(in crate::md) mod test {
+    use super::{MdArrayFixture, MdFixtureFailure, MdFixtureSuperblock};
+    use crate::block_device::{BlockDeviceReader, BlockSize, InMemoryBlockDevice};
+    use crate::ext::ReadAll;
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::diagnosis::MdArrayHealth;
+    use crate::md::format::MdFormat;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::superblock::SuperblockVersion1;
+    use crate::md::units::{
+        DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber,
+    };
+    use crate::md::{MdArray, MdDevice};
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::fs;
+
+    pub(in crate::md) const DEVICE_COUNT: u32 = 3;
+    pub(in crate::md) const CHUNK_SIZE: u32 = 8;
+    pub(in crate::md) const DATA_OFFSET: u64 = 16;
+
+    pub(in crate::md) fn pseudo_random_data(length: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// A three-device left-symmetric RAID5 array of `array_uuid`, with v1.2
+    /// superblocks.
+    pub(in crate::md) fn raid5_fixture(array_uuid: [u8; 16]) -> MdArrayFixture {
+        MdArrayFixture {
+            array_uuid,
+            ..MdArrayFixture::new(
+                MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric),
+                DEVICE_COUNT,
+                CHUNK_SIZE,
+            )
+        }
+    }
+
+    /// Changes the v1.2 superblock of a member image, and updates its checksum.
+    pub(in crate::md) fn update_superblock(image: &mut [u8], update: impl FnOnce(&mut [u8])) {
+        let superblock = &mut image[4096..8192];
+        update(superblock);
+        let checksum = SuperblockVersion1::new(&*superblock, 2).expected_checksum();
+        LittleEndian::write_u32(&mut superblock[216..], checksum);
+    }
+
+    /// Opens member images named after their slot, leaving out those given
+    /// as `None`.
+    pub(in crate::md) fn open_member_images<I: Into<Option<Vec<u8>>>>(
+        images: impl IntoIterator<Item = I>,
+    ) -> Vec<MdDevice<InMemoryBlockDevice>> {
+        images
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, image)| {
+                Some(
+                    MdDevice::from_block_device(
+                        InMemoryBlockDevice::new(image.into()?, BlockSize(512)),
+                        Some(format!("member{index}")),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    impl MdArrayFixture {
+        /// Opens the member images that are present, named `member{N}` after
+        /// their role.
+        pub fn open(&self, data: &[u8]) -> Vec<MdDevice<InMemoryBlockDevice>> {
+            open_member_images(self.member_images(data).unwrap())
+        }
+
+        /// The images of the members that are present.
+        pub fn present_member_images(&self, data: &[u8]) -> Vec<Vec<u8>> {
+            self.member_images(data)
+                .unwrap()
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+    }
+
+    #[test]
+    fn read_every_layout_and_superblock_version() -> anyhow::Result<()> {
+        let data = pseudo_random_data(3 * 4 * 4096, 50);
+        // Only RAID5 and RAID6 arrays can be read so far.
+        let raid5_algorithms = [
+            Raid5Algorithm::LeftAsymmetric,
+            Raid5Algorithm::RightAsymmetric,
+            Raid5Algorithm::LeftSymmetric,
+            Raid5Algorithm::RightSymmetric,
+            Raid5Algorithm::Parity0,
+            Raid5Algorithm::ParityN,
+        ];
+        let raid6_algorithms = [
+            Raid6Algorithm::LeftAsymmetric,
+            Raid6Algorithm::RightAsymmetric,
+            Raid6Algorithm::LeftSymmetric,
+            Raid6Algorithm::RightSymmetric,
+            Raid6Algorithm::Parity0,
+            Raid6Algorithm::ParityN,
+            Raid6Algorithm::Rotating0Restart,
+            Raid6Algorithm::RotatingNRestart,
+            Raid6Algorithm::RotatingNContinue,
+            Raid6Algorithm::LeftAsymmetric6,
+            Raid6Algorithm::RightAsymmetric6,
+            Raid6Algorithm::LeftSymmetric6,
+            Raid6Algorithm::RightSymmetric6,
+            Raid6Algorithm::Parity06,
+        ];
+        let algorithms = raid5_algorithms
+            .into_iter()
+            .map(MdAlgorithm::Raid5)
+            .chain(raid6_algorithms.into_iter().map(MdAlgorithm::Raid6));
+        for algorithm in algorithms {
+            let device_count = 3 + u32::from(algorithm.parity_device_count().unwrap());
+            for (superblock, data_offset) in [
+                (MdFixtureSuperblock::Version0_90, 0),
+                (MdFixtureSuperblock::Version1_0, 0),
+                (MdFixtureSuperblock::Version1_1, 8),
+                (MdFixtureSuperblock::Version1_2, 2048),
+            ] {
+                let fixture = MdArrayFixture {
+                    superblock: Some(superblock),
+                    data_offset: SectorNumber(data_offset),
+                    ..MdArrayFixture::new(algorithm.clone(), device_count, 8)
+                };
+                let array = MdArray::open(fixture.open(&data));
+                assert_eq!(array.diagnose().health, MdArrayHealth::Assemblable);
+                assert_eq!(array.format(), Some(&fixture.format(&data)?));
+                assert!(array.scrub()?.mismatches.is_empty());
+                assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn diagnose_injected_failures() -> anyhow::Result<()> {
+        let data = pseudo_random_data(2 * 8 * 4096, 51);
+        let fixture = MdArrayFixture::new(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric), 3, 8);
+        let with_failure = |failure| MdArrayFixture {
+            failures: vec![failure],
+            ..fixture.clone()
+        };
+
+        let fixture = with_failure(MdFixtureFailure::MissingMember(DeviceNumber(1)));
+        let array = MdArray::open(fixture.open(&data));
+        let diagnosis = array.diagnose();
+        assert_eq!(diagnosis.health, MdArrayHealth::Degraded);
+        assert_eq!(
+            diagnosis.missing_member_problem,
+            Some(vec![DeviceNumber(1)])
+        );
+        assert_eq!(BlockDeviceReader::new(array).read_all()?, data);
+
+        let fixture = with_failure(MdFixtureFailure::StaleEvents(
+            DeviceNumber(2),
+            MetadataEventCount(15),
+        ));
+        let problem = MdArray::open(fixture.open(&data))
+            .diagnose()
+            .event_count_problem
+            .unwrap();
+        assert_eq!(problem[&MetadataEventCount(15)].len(), 1);
+        assert_eq!(problem[&MetadataEventCount(20)].len(), 2);
+
+        let fixture = with_failure(MdFixtureFailure::CorruptedChunk(DeviceNumber(0), 3));
+        let report = MdArray::open(fixture.open(&data)).scrub()?;
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(
+            report.mismatches[0].member_sectors,
+            SectorNumber(24)..SectorNumber(32)
+        );
+
+        // Grown to four devices, with the first two stripes of the new
+        // layout written.
+        let new_format = MdFormat {
+            device_count: DeviceCount(4),
+            chunk_size: SectorCount(16),
+            ..fixture.format(&data)?
+        };
+        let fixture = with_failure(MdFixtureFailure::InterruptedReshape {
+            new_format: new_format.clone(),
+            reshape_position: SectorNumber(96),
+        });
+        let array = MdArray::open(fixture.open(&data));
+        assert!(array.diagnose().reshape_problem.is_none());
+        assert_eq!(
+            array.format().map(|format| format.device_count),
+            Some(DeviceCount(4))
+        );
+        let mut reshaped = BlockDeviceReader::new(array).read_all()?;
+        reshaped.truncate(96 * 512);
+        assert_eq!(reshaped, data[..96 * 512]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_member_images() -> anyhow::Result<()> {
+        let directory =
+            std::env::temp_dir().join(format!("md-recover-fixture-{}", std::process::id()));
+        let data = pseudo_random_data(2 * 8 * 4096, 52);
+        let fixture = MdArrayFixture {
+            failures: vec![MdFixtureFailure::MissingMember(DeviceNumber(1))],
+            ..MdArrayFixture::new(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric), 3, 8)
+        };
+        let paths = fixture.write_member_images(&data, &directory)?;
+        assert_eq!(
+            paths,
+            vec![directory.join("member0.img"), directory.join("member2.img")]
+        );
+        let images = fixture.member_images(&data)?;
+        assert_eq!(fs::read(&paths[0])?, images[0].clone().unwrap());
+        assert_eq!(fs::read(&paths[1])?, images[2].clone().unwrap());
+        fs::remove_dir_all(&directory)?;
+
+        // Data that does not fill a whole number of stripes is refused.
+        assert!(fixture.member_images(&data[..4096]).is_err());
+        let fixture = MdArrayFixture {
+            superblock: Some(MdFixtureSuperblock::Version0_90),
+            ..fixture
+        };
+        assert!(fixture.member_images(&data).is_err());
+        assert!("imsm".parse::<MdFixtureSuperblock>().is_err());
+        Ok(())
+    }
+}
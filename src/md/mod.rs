@@ -1,16 +1,40 @@
 mod algorithm;
 mod array;
+mod conf;
+mod create_command;
 mod definition;
 mod device;
 mod diagnosis;
+mod fixture;
 mod format;
 mod raid5;
 mod raid6;
+mod raid_hints;
+mod rebuild;
+mod repair;
+mod scrub;
+mod search;
 pub mod superblock;
+mod timeline;
 mod units;
 
 #[allow(unused_imports)]
 pub use self::{
+    algorithm::MdAlgorithm,
     array::MdArray,
-    device::{MdDevice, MdDeviceId, MdDeviceSuperblock},
+    conf::MdConfArray,
+    create_command::MdCreateCommand,
+    device::{scan_for_superblocks, MdDevice, MdDeviceId, MdDeviceSuperblock, MdSuperblockKind},
+    diagnosis::{MdJsonReport, JSON_SCHEMA_VERSION},
+    fixture::{MdArrayFixture, MdFixtureFailure, MdFixtureSuperblock},
+    format::MdFormat,
+    raid_hints::MdExt4RaidHints,
+    repair::{MdRepairedSuperblock, MdSuperblockRepair},
+    scrub::{MdScrubMismatch, MdScrubReport, MdScrubUnreadable},
+    search::{
+        MdChunkSizeAnalysis, MdChunkSizeEstimate, MdGeometry, MdGeometryCandidate,
+        MdGeometrySearch, MdParityCandidate, MdParityInference, MdParityReport,
+    },
+    timeline::{MdTimeline, MdTimelineUpdate},
+    units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber},
 };
@@ -1,4 +1,5 @@
 use crate::md::units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber};
+use std::io;
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub enum Raid5Algorithm {
@@ -29,6 +30,17 @@ impl Raid5Algorithm {
         }
     }
 
+    pub fn layout(&self) -> u32 {
+        match self {
+            Self::LeftAsymmetric => 0,
+            Self::RightAsymmetric => 1,
+            Self::LeftSymmetric => 2,
+            Self::RightSymmetric => 3,
+            Self::Parity0 => 4,
+            Self::ParityN => 5,
+        }
+    }
+
     pub fn compute_sector(
         &self,
         sector_number: SectorNumber,
@@ -87,4 +99,44 @@ impl Raid5Algorithm {
 
         Some((sector_in_device, parity_device_number, data_device_number))
     }
+
+    pub(in crate::md) fn read_sector<F>(
+        &self,
+        sector_number: SectorNumber,
+        sectors_per_chunk: SectorCount<u32>,
+        raid_device_count: DeviceCount,
+        mut read_sector_of_device: F,
+    ) -> io::Result<Vec<u8>>
+    where
+        F: FnMut(DeviceNumber, SectorNumber, &mut [u8]) -> io::Result<usize>,
+    {
+        let (sector_in_device, _, data_device_number) = self
+            .compute_sector(sector_number, sectors_per_chunk, raid_device_count)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+
+        // The data is taken as read, as md does: checking it against parity
+        // is left to scrub.
+        let mut data = vec![0; 512];
+        let error = match read_sector_of_device(data_device_number, sector_in_device, &mut data) {
+            Ok(length) if length == data.len() => return Ok(data),
+            Ok(_) => io::Error::from(io::ErrorKind::InvalidData),
+            Err(error) => error,
+        };
+
+        // A single member that cannot be read, such as one that is missing,
+        // can be recovered from the others.
+        (0..u32::from(raid_device_count))
+            .map(DeviceNumber)
+            .filter(|&device_number| device_number != data_device_number)
+            .try_fold(vec![0; 512], |acc, device_number| {
+                let mut buf = vec![0; 512];
+                match read_sector_of_device(device_number, sector_in_device, &mut buf) {
+                    Ok(length) if length == buf.len() => {
+                        Some(acc.iter().zip(buf).map(|(a, b)| a ^ b).collect())
+                    }
+                    _ => None,
+                }
+            })
+            .ok_or(error)
+    }
 }
@@ -1,5 +1,5 @@
+use crate::md::raid6::{gf_inv, gf_mul, gf_pow, q_syndrome};
 use crate::md::units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber};
-use itertools::Itertools;
 use std::io;
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
@@ -55,6 +55,25 @@ impl Raid6Algorithm {
         }
     }
 
+    pub fn layout(&self) -> u32 {
+        match self {
+            Self::LeftAsymmetric => 0,
+            Self::RightAsymmetric => 1,
+            Self::LeftSymmetric => 2,
+            Self::RightSymmetric => 3,
+            Self::Parity0 => 4,
+            Self::ParityN => 5,
+            Self::Rotating0Restart => 8,
+            Self::RotatingNRestart => 9,
+            Self::RotatingNContinue => 10,
+            Self::LeftAsymmetric6 => 16,
+            Self::RightAsymmetric6 => 17,
+            Self::LeftSymmetric6 => 18,
+            Self::RightSymmetric6 => 19,
+            Self::Parity06 => 20,
+        }
+    }
+
     pub fn compute_sector(
         &self,
         sector_number: SectorNumber,
@@ -204,45 +223,76 @@ impl Raid6Algorithm {
         let (sector_in_device, p_device_number, q_device_number, data_device_number) = self
             .compute_sector(sector_number, sectors_per_chunk, raid_device_count)
             .ok_or(io::ErrorKind::InvalidInput)?;
-        // TODO: Recover from read errors
-        let buffers = (0..u32::from(raid_device_count))
-            .map(DeviceNumber)
-            .map(|device_number| {
-                let mut buf = vec![0; 512];
-                if read_sector_of_device(device_number, sector_in_device, &mut buf)? != buf.len() {
-                    Err(io::Error::from(io::ErrorKind::InvalidData))
-                } else {
-                    Ok(buf)
-                }
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-        let p_buffer = buffers
-            .get(usize::from(p_device_number))
-            .ok_or(io::ErrorKind::InvalidData)?;
-        // TODO: Recover data using q buffer
-        // let q_buffer = buffers
-        //     .get(usize::from(q_device_number))
-        //     .ok_or(io::ErrorKind::InvalidData)?;
-        let data_buffers = buffers
-            .iter()
-            .zip(0u32..)
-            .filter_map(|(buffer, i)| {
-                if DeviceNumber(i) == p_device_number || DeviceNumber(i) == q_device_number {
-                    None
-                } else {
-                    Some(buffer)
-                }
-            })
-            .collect_vec();
-        for i in 0..512 {
-            let xor = data_buffers
-                .iter()
-                .map(|buffer| buffer[i])
-                .fold(0, |acc, byte| acc ^ byte);
-            if xor != p_buffer[i] {
-                return Err(io::Error::from(io::ErrorKind::InvalidData));
+
+        // The data is taken as read, as md does: checking it against parity
+        // is left to scrub.
+        let mut data = vec![0; 512];
+        let error = match read_sector_of_device(data_device_number, sector_in_device, &mut data) {
+            Ok(length) if length == data.len() => return Ok(data),
+            Ok(_) => io::Error::from(io::ErrorKind::InvalidData),
+            Err(error) => error,
+        };
+
+        let mut read_block = |device_number| {
+            let mut buf = vec![0; 512];
+            match read_sector_of_device(device_number, sector_in_device, &mut buf) {
+                Ok(length) if length == buf.len() => Some(buf),
+                _ => None,
+            }
+        };
+
+        // The data members of the row in order, which is the order of their
+        // coefficients in Q, and those missing by index, flagging the one to
+        // recover.
+        let sectors_per_chunk_u64 = u64::from(sectors_per_chunk);
+        let data_device_count = u64::from(raid_device_count).saturating_sub(2);
+        let stripe_number = u64::from(sector_in_device) / sectors_per_chunk_u64;
+        let sector_in_chunk = u64::from(sector_in_device) % sectors_per_chunk_u64;
+        let mut missing = Vec::new();
+        let mut blocks = Vec::new();
+        for data_index in 0..data_device_count {
+            let row_sector = SectorNumber(
+                (stripe_number * data_device_count + data_index) * sectors_per_chunk_u64
+                    + sector_in_chunk,
+            );
+            let (_, _, _, device_number) = self
+                .compute_sector(row_sector, sectors_per_chunk, raid_device_count)
+                .ok_or(io::ErrorKind::InvalidInput)?;
+            let block = if device_number == data_device_number {
+                None
+            } else {
+                read_block(device_number)
+            };
+            if block.is_none() {
+                missing.push((data_index as u32, device_number == data_device_number));
+            }
+            blocks.push(block.unwrap_or_else(|| vec![0; 512]));
+        }
+        let p = read_block(p_device_number);
+        let q = read_block(q_device_number);
+
+        // With the missing blocks taken as zero, P and Q leave
+        // `p = Dx + Dy` and `q = g^x * Dx + g^y * Dy`.
+        let blocks = blocks.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let xor = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(a, b)| a ^ b).collect::<Vec<_>>();
+        let p = p.map(|p| blocks.iter().fold(p, |p, block| xor(&p, block)));
+        let q = q.map(|q| xor(&q, &q_syndrome(&blocks)));
+        match (missing.as_slice(), p, q) {
+            ([_], Some(p), _) => Ok(p),
+            ([(x, _)], None, Some(q)) => {
+                let factor = gf_inv(gf_pow(2, *x));
+                Ok(q.iter().map(|&q| gf_mul(q, factor)).collect())
+            }
+            ([(x, x_wanted), (y, _)], Some(p), Some(q)) => {
+                let (x, y) = if *x_wanted { (*x, *y) } else { (*y, *x) };
+                let g_y = gf_pow(2, y);
+                let factor = gf_inv(gf_pow(2, x) ^ g_y);
+                Ok(p.iter()
+                    .zip(q)
+                    .map(|(&p, q)| gf_mul(gf_mul(g_y, p) ^ q, factor))
+                    .collect())
             }
+            _ => Err(error),
         }
-        Ok(buffers[usize::from(data_device_number)].clone())
     }
 }
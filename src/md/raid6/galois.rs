@@ -0,0 +1,56 @@
+/// Multiplies two elements of GF(2^8), using the polynomial that md uses for
+/// RAID6, x^8 + x^4 + x^3 + x^2 + 1.
+pub fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1d } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// Raises an element of GF(2^8) to a power.
+pub fn gf_pow(a: u8, exponent: u32) -> u8 {
+    (0..exponent).fold(1, |power, _| gf_mul(power, a))
+}
+
+/// Finds the multiplicative inverse of a nonzero element of GF(2^8), which is
+/// `a^254`.
+pub fn gf_inv(a: u8) -> u8 {
+    gf_pow(a, 254)
+}
+
+/// Computes the Q syndrome of a row, given the blocks of its data devices in
+/// order: the sum of `g^i * data[i]` with the generator `g = 2`.
+pub fn q_syndrome(data: &[&[u8]]) -> Vec<u8> {
+    let length = data.iter().map(|block| block.len()).max().unwrap_or(0);
+    let mut q = vec![0u8; length];
+    for block in data.iter().rev() {
+        for (q_byte, data_byte) in q.iter_mut().zip(block.iter()) {
+            *q_byte = gf_mul(*q_byte, 2) ^ data_byte;
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::raid6::galois::{gf_inv, gf_mul, gf_pow, q_syndrome};
+
+    #[test]
+    fn test() {
+        assert_eq!(gf_mul(0x80, 2), 0x1d);
+        assert_eq!(gf_mul(0x53, 1), 0x53);
+        assert_eq!(gf_mul(0x53, 0), 0);
+        assert_eq!(gf_mul(3, 7), 9);
+        assert_eq!(gf_pow(2, 8), 0x1d);
+        assert!((1..=255).all(|a| gf_mul(a, gf_inv(a)) == 1));
+        assert_eq!(
+            q_syndrome(&[&[1, 0x80], &[1, 0x80], &[1, 0]]),
+            vec![1 ^ 2 ^ 4, 0x80 ^ 0x1d]
+        );
+    }
+}
@@ -1,4 +1,8 @@
 mod algorithm;
+mod galois;
 
 #[allow(unused_imports)]
-pub use algorithm::Raid6Algorithm;
+pub use self::{
+    algorithm::Raid6Algorithm,
+    galois::{gf_inv, gf_mul, gf_pow, q_syndrome},
+};
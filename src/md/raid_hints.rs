@@ -0,0 +1,109 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use crate::ext4::Ext4Superblock;
+use crate::md::format::MdFormat;
+use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+use crate::md::MdDevice;
+use std::io::{Read, Seek, SeekFrom};
+
+/// The chunk size and number of data devices that mkfs was told about when
+/// an ext4 filesystem was created, as recorded in its superblock.
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Debug)]
+pub struct MdExt4RaidHints {
+    pub chunk_size: Option<SectorCount<u32>>,
+    pub data_device_count: Option<DeviceCount>,
+}
+
+impl MdExt4RaidHints {
+    /// Returns `None` if the filesystem was not created with any hints.
+    pub fn from_superblock<S: AsRef<[u8]>>(superblock: &Ext4Superblock<S>) -> Option<Self> {
+        let stride = u64::from(superblock.raid_stride());
+        let stripe_width = u64::from(superblock.raid_stripe_width());
+        let hints = Self {
+            chunk_size: stride
+                .checked_mul(superblock.block_size_bytes())
+                .map(|bytes| bytes / 512)
+                .filter(|&sectors| sectors > 0)
+                .and_then(|sectors| u32::try_from(sectors).ok())
+                .map(SectorCount),
+            data_device_count: stripe_width
+                .checked_div(stride)
+                .filter(|&count| count > 0 && stripe_width % stride == 0)
+                .and_then(|count| u32::try_from(count).ok())
+                .map(DeviceCount),
+        };
+        if hints.chunk_size.is_none() && hints.data_device_count.is_none() {
+            None
+        } else {
+            Some(hints)
+        }
+    }
+
+    /// Reads the hints of an ext4 filesystem at the start of the array, from
+    /// the member holding its first chunk, with its data at `data_offset`.
+    pub fn read_from_member<D: BlockDevice>(
+        device: &MdDevice<D>,
+        data_offset: SectorNumber,
+    ) -> Option<Self> {
+        let mut reader = BlockDeviceReader::new(device.try_clone().ok()?);
+        reader
+            .seek(SeekFrom::Start(
+                u64::from(data_offset).checked_mul(512)?.checked_add(1024)?,
+            ))
+            .ok()?;
+        let mut buf = vec![0u8; Ext4Superblock::<&[u8]>::SIZE];
+        reader.read_exact(&mut buf).ok()?;
+        Some(Ext4Superblock::new(buf))
+            .filter(|superblock| superblock.valid_magic())
+            .and_then(|superblock| Self::from_superblock(&superblock))
+    }
+
+    /// Whether every hint agrees with the given format.
+    pub fn matches(&self, format: &MdFormat) -> bool {
+        self.chunk_size
+            .is_none_or(|chunk_size| chunk_size == format.chunk_size)
+            && self
+                .data_device_count
+                .is_none_or(|count| Some(count) == format.data_device_count())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ext4::Ext4Superblock;
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::format::MdFormat;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid_hints::MdExt4RaidHints;
+    use crate::md::units::{DeviceCount, SectorCount};
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn test() {
+        let mut buf = vec![0u8; 1024];
+        LittleEndian::write_u32(&mut buf[24..], 2);
+        LittleEndian::write_u16(&mut buf[56..], 0xef53);
+        assert_eq!(
+            MdExt4RaidHints::from_superblock(&Ext4Superblock::new(buf.as_slice())),
+            None
+        );
+
+        LittleEndian::write_u16(&mut buf[356..], 16);
+        LittleEndian::write_u32(&mut buf[368..], 48);
+        let hints = MdExt4RaidHints::from_superblock(&Ext4Superblock::new(buf.as_slice())).unwrap();
+        assert_eq!(hints.chunk_size, Some(SectorCount(128)));
+        assert_eq!(hints.data_device_count, Some(DeviceCount(3)));
+
+        let mut format = MdFormat {
+            algorithm: MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric),
+            device_count: DeviceCount(4),
+            sectors_per_device: SectorCount(1 << 20),
+            chunk_size: SectorCount(128),
+        };
+        assert!(hints.matches(&format));
+        format.device_count = DeviceCount(5);
+        assert!(!hints.matches(&format));
+        format.device_count = DeviceCount(4);
+        format.chunk_size = SectorCount(1024);
+        assert!(!hints.matches(&format));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use crate::md::definition::MdArrayDefinition;
+use crate::md::format::MdFormat;
+use crate::md::raid6::q_syndrome;
+use crate::md::superblock::{
+    NewSuperblock, Superblock, SuperblockMut, SuperblockVersion0, SuperblockVersion1,
+};
+use crate::md::units::{DeviceNumber, SectorCount, SectorNumber, Timestamp};
+use crate::md::{MdArray, MdDevice};
+use itertools::Itertools;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// Copies the bytes in `range` of `device` to `output`, with `patch` written
+/// over them at its offset.
+fn copy_with_patch<D: BlockDevice, W: Write>(
+    device: &MdDevice<D>,
+    range: Range<u64>,
+    patch: &(u64, Vec<u8>),
+    output: &mut W,
+) -> io::Result<()> {
+    let mut reader = BlockDeviceReader::new(device.try_clone()?);
+    reader.seek(SeekFrom::Start(range.start))?;
+    let mut buf = vec![0u8; 1 << 20];
+    let mut position = range.start;
+    while position < range.end {
+        let length = (range.end - position).min(buf.len() as u64) as usize;
+        let buf = &mut buf[..length];
+        reader.read_exact(buf)?;
+        let (patch_offset, patch) = patch;
+        let overlap = position.max(*patch_offset)
+            ..(position + length as u64).min(patch_offset + patch.len() as u64);
+        if overlap.start < overlap.end {
+            buf[(overlap.start - position) as usize..(overlap.end - position) as usize]
+                .copy_from_slice(
+                    &patch[(overlap.start - patch_offset) as usize
+                        ..(overlap.end - patch_offset) as usize],
+                );
+        }
+        output.write_all(buf)?;
+        position += length as u64;
+    }
+    Ok(())
+}
+
+fn random_device_uuid() -> [u8; 16] {
+    let mut device_uuid = [0u8; 16];
+    for half in device_uuid.chunks_mut(8) {
+        half.copy_from_slice(&RandomState::new().build_hasher().finish().to_le_bytes());
+    }
+    device_uuid
+}
+
+/// Writes a version 0.90 superblock from scratch for the member in
+/// `device_number`, with the array as `donor` describes it.
+fn replacement_version_0(
+    donor: &SuperblockVersion0,
+    device_number: DeviceNumber,
+) -> io::Result<Vec<u8>> {
+    let device_roles = donor.device_roles();
+    let mut array_uuid = [0u8; 16];
+    let uuid = donor.array_uuid();
+    array_uuid[..uuid.as_bytes().len()].copy_from_slice(uuid.as_bytes());
+    let new = NewSuperblock {
+        array_uuid,
+        array_name: Vec::new(),
+        format: MdFormat {
+            algorithm: donor.algorithm(),
+            device_count: donor.raid_device_count(),
+            sectors_per_device: donor.sectors_per_device(),
+            chunk_size: donor.chunk_size(),
+        },
+        creation_time: donor.creation_time().unwrap_or(Timestamp::from_seconds(0)),
+        update_time: donor.update_time().unwrap_or(Timestamp::from_seconds(0)),
+        event_count: donor.event_count(),
+        reshape_status: donor.reshape_status(),
+        device_role_index: device_roles
+            .iter()
+            .position(|role| role.device_number() == Some(device_number))
+            .ok_or(io::ErrorKind::InvalidData)?,
+        device_roles,
+        device_uuid: [0u8; 16],
+        data_offset: SectorNumber(0),
+        data_size: SectorCount(0),
+        super_offset: SectorNumber(0),
+    };
+    Ok(SuperblockVersion0::create(&new, donor.big_endian())?.to_bytes())
+}
+
+/// Writes an image of a missing member to `output`, with its data worked out
+/// from the other members. When another member has a version 0.90 or 1
+/// superblock, the image is laid out like that member, with everything
+/// outside the data copied from it and a superblock written for the missing
+/// member. Otherwise the image holds the data alone.
+pub(in crate::md) fn rebuild_member<D: BlockDevice, W: Write>(
+    array: &MdArray<D>,
+    definition: &MdArrayDefinition<D>,
+    device_number: DeviceNumber,
+    output: &mut W,
+) -> io::Result<()> {
+    let format = array.format().ok_or(io::ErrorKind::InvalidData)?;
+    if u32::from(device_number) >= u32::from(format.device_count)
+        || definition.devices.contains_key(&device_number)
+    {
+        Err(io::ErrorKind::InvalidInput)?;
+    }
+    let chunk_size = u64::from(format.chunk_size);
+    let data_device_count = u64::from(
+        format
+            .data_device_count()
+            .ok_or(io::ErrorKind::Unsupported)?,
+    );
+    let stripe_count = u64::from(format.sectors_per_device)
+        .checked_div(chunk_size)
+        .ok_or(io::ErrorKind::InvalidData)?;
+
+    // The most up to date member with a version 0.90 or 1 superblock.
+    let donor = definition
+        .devices
+        .values()
+        .filter_map(|device| {
+            let superblock = device.superblock.as_option()?;
+            let location = device.superblock_location()?;
+            matches!(superblock.major_version(), 0 | 1).then_some((device, superblock, location))
+        })
+        .max_by_key(|(_, superblock, _)| superblock.event_count());
+    let layout = match donor {
+        Some((device, superblock, location)) => {
+            let mut reader = BlockDeviceReader::new(device.try_clone()?);
+            reader.seek(SeekFrom::Start(location.offset))?;
+            let replacement = if superblock.major_version() == 0 {
+                replacement_version_0(&SuperblockVersion0::read(&mut reader)?, device_number)?
+            } else {
+                let mut replacement =
+                    SuperblockVersion1::read(&mut reader, superblock.minor_version())?;
+                replacement.make_replacement(device_number, random_device_uuid())?;
+                replacement.into_buffer()
+            };
+            let size = device
+                .block_count()?
+                .size_bytes(device.block_size()?)
+                .ok_or(io::ErrorKind::InvalidData)?;
+            let data_offset = u64::from(superblock.data_offset()) * 512;
+            Some((device, size, data_offset, (location.offset, replacement)))
+        }
+        None => None,
+    };
+
+    if let Some((device, _, data_offset, patch)) = &layout {
+        copy_with_patch(*device, 0..*data_offset, patch, output)?;
+    }
+
+    let mut reader = BlockDeviceReader::new(array.try_clone()?);
+    let mut stripe = vec![0u8; (data_device_count * chunk_size) as usize * 512];
+    for stripe_number in 0..stripe_count {
+        reader.read_exact(&mut stripe)?;
+        let data = stripe.chunks(chunk_size as usize * 512).collect_vec();
+        let locations = (0..data_device_count)
+            .map(|index| {
+                format
+                    .algorithm
+                    .compute_sector(
+                        SectorNumber((stripe_number * data_device_count + index) * chunk_size),
+                        format.chunk_size,
+                        format.device_count,
+                    )
+                    .ok_or(io::ErrorKind::Unsupported)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let chunk = if let Some(index) = locations
+            .iter()
+            .position(|location| location.data_device_number == device_number)
+        {
+            data[index].to_vec()
+        } else if locations[0].p_device_number == device_number {
+            let mut p = vec![0u8; chunk_size as usize * 512];
+            for chunk in &data {
+                p.iter_mut()
+                    .zip(chunk.iter())
+                    .for_each(|(acc, byte)| *acc ^= byte);
+            }
+            p
+        } else if locations[0].q_device_number == Some(device_number) {
+            q_syndrome(&data)
+        } else {
+            Err(io::ErrorKind::InvalidData)?
+        };
+        output.write_all(&chunk)?;
+    }
+
+    // Sectors past the last whole chunk are not used by the array.
+    let data_end = u64::from(format.sectors_per_device) * 512;
+    io::copy(
+        &mut io::repeat(0).take(data_end - stripe_count * chunk_size * 512),
+        output,
+    )?;
+
+    if let Some((device, size, data_offset, patch)) = &layout {
+        copy_with_patch(
+            *device,
+            (data_offset + data_end).min(*size)..*size,
+            patch,
+            output,
+        )?;
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::{open_member_images, pseudo_random_data, raid5_fixture};
+    use crate::md::fixture::{MdArrayFixture, MdFixtureFailure, MdFixtureSuperblock};
+    use crate::md::format::MdFormat;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::superblock::{Superblock, SuperblockVersion0, SuperblockVersion1};
+    use crate::md::units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber};
+    use crate::md::{MdArray, MdDevice};
+    use std::rc::Rc;
+
+    #[test]
+    fn rebuild_raid6_members() -> anyhow::Result<()> {
+        let algorithm = MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric);
+        let data = pseudo_random_data(16 * 4096, 15);
+        let images = member_images(&data, &algorithm, 4, 8, 0)?;
+        let format = MdFormat {
+            algorithm,
+            device_count: DeviceCount(4),
+            sectors_per_device: SectorCount(images[0].len() as u64 / 512),
+            chunk_size: SectorCount(8),
+        };
+        for missing in 0..4 {
+            let members = images.iter().enumerate().map(|(index, image)| {
+                (index != missing).then(|| {
+                    let device = MdDevice::from_block_device(
+                        InMemoryBlockDevice::new(image.clone(), BlockSize(512)),
+                        Some(format!("member{index}")),
+                    )
+                    .unwrap();
+                    (Rc::new(device), SectorNumber(0))
+                })
+            });
+            let array = MdArray::assemble(format.clone(), members)?;
+            let mut image = Vec::new();
+            array.rebuild_member(DeviceNumber(missing as u32), &mut image)?;
+            assert!(image == images[missing], "member {missing} differs");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_member_with_superblock() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 16);
+        let mut images = raid5_fixture([16; 16]).present_member_images(&data);
+        let original = images.remove(1);
+        let array = MdArray::open(open_member_images(images));
+        let mut image = Vec::new();
+        array.rebuild_member(DeviceNumber(1), &mut image)?;
+
+        assert_eq!(image.len(), original.len());
+        assert!(image[8192..] == original[8192..]);
+        let superblock = SuperblockVersion1::new(&image[4096..8192], 2);
+        assert!(superblock.valid_checksum());
+        assert_eq!(superblock.device_role_index(), 1);
+        // Only the device UUID and the checksum differ from the original.
+        for (offset, (rebuilt, original)) in image[..8192].iter().zip(&original[..8192]).enumerate()
+        {
+            if !(4096 + 168..4096 + 184).contains(&offset)
+                && !(4096 + 216..4096 + 220).contains(&offset)
+            {
+                assert_eq!(rebuilt, original, "byte {offset} differs");
+            }
+        }
+
+        assert!(array
+            .rebuild_member(DeviceNumber(0), &mut Vec::new())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_member_with_superblock_version_0() -> anyhow::Result<()> {
+        let data = pseudo_random_data(2 * 8 * 4096, 17);
+        let fixture = MdArrayFixture {
+            superblock: Some(MdFixtureSuperblock::Version0_90),
+            data_offset: SectorNumber(0),
+            ..MdArrayFixture::new(MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric), 3, 8)
+        };
+        let original = fixture.member_images(&data)?.remove(1).unwrap();
+        let degraded = MdArrayFixture {
+            failures: vec![MdFixtureFailure::MissingMember(DeviceNumber(1))],
+            ..fixture.clone()
+        };
+        let array = MdArray::open(degraded.open(&data));
+        let mut image = Vec::new();
+        array.rebuild_member(DeviceNumber(1), &mut image)?;
+
+        assert_eq!(image.len(), original.len());
+        let offset = original.len() - 65536;
+        let superblock = SuperblockVersion0::read(&image[offset..])?;
+        assert!(superblock.valid_checksum());
+        assert_eq!(superblock.device_role_index(), 1);
+        assert!(image == original);
+
+        // A stripe whose parity does not match is rebuilt from the data as
+        // read, as md would, rather than failing the rebuild.
+        let corrupted = MdArrayFixture {
+            failures: vec![
+                MdFixtureFailure::MissingMember(DeviceNumber(1)),
+                MdFixtureFailure::CorruptedChunk(DeviceNumber(0), 2),
+            ],
+            ..fixture
+        };
+        let mut image = Vec::new();
+        MdArray::open(corrupted.open(&data)).rebuild_member(DeviceNumber(1), &mut image)?;
+        assert_eq!(image.len(), original.len());
+        assert!(image[..16 * 512] == original[..16 * 512]);
+        assert!(image[24 * 512..] == original[24 * 512..]);
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use crate::block_device::BlockDevice;
+use crate::md::definition::MdArrayDefinition;
+use crate::md::superblock::MdDeviceRole;
+use crate::md::MdDevice;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+/// Changes to make to the superblock of every member of an array, for md
+/// to assemble it as it is defined here.
+#[derive(Clone, Default, Debug)]
+pub struct MdSuperblockRepair {
+    /// Raise the event count of every member to the highest among them, so
+    /// that md takes none of them for stale.
+    pub sync_event_counts: bool,
+
+    /// Forget a reshape in progress.
+    pub clear_reshape: bool,
+
+    /// Record each member in the role it has in the array, such as one
+    /// assembled with a given order.
+    pub fix_roles: bool,
+}
+
+/// The superblock of a member as repaired, and where it goes.
+pub struct MdRepairedSuperblock<D: BlockDevice> {
+    pub device: Rc<MdDevice<D>>,
+
+    /// In bytes from the start of the member.
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl<D: BlockDevice> MdRepairedSuperblock<D> {
+    /// Writes the superblock to a copy of its member, such as an image file.
+    pub fn write_to<W: Write + Seek>(&self, output: &mut W) -> io::Result<()> {
+        output.seek(SeekFrom::Start(self.offset))?;
+        output.write_all(&self.bytes)?;
+        output.flush()
+    }
+}
+
+/// Works out the repaired superblock of every member that has one, from
+/// the superblock read again from the member.
+pub(in crate::md) fn repair_superblocks<D: BlockDevice>(
+    definition: &MdArrayDefinition<D>,
+    repair: &MdSuperblockRepair,
+) -> io::Result<Vec<MdRepairedSuperblock<D>>> {
+    let mut members = definition
+        .devices
+        .iter()
+        .filter_map(|(device_number, device)| {
+            Some((
+                *device_number,
+                device.clone(),
+                device.superblock.as_option()?.device_role_index(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    members.sort_by_key(|(device_number, _, _)| u32::from(*device_number));
+
+    let event_count = definition
+        .devices
+        .values()
+        .filter_map(|device| Some(device.superblock.as_option()?.event_count()))
+        .max();
+
+    // The roles of the newest superblock, with every member in its slot,
+    // and any other slot that held one of them taken for a failed device.
+    let mut roles = definition
+        .newest_superblock()
+        .map(|(_, superblock)| superblock.device_roles())
+        .unwrap_or_default();
+    for role in &mut roles {
+        if role.device_number().is_some_and(|device_number| {
+            members
+                .iter()
+                .any(|(member, _, _)| *member == device_number)
+        }) {
+            *role = MdDeviceRole::faulty();
+        }
+    }
+    for (device_number, _, slot) in &members {
+        if roles.len() <= *slot {
+            roles.resize(slot + 1, MdDeviceRole::spare());
+        }
+        if roles[*slot].device_number().is_some() {
+            // Two members share a slot.
+            Err(io::ErrorKind::InvalidData)?;
+        }
+        roles[*slot] = MdDeviceRole::from_device_number(*device_number);
+    }
+
+    members
+        .into_iter()
+        .map(|(_, device, _)| {
+            let mut superblock = device.read_superblock_mut()?;
+            if let (true, Some(event_count)) = (repair.sync_event_counts, event_count) {
+                superblock.set_event_count(event_count);
+            }
+            if repair.clear_reshape {
+                superblock.clear_reshape();
+            }
+            if repair.fix_roles {
+                superblock.set_device_roles(&roles)?;
+            }
+            let offset = device
+                .superblock_location()
+                .ok_or(io::ErrorKind::NotFound)?
+                .offset;
+            Ok(MdRepairedSuperblock {
+                device,
+                offset,
+                bytes: superblock.to_bytes(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::fixture::test::{
+        open_member_images, pseudo_random_data, raid5_fixture, update_superblock, DEVICE_COUNT,
+    };
+    use crate::md::units::{DeviceNumber, MetadataEventCount, SectorNumber};
+    use crate::md::{MdArray, MdDevice, MdSuperblockRepair};
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[test]
+    fn repair_swapped_and_stale_members() -> anyhow::Result<()> {
+        let data = pseudo_random_data(64 * 1024, 19);
+        let mut images = raid5_fixture([19; 16]).present_member_images(&data);
+        // Members 0 and 1 claim each other's roles, and member 2 is stale.
+        images.swap(0, 1);
+        update_superblock(&mut images[0], |superblock| {
+            LittleEndian::write_u32(&mut superblock[160..], 0)
+        });
+        update_superblock(&mut images[1], |superblock| {
+            LittleEndian::write_u32(&mut superblock[160..], 1)
+        });
+        for (image, event_count) in images.iter_mut().zip([7, 7, 5]) {
+            update_superblock(image, |superblock| {
+                LittleEndian::write_u64(&mut superblock[200..], event_count)
+            });
+        }
+        let devices = open_member_images(images.clone())
+            .into_iter()
+            .map(Rc::new)
+            .collect::<Vec<_>>();
+
+        // Assembled in the right order, ignoring the superblocks.
+        let format = MdArray::open(devices.clone()).format().unwrap().clone();
+        let array = MdArray::assemble(
+            format,
+            [1, 0, 2].map(|index| Some((devices[index].clone(), SectorNumber(16)))),
+        )?;
+        let repaired = array.repair_superblocks(&MdSuperblockRepair {
+            sync_event_counts: true,
+            fix_roles: true,
+            ..MdSuperblockRepair::default()
+        })?;
+        assert_eq!(repaired.len(), DEVICE_COUNT as usize);
+
+        let mut repaired_images = Vec::new();
+        for (device_number, (superblock, image)) in (0..)
+            .map(DeviceNumber)
+            .zip(repaired.iter().zip([&images[1], &images[0], &images[2]]))
+        {
+            assert_eq!(superblock.offset, 4096);
+            let mut output = Cursor::new(image.clone());
+            superblock.write_to(&mut output)?;
+            let image = output.into_inner();
+            let device = MdDevice::from_block_device(
+                InMemoryBlockDevice::new(image.clone(), BlockSize(512)),
+                None::<&str>,
+            )?;
+            let superblock = device.superblock.as_option().unwrap();
+            assert_eq!(superblock.event_count(), MetadataEventCount(7));
+            assert_eq!(
+                superblock.device_roles()[superblock.device_role_index()].device_number(),
+                Some(device_number)
+            );
+            repaired_images.push(image);
+        }
+        let diagnosis = MdArray::open(open_member_images(repaired_images)).diagnose();
+        assert!(diagnosis.event_count_problem.is_none());
+        assert!(diagnosis.device_roles_problem.is_none());
+        assert!(diagnosis.missing_member_problem.is_none());
+        Ok(())
+    }
+}
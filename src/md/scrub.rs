@@ -0,0 +1,442 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use crate::md::definition::MdArrayDefinition;
+use crate::md::raid6::q_syndrome;
+use crate::md::units::{DeviceNumber, SectorNumber, StripeNumber};
+use itertools::Itertools;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// A run of consecutive stripes in which the parity does not match the
+/// data.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdScrubMismatch {
+    pub stripes: Range<StripeNumber>,
+
+    /// The sectors of the array held by the stripes.
+    pub array_sectors: Range<SectorNumber>,
+
+    /// The sectors of each member spanned by the stripes, counted from its
+    /// data offset.
+    pub member_sectors: Range<SectorNumber>,
+
+    /// How many rows of the stripes have a P sector that does not match.
+    pub p_mismatch_count: u64,
+
+    /// How many rows of the stripes have a Q sector that does not match.
+    pub q_mismatch_count: u64,
+}
+
+/// A run of consecutive stripes in which the same members are missing or
+/// could not be read.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdScrubUnreadable {
+    pub stripes: Range<StripeNumber>,
+
+    /// The sectors of the array held by the stripes.
+    pub array_sectors: Range<SectorNumber>,
+
+    /// The sectors of each member spanned by the stripes, counted from its
+    /// data offset.
+    pub member_sectors: Range<SectorNumber>,
+
+    /// In order.
+    pub devices: Vec<DeviceNumber>,
+
+    /// Whether the members left were enough to check one of the parities
+    /// of a RAID6 array.
+    pub parity_checked: bool,
+}
+
+/// The result of checking the parity of every stripe of an array, as md's
+/// `check` action does.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct MdScrubReport {
+    pub stripe_count: u64,
+
+    /// How many stripes had enough members to check at least one parity.
+    pub stripes_checked: u64,
+
+    pub mismatches: Vec<MdScrubMismatch>,
+    pub unreadable: Vec<MdScrubUnreadable>,
+}
+
+impl Display for MdScrubReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.stripes_checked == self.stripe_count {
+            write!(f, "Checked {} stripes", self.stripes_checked)?;
+        } else {
+            write!(
+                f,
+                "Checked {} of {} stripes",
+                self.stripes_checked, self.stripe_count
+            )?;
+        }
+        if self.mismatches.is_empty() {
+            writeln!(f, ": the parity matches the data throughout.")?;
+        } else {
+            writeln!(
+                f,
+                ": the parity does not match the data in {} of them:",
+                self.mismatches
+                    .iter()
+                    .map(|mismatch| u64::from(mismatch.stripes.end)
+                        - u64::from(mismatch.stripes.start))
+                    .sum::<u64>()
+            )?;
+        }
+        for mismatch in &self.mismatches {
+            write!(
+                f,
+                "- array sectors {}-{}, member sectors {}-{}: P differs in {} rows",
+                u64::from(mismatch.array_sectors.start),
+                u64::from(mismatch.array_sectors.end) - 1,
+                u64::from(mismatch.member_sectors.start),
+                u64::from(mismatch.member_sectors.end) - 1,
+                mismatch.p_mismatch_count
+            )?;
+            if mismatch.q_mismatch_count > 0 {
+                write!(f, ", Q in {} rows", mismatch.q_mismatch_count)?;
+            }
+            writeln!(f)?;
+        }
+        if !self.unreadable.is_empty() {
+            writeln!(f, "Some stripes could not be read from every member:")?;
+        }
+        for unreadable in &self.unreadable {
+            writeln!(
+                f,
+                "- array sectors {}-{}, member sectors {}-{}: {} could not be read, {}",
+                u64::from(unreadable.array_sectors.start),
+                u64::from(unreadable.array_sectors.end) - 1,
+                u64::from(unreadable.member_sectors.start),
+                u64::from(unreadable.member_sectors.end) - 1,
+                unreadable.devices.iter().join(", "),
+                if unreadable.parity_checked {
+                    "so only one parity was checked"
+                } else {
+                    "so the parity was not checked"
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads every stripe of the array from all of its members, and compares the
+/// parity on them with the parity of the data. Stripes missing more members
+/// than the array has parities are left unchecked rather than failing the
+/// whole scan.
+pub(in crate::md) fn scrub<D: BlockDevice>(
+    definition: &MdArrayDefinition<D>,
+) -> io::Result<MdScrubReport> {
+    let format = definition
+        .new_format
+        .as_ref()
+        .or(definition.format.as_ref())
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let chunk_size = u64::from(format.chunk_size);
+    let data_device_count = u64::from(
+        format
+            .data_device_count()
+            .ok_or(io::ErrorKind::Unsupported)?,
+    );
+    let parity_device_count = usize::from(
+        format
+            .algorithm
+            .parity_device_count()
+            .ok_or(io::ErrorKind::Unsupported)?,
+    );
+    let stripe_count = u64::from(format.sectors_per_device)
+        .checked_div(chunk_size)
+        .ok_or(io::ErrorKind::InvalidData)?;
+
+    let mut readers = (0..u32::from(format.device_count))
+        .map(DeviceNumber)
+        .map(|device_number| {
+            definition
+                .devices
+                .get(&device_number)
+                .map(|device| {
+                    Ok((
+                        BlockDeviceReader::new(device.as_ref().try_clone()?),
+                        definition.data_offset(device_number),
+                    ))
+                })
+                .transpose()
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut chunks = vec![vec![0u8; chunk_size as usize * 512]; readers.len()];
+    let mut stripes_checked = 0;
+    let mut mismatches: Vec<MdScrubMismatch> = Vec::new();
+    let mut unreadable: Vec<MdScrubUnreadable> = Vec::new();
+    for stripe in 0..stripe_count {
+        let stripes = StripeNumber(stripe)..StripeNumber(stripe + 1);
+        let array_sectors = SectorNumber(stripe * data_device_count * chunk_size)
+            ..SectorNumber((stripe + 1) * data_device_count * chunk_size);
+        let member_sectors =
+            SectorNumber(stripe * chunk_size)..SectorNumber((stripe + 1) * chunk_size);
+
+        let mut unavailable = Vec::new();
+        for (index, (reader, chunk)) in readers.iter_mut().zip(chunks.iter_mut()).enumerate() {
+            let read = reader.as_mut().map(|(reader, data_offset)| {
+                reader.seek(SeekFrom::Start(
+                    (u64::from(*data_offset) + stripe * chunk_size) * 512,
+                ))?;
+                reader.read_exact(chunk)
+            });
+            if !matches!(read, Some(Ok(()))) {
+                unavailable.push(DeviceNumber(index as u32));
+            }
+        }
+        let parity_checked = unavailable.len() < parity_device_count;
+        if !unavailable.is_empty() {
+            match unreadable.last_mut() {
+                Some(last) if last.stripes.end == stripes.start && last.devices == unavailable => {
+                    last.stripes.end = stripes.end;
+                    last.array_sectors.end = array_sectors.end;
+                    last.member_sectors.end = member_sectors.end;
+                }
+                _ => unreadable.push(MdScrubUnreadable {
+                    stripes: stripes.clone(),
+                    array_sectors: array_sectors.clone(),
+                    member_sectors: member_sectors.clone(),
+                    devices: unavailable.clone(),
+                    parity_checked,
+                }),
+            }
+        }
+        if !parity_checked {
+            continue;
+        }
+        stripes_checked += 1;
+
+        // The first sector of each data chunk of the stripe, in order.
+        let locations = (0..data_device_count)
+            .map(|index| {
+                format
+                    .algorithm
+                    .compute_sector(
+                        SectorNumber((stripe * data_device_count + index) * chunk_size),
+                        format.chunk_size,
+                        format.device_count,
+                    )
+                    .ok_or(io::ErrorKind::Unsupported)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let p_device_number = locations[0].p_device_number;
+        let q_device_number = locations[0].q_device_number;
+
+        // A missing data chunk is rebuilt from P, which then matches by
+        // construction, so that Q can still be checked against it.
+        if let Some(&missing) = unavailable.first() {
+            if locations
+                .iter()
+                .any(|location| location.data_device_number == missing)
+            {
+                let mut rebuilt = chunks[usize::from(p_device_number)].clone();
+                for location in &locations {
+                    if location.data_device_number != missing {
+                        rebuilt
+                            .iter_mut()
+                            .zip(chunks[usize::from(location.data_device_number)].iter())
+                            .for_each(|(acc, byte)| *acc ^= byte);
+                    }
+                }
+                chunks[usize::from(missing)] = rebuilt;
+            }
+        }
+
+        let data = locations
+            .iter()
+            .map(|location| chunks[usize::from(location.data_device_number)].as_slice())
+            .collect_vec();
+        let count_mismatches = |expected: &[u8], device_number: DeviceNumber| {
+            if unavailable.contains(&device_number) {
+                return 0;
+            }
+            expected
+                .chunks(512)
+                .zip(chunks[usize::from(device_number)].chunks(512))
+                .filter(|(expected, actual)| expected != actual)
+                .count() as u64
+        };
+        let mut p = vec![0u8; chunk_size as usize * 512];
+        for chunk in &data {
+            p.iter_mut()
+                .zip(chunk.iter())
+                .for_each(|(acc, byte)| *acc ^= byte);
+        }
+        let p_mismatch_count = count_mismatches(&p, p_device_number);
+        let q_mismatch_count = q_device_number.map_or(0, |device_number| {
+            count_mismatches(&q_syndrome(&data), device_number)
+        });
+        if p_mismatch_count == 0 && q_mismatch_count == 0 {
+            continue;
+        }
+
+        match mismatches.last_mut() {
+            Some(last) if last.stripes.end == stripes.start => {
+                last.stripes.end = stripes.end;
+                last.array_sectors.end = array_sectors.end;
+                last.member_sectors.end = member_sectors.end;
+                last.p_mismatch_count += p_mismatch_count;
+                last.q_mismatch_count += q_mismatch_count;
+            }
+            _ => mismatches.push(MdScrubMismatch {
+                stripes,
+                array_sectors,
+                member_sectors,
+                p_mismatch_count,
+                q_mismatch_count,
+            }),
+        }
+    }
+
+    Ok(MdScrubReport {
+        stripe_count,
+        stripes_checked,
+        mismatches,
+        unreadable,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, DdrescueBlockDevice, DdrescueMap, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::pseudo_random_data;
+    use crate::md::format::MdFormat;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::units::{DeviceCount, DeviceNumber, SectorCount, SectorNumber, StripeNumber};
+    use crate::md::{MdArray, MdDevice};
+    use std::rc::Rc;
+
+    #[test]
+    fn scrub_raid6() -> anyhow::Result<()> {
+        const CHUNK_SIZE: u32 = 8;
+        let algorithm = MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric);
+        let data = pseudo_random_data(16 * 4096, 14);
+        let mut images = member_images(&data, &algorithm, 4, CHUNK_SIZE, 0)?;
+        let format = MdFormat {
+            algorithm,
+            device_count: DeviceCount(4),
+            sectors_per_device: SectorCount(images[0].len() as u64 / 512),
+            chunk_size: SectorCount(CHUNK_SIZE),
+        };
+        let scrub = |images: &[Vec<u8>]| {
+            let members = images.iter().enumerate().map(|(index, image)| {
+                let device = MdDevice::from_block_device(
+                    InMemoryBlockDevice::new(image.clone(), BlockSize(512)),
+                    Some(format!("member{index}")),
+                )
+                .unwrap();
+                Some((Rc::new(device), SectorNumber(0)))
+            });
+            MdArray::assemble(format.clone(), members)
+                .unwrap()
+                .scrub()
+                .unwrap()
+        };
+
+        let report = scrub(&images);
+        assert_eq!(report.stripes_checked, 8);
+        assert!(report.mismatches.is_empty());
+
+        // Corrupt a sector of the second and third stripes of one member,
+        // which holds data in one and parity in the other.
+        images[1][9 * 512] ^= 1;
+        images[1][17 * 512] ^= 1;
+        let report = scrub(&images);
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.stripes, StripeNumber(1)..StripeNumber(3));
+        assert_eq!(mismatch.array_sectors, SectorNumber(16)..SectorNumber(48));
+        assert_eq!(mismatch.member_sectors, SectorNumber(8)..SectorNumber(24));
+        assert_eq!(mismatch.p_mismatch_count + mismatch.q_mismatch_count, 3);
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("Checked 8 stripes: the parity does not match the data in 2 of them:")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scrub_degraded_raid6_with_unreadable_sectors() -> anyhow::Result<()> {
+        const CHUNK_SIZE: u32 = 8;
+        let algorithm = MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric);
+        let data = pseudo_random_data(16 * 4096, 15);
+        let mut images = member_images(&data, &algorithm, 4, CHUNK_SIZE, 0)?;
+        let format = MdFormat {
+            algorithm,
+            device_count: DeviceCount(4),
+            sectors_per_device: SectorCount(images[0].len() as u64 / 512),
+            chunk_size: SectorCount(CHUNK_SIZE),
+        };
+        // The last member is missing, a sector of the third stripe of the
+        // first member was never rescued, and a sector of the second
+        // stripe of the second member is corrupt.
+        images[1][9 * 512] ^= 1;
+        let unread = DdrescueMap {
+            blocks: vec![(17 * 512..18 * 512, '-')],
+        };
+        let members = images.iter().take(3).enumerate().map(|(index, image)| {
+            let image = InMemoryBlockDevice::new(image.clone(), BlockSize(512));
+            let device = if index == 0 {
+                DdrescueBlockDevice::new(image, &unread)
+            } else {
+                DdrescueBlockDevice::without_map(image)
+            };
+            let device = MdDevice::from_block_device(device, Some(format!("member{index}")));
+            Some((Rc::new(device.unwrap()), SectorNumber(0)))
+        });
+        let report = MdArray::assemble(format, members.chain([None]))?.scrub()?;
+
+        assert_eq!(report.stripe_count, 8);
+        assert_eq!(report.stripes_checked, 7);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(
+            report.mismatches[0].stripes,
+            StripeNumber(1)..StripeNumber(2)
+        );
+        let unreadable = report
+            .unreadable
+            .iter()
+            .map(|unreadable| {
+                (
+                    unreadable.stripes.clone(),
+                    unreadable.devices.clone(),
+                    unreadable.parity_checked,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unreadable,
+            vec![
+                (
+                    StripeNumber(0)..StripeNumber(2),
+                    vec![DeviceNumber(3)],
+                    true
+                ),
+                (
+                    StripeNumber(2)..StripeNumber(3),
+                    vec![DeviceNumber(0), DeviceNumber(3)],
+                    false
+                ),
+                (
+                    StripeNumber(3)..StripeNumber(8),
+                    vec![DeviceNumber(3)],
+                    true
+                ),
+            ]
+        );
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("Checked 7 of 8 stripes: the parity does not match the data in 1 of them:")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Keeps the `limit` candidates with the highest keys among those offered,
+/// preferring those offered first when keys tie, without holding on to the
+/// rest.
+pub(super) struct BestCandidates<K: Ord, T> {
+    heap: BinaryHeap<Reverse<Entry<K, T>>>,
+    limit: usize,
+    offered: u64,
+}
+
+struct Entry<K: Ord, T> {
+    key: K,
+    offered: Reverse<u64>,
+    candidate: T,
+}
+
+impl<K: Ord, T> PartialEq for Entry<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, T> Eq for Entry<K, T> {}
+
+impl<K: Ord, T> PartialOrd for Entry<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for Entry<K, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.key, self.offered).cmp(&(&other.key, other.offered))
+    }
+}
+
+impl<K: Ord, T> BestCandidates<K, T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(limit.saturating_add(1)),
+            limit,
+            offered: 0,
+        }
+    }
+
+    pub fn offer(&mut self, key: K, candidate: T) {
+        self.heap.push(Reverse(Entry {
+            key,
+            offered: Reverse(self.offered),
+            candidate,
+        }));
+        self.offered += 1;
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// The candidates kept, best first.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| entry.candidate)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::search::best::BestCandidates;
+
+    #[test]
+    fn keeps_best_in_order_offered() {
+        let mut best = BestCandidates::new(3);
+        for (key, candidate) in [(1, 'a'), (5, 'b'), (3, 'c'), (5, 'd'), (2, 'e'), (3, 'f')] {
+            best.offer(key, candidate);
+        }
+        assert_eq!(best.into_sorted_vec(), ['b', 'd', 'c']);
+
+        let mut best = BestCandidates::new(0);
+        best.offer(1, 'a');
+        assert!(best.into_sorted_vec().is_empty());
+    }
+}
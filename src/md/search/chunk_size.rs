@@ -0,0 +1,361 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use crate::md::search::MdGeometrySearch;
+use crate::md::units::{SectorCount, SectorNumber};
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Estimates the chunk size of an array from the raw contents of any of its
+/// members.
+///
+/// Consecutive chunks on a member hold data from unrelated parts of the
+/// array, so the contents of a member tend to change more across chunk
+/// boundaries than within chunks. If every member of a RAID6 array is
+/// present, the member holding Q only changes at chunk boundaries too.
+#[derive(Clone, Debug)]
+pub struct MdChunkSizeAnalysis {
+    pub chunk_sizes: Vec<SectorCount<u32>>,
+
+    /// Chunk boundaries are aligned to the data offset, which is assumed to
+    /// be one of these.
+    pub data_offsets: Vec<SectorNumber>,
+
+    pub window_sectors: u64,
+    pub window_count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct MdChunkSizeEstimate {
+    pub chunk_size: SectorCount<u32>,
+
+    /// How much more, on average, the entropy of the sectors of the members
+    /// changes across the boundaries of chunks of this size than halfway
+    /// between them, in bits per byte.
+    pub boundary_contrast: f64,
+
+    /// Whether the member holding Q only changes at the boundaries of chunks
+    /// of this size. `None` if Q could not be seen to move, as when some
+    /// members are missing or the array is not RAID6.
+    pub q_rotation_consistent: Option<bool>,
+}
+
+impl Default for MdChunkSizeAnalysis {
+    fn default() -> Self {
+        let search = MdGeometrySearch::default();
+        Self {
+            chunk_sizes: search.chunk_sizes,
+            data_offsets: search.data_offsets,
+            // Four of the largest chunks.
+            window_sectors: 8192,
+            window_count: 16,
+        }
+    }
+}
+
+/// Sums of the changes between consecutive sectors that fall on chunk
+/// boundaries, and halfway between them.
+#[derive(Clone, Copy, Default)]
+struct BoundarySums {
+    boundary: f64,
+    boundary_count: u64,
+    midpoint: f64,
+    midpoint_count: u64,
+}
+
+impl BoundarySums {
+    fn contrast(&self) -> f64 {
+        if self.boundary_count == 0 || self.midpoint_count == 0 {
+            0.0
+        } else {
+            self.boundary / self.boundary_count as f64 - self.midpoint / self.midpoint_count as f64
+        }
+    }
+}
+
+impl MdChunkSizeAnalysis {
+    /// Returns an estimate for every chunk size, most likely first.
+    pub fn run<D: BlockDevice>(&self, devices: &[D]) -> io::Result<Vec<MdChunkSizeEstimate>> {
+        let mut readers = devices
+            .iter()
+            .map(|device| Ok(BlockDeviceReader::new(device.try_clone()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let min_sectors = devices
+            .iter()
+            .map(|device| {
+                Ok(device
+                    .block_count()?
+                    .size_bytes(device.block_size()?)
+                    .ok_or(io::ErrorKind::InvalidInput)?
+                    >> 9)
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .min()
+            .unwrap_or(0);
+
+        // Start past the largest data offset, to stay clear of superblocks
+        // and bitmaps.
+        let first_sector = self
+            .data_offsets
+            .iter()
+            .map(|&data_offset| u64::from(data_offset))
+            .filter(|&data_offset| data_offset < min_sectors)
+            .max()
+            .unwrap_or(0);
+        let span = min_sectors - first_sector;
+        let window_sectors = self.window_sectors.min(span);
+        let window_count = span
+            .checked_div(window_sectors)
+            .map_or(0, |count| count.min(self.window_count));
+
+        // For each chunk size, the candidate phases of its boundaries.
+        let phases = self
+            .chunk_sizes
+            .iter()
+            .map(|&chunk_size| {
+                self.data_offsets
+                    .iter()
+                    .filter_map(|&data_offset| {
+                        u64::from(data_offset).checked_rem(u64::from(chunk_size))
+                    })
+                    .unique()
+                    .collect_vec()
+            })
+            .collect_vec();
+        let mut sums = phases
+            .iter()
+            .map(|phases| vec![BoundarySums::default(); phases.len()])
+            .collect_vec();
+        let mut q_changes = Vec::new();
+
+        for window in 0..window_count {
+            let start = first_sector + span / window_count * window;
+            let windows = readers
+                .iter_mut()
+                .map(|reader| {
+                    let mut buf = vec![0u8; (window_sectors * 512) as usize];
+                    reader.seek(SeekFrom::Start(start * 512))?;
+                    reader.read_exact(&mut buf)?;
+                    Ok(buf)
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+
+            for buf in &windows {
+                let entropies = buf.chunks(512).map(entropy).collect_vec();
+                for (index, (previous, next)) in entropies.iter().tuple_windows().enumerate() {
+                    // The change between the last sector of one chunk and the
+                    // first sector of the next.
+                    let sector = start + index as u64 + 1;
+                    let change = (next - previous).abs();
+                    for ((&chunk_size, phases), sums) in
+                        self.chunk_sizes.iter().zip(&phases).zip(&mut sums)
+                    {
+                        let chunk_size = u64::from(chunk_size);
+                        for (&phase, sums) in phases.iter().zip(sums.iter_mut()) {
+                            let position = (sector + chunk_size - phase) % chunk_size;
+                            if position == 0 {
+                                sums.boundary += change;
+                                sums.boundary_count += 1;
+                            } else if position == chunk_size / 2 {
+                                sums.midpoint += change;
+                                sums.midpoint_count += 1;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let q_members = (0..window_sectors as usize)
+                .map(|sector| q_member(&windows, sector))
+                .collect_vec();
+            q_changes.extend(q_members.iter().tuple_windows().enumerate().filter_map(
+                |(index, pair)| match pair {
+                    (Some(previous), Some(next)) if previous != next => {
+                        Some(start + index as u64 + 1)
+                    }
+                    _ => None,
+                },
+            ));
+        }
+
+        let mut estimates = self
+            .chunk_sizes
+            .iter()
+            .zip(&phases)
+            .zip(&sums)
+            .map(|((&chunk_size, phases), sums)| {
+                phases
+                    .iter()
+                    .zip(sums)
+                    .map(|(&phase, sums)| MdChunkSizeEstimate {
+                        chunk_size,
+                        boundary_contrast: sums.contrast(),
+                        q_rotation_consistent: if q_changes.is_empty() {
+                            None
+                        } else {
+                            Some(
+                                q_changes
+                                    .iter()
+                                    .all(|&sector| sector % u64::from(chunk_size) == phase),
+                            )
+                        },
+                    })
+                    .max_by(compare_estimates)
+                    .unwrap_or(MdChunkSizeEstimate {
+                        chunk_size,
+                        boundary_contrast: 0.0,
+                        q_rotation_consistent: None,
+                    })
+            })
+            .collect_vec();
+        estimates.sort_by(|a, b| compare_estimates(b, a));
+        Ok(estimates)
+    }
+}
+
+/// Orders estimates from least to most likely: an estimate that Q rotation
+/// rules out is the least likely, and the boundary contrast decides the rest.
+fn compare_estimates(a: &MdChunkSizeEstimate, b: &MdChunkSizeEstimate) -> Ordering {
+    let rank = |estimate: &MdChunkSizeEstimate| estimate.q_rotation_consistent != Some(false);
+    rank(a)
+        .cmp(&rank(b))
+        .then(a.boundary_contrast.total_cmp(&b.boundary_contrast))
+}
+
+/// The Shannon entropy of the bytes of a sector, in bits per byte.
+fn entropy(sector: &[u8]) -> f64 {
+    let mut counts = [0u32; 256];
+    for &byte in sector {
+        counts[usize::from(byte)] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = f64::from(count) / sector.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// The only member whose sector at the given index is the XOR of all the
+/// members, which is where Q must be for the others to be consistent with P.
+fn q_member(windows: &[Vec<u8>], sector: usize) -> Option<usize> {
+    if windows.len() < 4 {
+        return None;
+    }
+    let sectors = windows
+        .iter()
+        .map(|buf| &buf[sector * 512..][..512])
+        .collect_vec();
+    let mut xor = [0u8; 512];
+    for buf in &sectors {
+        xor.iter_mut()
+            .zip(buf.iter())
+            .for_each(|(acc, byte)| *acc ^= byte);
+    }
+    if xor.iter().all(|&byte| byte == 0) {
+        return None;
+    }
+    sectors
+        .iter()
+        .positions(|buf| **buf == xor)
+        .exactly_one()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::pseudo_random_data;
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::search::MdChunkSizeAnalysis;
+    use crate::md::units::{SectorCount, SectorNumber};
+    use itertools::Itertools;
+
+    const CHUNK_SIZE: u32 = 64;
+
+    /// Generates data that looks like a filesystem: files of 4KiB blocks, each
+    /// filled with zeros, text, a repeating pattern or random bytes.
+    fn file_like_data(length: usize, seed: u32) -> Vec<u8> {
+        let choices = pseudo_random_data(length / 4096, seed);
+        let random = pseudo_random_data(length, seed + 1);
+        let mut data = Vec::with_capacity(length);
+        let mut choices = choices.iter();
+        while data.len() < length {
+            let (Some(kind), Some(blocks)) = (choices.next(), choices.next()) else {
+                break;
+            };
+            let file_length = (usize::from(blocks % 16) + 1) * 4096;
+            let file_length = file_length.min(length - data.len());
+            let start = data.len();
+            data.extend((start..start + file_length).map(|index| match kind % 4 {
+                0 => 0,
+                1 => b'a' + random[index] % 26,
+                2 => [0xde, 0xad, 0xbe, 0xef][index % 4],
+                _ => random[index],
+            }));
+        }
+        data.resize(length, 0);
+        data
+    }
+
+    fn analysis() -> MdChunkSizeAnalysis {
+        MdChunkSizeAnalysis {
+            data_offsets: vec![SectorNumber(0), SectorNumber(2048)],
+            ..MdChunkSizeAnalysis::default()
+        }
+    }
+
+    #[test]
+    fn estimate_chunk_size_from_some_members() -> anyhow::Result<()> {
+        let images = member_images(
+            &file_like_data(8 * 1024 * 1024, 8),
+            &MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric),
+            3,
+            CHUNK_SIZE,
+            2048,
+        )?;
+        let devices = images[1..]
+            .iter()
+            .map(|image| InMemoryBlockDevice::new(image.clone(), BlockSize(512)))
+            .collect_vec();
+
+        let estimates = analysis().run(&devices)?;
+        assert_eq!(estimates[0].chunk_size, SectorCount(CHUNK_SIZE));
+        assert!(estimates[0].boundary_contrast > estimates[1].boundary_contrast);
+        assert!(estimates
+            .iter()
+            .all(|estimate| estimate.q_rotation_consistent.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn estimate_chunk_size_from_q_rotation() -> anyhow::Result<()> {
+        let images = member_images(
+            &file_like_data(8 * 1024 * 1024, 9),
+            &MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric),
+            4,
+            CHUNK_SIZE,
+            2048,
+        )?;
+        let devices = images
+            .iter()
+            .map(|image| InMemoryBlockDevice::new(image.clone(), BlockSize(512)))
+            .collect_vec();
+
+        let estimates = analysis().run(&devices)?;
+        assert_eq!(estimates[0].chunk_size, SectorCount(CHUNK_SIZE));
+        for estimate in &estimates {
+            assert_eq!(
+                estimate.q_rotation_consistent,
+                Some(u32::from(estimate.chunk_size) <= CHUNK_SIZE)
+            );
+        }
+        Ok(())
+    }
+}
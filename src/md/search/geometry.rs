@@ -0,0 +1,69 @@
+use crate::block_device::BlockDevice;
+use crate::md::format::MdFormat;
+use crate::md::search::member_sectors::MemberSectors;
+use crate::md::units::SectorNumber;
+
+/// A complete guess at how an array is laid out over a set of devices.
+#[derive(PartialEq, Clone, Debug)]
+pub struct MdGeometry {
+    pub format: MdFormat,
+
+    /// For each device number of the array, the index of the device that
+    /// plays that role, or `None` if that member is missing.
+    pub order: Vec<Option<usize>>,
+
+    pub data_offset: SectorNumber,
+}
+
+impl MdGeometry {
+    /// Reads a sector of the array as this geometry would lay it out. If the
+    /// member holding the data is missing, the sector is reconstructed from
+    /// the P parity. Returns `None` if the sector cannot be read.
+    pub(super) fn read_sector<D: BlockDevice>(
+        &self,
+        sectors: &mut MemberSectors<D>,
+        sector_number: SectorNumber,
+    ) -> Option<Vec<u8>> {
+        let location = self.format.algorithm.compute_sector(
+            sector_number,
+            self.format.chunk_size,
+            self.format.device_count,
+        )?;
+        let sector = u64::from(self.data_offset).checked_add(location.sector_in_device.into())?;
+        match self.order.get(usize::from(location.data_device_number))? {
+            Some(member) => sectors.read(*member, sector).map(|buf| buf.to_vec()),
+            None => {
+                let mut buf = vec![0u8; 512];
+                for (device_number, member) in self.order.iter().enumerate() {
+                    if device_number == usize::from(location.data_device_number)
+                        || Some(device_number) == location.q_device_number.map(usize::from)
+                    {
+                        continue;
+                    }
+                    let member_buf = sectors.read((*member)?, sector)?;
+                    buf.iter_mut()
+                        .zip(member_buf.iter())
+                        .for_each(|(byte, member_byte)| *byte ^= member_byte);
+                }
+                Some(buf)
+            }
+        }
+    }
+
+    /// Reads consecutive bytes of the array.
+    pub(super) fn read_bytes<D: BlockDevice>(
+        &self,
+        sectors: &mut MemberSectors<D>,
+        offset: u64,
+        length: usize,
+    ) -> Option<Vec<u8>> {
+        let first_sector = offset / 512;
+        let last_sector = offset.checked_add(length as u64)?.div_ceil(512);
+        let mut buf = Vec::with_capacity(((last_sector - first_sector) * 512) as usize);
+        for sector in first_sector..last_sector {
+            buf.extend(self.read_sector(sectors, SectorNumber(sector))?);
+        }
+        let start = (offset % 512) as usize;
+        Some(buf[start..start + length].to_vec())
+    }
+}
@@ -0,0 +1,63 @@
+use crate::block_device::{BlockDevice, BlockDeviceReader};
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::rc::Rc;
+
+/// Reads sectors of the devices under search, remembering every sector read
+/// so that candidate geometries that overlap do not read the same sector
+/// twice.
+pub(super) struct MemberSectors<D: BlockDevice> {
+    readers: Vec<BlockDeviceReader<D>>,
+    sizes: Vec<u64>,
+    cache: HashMap<(usize, u64), Option<Rc<[u8]>>>,
+}
+
+impl<D: BlockDevice> MemberSectors<D> {
+    pub fn new(devices: &[D]) -> io::Result<Self> {
+        Ok(Self {
+            readers: devices
+                .iter()
+                .map(|device| Ok(BlockDeviceReader::new(device.try_clone()?)))
+                .collect::<io::Result<_>>()?,
+            sizes: devices
+                .iter()
+                .map(|device| {
+                    Ok(device
+                        .block_count()?
+                        .size_bytes(device.block_size()?)
+                        .ok_or(io::ErrorKind::InvalidInput)?
+                        >> 9)
+                })
+                .collect::<io::Result<_>>()?,
+            cache: HashMap::new(),
+        })
+    }
+
+    pub fn member_count(&self) -> usize {
+        self.readers.len()
+    }
+
+    /// The size of the smallest member, in sectors.
+    pub fn min_sectors(&self) -> u64 {
+        self.sizes.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Returns the given sector of a member, or `None` if it could not be
+    /// read.
+    pub fn read(&mut self, member: usize, sector: u64) -> Option<Rc<[u8]>> {
+        if let Some(cached) = self.cache.get(&(member, sector)) {
+            return cached.clone();
+        }
+        let buf = self.readers.get_mut(member).and_then(|reader| {
+            let mut buf = vec![0u8; 512];
+            reader
+                .seek(SeekFrom::Start(sector.checked_mul(512)?))
+                .ok()?;
+            reader.read_exact(&mut buf).ok()?;
+            Some(Rc::from(buf))
+        });
+        self.cache.insert((member, sector), buf.clone());
+        buf
+    }
+}
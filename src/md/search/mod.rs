@@ -0,0 +1,16 @@
+mod best;
+mod chunk_size;
+mod geometry;
+mod member_sectors;
+mod parity;
+mod score;
+mod search;
+
+#[allow(unused_imports)]
+pub use self::{
+    chunk_size::{MdChunkSizeAnalysis, MdChunkSizeEstimate},
+    geometry::MdGeometry,
+    parity::{MdParityCandidate, MdParityInference, MdParityReport},
+    score::MdGeometryScore,
+    search::{MdGeometryCandidate, MdGeometrySearch},
+};
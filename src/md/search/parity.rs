@@ -0,0 +1,459 @@
+use crate::block_device::BlockDevice;
+use crate::md::raid6::q_syndrome;
+use crate::md::search::member_sectors::MemberSectors;
+use crate::md::search::{MdGeometry, MdGeometrySearch};
+use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+use crate::md::{algorithm::MdAlgorithm, format::MdFormat};
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+/// Infers the layout and member order of a RAID5 or RAID6 array from the
+/// parity of the data on its members alone, without any knowledge of what
+/// the array holds. Every member of the array must be present.
+///
+/// RAID5 parity is the same whichever member holds it, so it can only tell
+/// RAID5 apart from RAID6, and narrow down the parity member in rows where
+/// a single data chunk is in use. RAID6 Q syndromes depend on the order of
+/// the data chunks, so they pin down the layout and order.
+#[derive(Clone, Debug)]
+pub struct MdParityInference {
+    pub algorithms: Vec<MdAlgorithm>,
+    pub chunk_sizes: Vec<SectorCount<u32>>,
+    pub data_offsets: Vec<SectorNumber>,
+    pub row_sample_count: u64,
+    pub max_results: usize,
+}
+
+/// Geometries that put parity and data on the same members at every sector
+/// of the devices, and so are equally consistent with any data.
+#[derive(Clone, Debug)]
+pub struct MdParityCandidate {
+    pub geometries: Vec<MdGeometry>,
+    pub rows_consistent: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct MdParityReport {
+    /// Candidates, most consistent first.
+    pub candidates: Vec<MdParityCandidate>,
+
+    /// Sampled rows that could be read from every member and were not
+    /// entirely zero.
+    pub rows_checked: u32,
+
+    /// The fraction of rows consistent with the best candidate, shared
+    /// equally between all the candidates kept that are as consistent as it
+    /// is.
+    pub confidence: f64,
+}
+
+impl Default for MdParityInference {
+    fn default() -> Self {
+        let search = MdGeometrySearch::default();
+        Self {
+            algorithms: search.algorithms,
+            chunk_sizes: search.chunk_sizes,
+            data_offsets: search.data_offsets,
+            row_sample_count: 256,
+            max_results: 20,
+        }
+    }
+}
+
+/// The sectors of every member at one offset into the devices.
+struct Row {
+    sector: u64,
+    members: Vec<Rc<[u8]>>,
+
+    /// Members whose sector is not entirely zero.
+    nonzero_members: Vec<usize>,
+
+    /// Whether the XOR of every member is zero.
+    xor_is_zero: bool,
+
+    /// Members whose sector is the XOR of all the others, which is where Q
+    /// must be for the rest of the row to be consistent with P.
+    q_members: Vec<usize>,
+}
+
+/// The members that hold P, Q and each data chunk, in order, for a row.
+#[derive(Eq, PartialEq, Clone, Hash, Debug)]
+pub(super) struct RowRoles {
+    pub p_member: usize,
+    pub q_member: Option<usize>,
+    pub data_members: Vec<usize>,
+}
+
+impl MdParityInference {
+    pub fn run<D: BlockDevice>(&self, devices: &[D]) -> io::Result<MdParityReport> {
+        let mut sectors = MemberSectors::new(devices)?;
+        let member_count = sectors.member_count();
+        let device_count = u32::try_from(member_count)
+            .map(DeviceCount)
+            .map_err(|_| io::ErrorKind::InvalidInput)?;
+        let min_sectors = sectors.min_sectors();
+
+        // Only sample rows that lie in the data area of every candidate, so
+        // that all candidates are judged on the same rows.
+        let data_offsets = self
+            .data_offsets
+            .iter()
+            .copied()
+            .filter(|&data_offset| u64::from(data_offset) < min_sectors)
+            .collect_vec();
+        let first_sector = data_offsets.iter().copied().max().map_or(0, u64::from);
+        let rows = (0..self.row_sample_count)
+            .map(|sample| {
+                first_sector
+                    + (u128::from(min_sectors - first_sector) * u128::from(sample)
+                        / u128::from(self.row_sample_count)) as u64
+            })
+            .dedup()
+            .filter_map(|sector| Row::read(&mut sectors, sector))
+            .collect_vec();
+
+        // The best candidates so far, in the order found, each with the
+        // signature that geometries are grouped by.
+        let mut candidates: Vec<(_, MdParityCandidate)> = Vec::new();
+        let mut syndromes = HashMap::new();
+        for algorithm in &self.algorithms {
+            if algorithm
+                .parity_device_count()
+                .is_none_or(|parity_count| u32::from(parity_count) * 2 > u32::from(device_count))
+            {
+                continue;
+            }
+            for &data_offset in &data_offsets {
+                let format = MdFormat {
+                    algorithm: algorithm.clone(),
+                    device_count,
+                    sectors_per_device: SectorCount(min_sectors - u64::from(data_offset)),
+                    chunk_size: SectorCount(0),
+                };
+                let first_row = Row::read(&mut sectors, data_offset.into());
+                for order in (0..member_count).permutations(member_count) {
+                    let mut geometry = MdGeometry {
+                        format: format.clone(),
+                        order: order.into_iter().map(Some).collect(),
+                        data_offset,
+                    };
+
+                    // The first row of the data area is in the first stripe
+                    // whatever the chunk size, so its parity rules out an
+                    // order for all of them.
+                    if let (Some(first_row), Some(&chunk_size)) =
+                        (&first_row, self.chunk_sizes.first())
+                    {
+                        geometry.format.chunk_size = chunk_size;
+                        if row_roles(&geometry, first_row.sector).is_some_and(|roles| {
+                            !first_row
+                                .is_consistent(&roles, |roles| first_row.q_syndrome_matches(roles))
+                        }) {
+                            continue;
+                        }
+                    }
+
+                    for &chunk_size in &self.chunk_sizes {
+                        geometry.format.chunk_size = chunk_size;
+                        let Some(signature) = signature(&geometry, first_sector) else {
+                            continue;
+                        };
+                        if let Some((_, candidate)) =
+                            candidates.iter_mut().find(|(kept, _)| *kept == signature)
+                        {
+                            candidate.geometries.push(geometry.clone());
+                            continue;
+                        }
+                        let rows_consistent = rows
+                            .iter()
+                            .enumerate()
+                            .filter(|(row_index, row)| {
+                                row_roles(&geometry, row.sector).is_some_and(|roles| {
+                                    row.is_consistent(&roles, |roles| {
+                                        *syndromes
+                                            .entry((*row_index, roles.clone()))
+                                            .or_insert_with(|| row.q_syndrome_matches(roles))
+                                    })
+                                })
+                            })
+                            .count() as u32;
+
+                        // Make room by dropping the least consistent, the
+                        // last found among those tied, if this is better.
+                        if candidates.len() >= self.max_results {
+                            let Some((worst, _)) = candidates
+                                .iter()
+                                .enumerate()
+                                .rev()
+                                .min_by_key(|(_, (_, candidate))| candidate.rows_consistent)
+                                .filter(|(_, (_, candidate))| {
+                                    candidate.rows_consistent < rows_consistent
+                                })
+                            else {
+                                continue;
+                            };
+                            candidates.remove(worst);
+                        }
+                        candidates.push((
+                            signature,
+                            MdParityCandidate {
+                                geometries: vec![geometry.clone()],
+                                rows_consistent,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut candidates = candidates
+            .into_iter()
+            .map(|(_, candidate)| candidate)
+            .collect_vec();
+        candidates.sort_by_key(|candidate| Reverse(candidate.rows_consistent));
+        let rows_checked = rows.len() as u32;
+        let confidence = match candidates.first() {
+            Some(best) if rows_checked > 0 => {
+                let tied = candidates
+                    .iter()
+                    .take_while(|candidate| candidate.rows_consistent == best.rows_consistent)
+                    .count();
+                f64::from(best.rows_consistent) / f64::from(rows_checked) / tied as f64
+            }
+            _ => 0.0,
+        };
+        Ok(MdParityReport {
+            candidates,
+            rows_checked,
+            confidence,
+        })
+    }
+}
+
+impl Row {
+    /// Reads a row, or returns `None` if it cannot be read in full or is
+    /// entirely zero, which is consistent with any geometry.
+    fn read<D: BlockDevice>(sectors: &mut MemberSectors<D>, sector: u64) -> Option<Self> {
+        let members = (0..sectors.member_count())
+            .map(|member| sectors.read(member, sector))
+            .collect::<Option<Vec<_>>>()?;
+        let nonzero_members = members
+            .iter()
+            .positions(|buf| buf.iter().any(|&byte| byte != 0))
+            .collect_vec();
+        if nonzero_members.is_empty() {
+            return None;
+        }
+        let mut xor = vec![0u8; 512];
+        for buf in &members {
+            xor.iter_mut()
+                .zip(buf.iter())
+                .for_each(|(acc, byte)| *acc ^= byte);
+        }
+        let q_members = members.iter().positions(|buf| **buf == *xor).collect_vec();
+        Some(Self {
+            sector,
+            members,
+            nonzero_members,
+            xor_is_zero: xor.iter().all(|&byte| byte == 0),
+            q_members,
+        })
+    }
+
+    fn is_consistent<F>(&self, roles: &RowRoles, mut q_syndrome_matches: F) -> bool
+    where
+        F: FnMut(&RowRoles) -> bool,
+    {
+        match roles.q_member {
+            // With RAID5, every member XORs to zero. If only two members are
+            // in use, one of them holds data and the other its parity.
+            None => {
+                self.xor_is_zero
+                    && (self.nonzero_members.len() != 2
+                        || self.nonzero_members.contains(&roles.p_member))
+            }
+            Some(q_member) => self.q_members.contains(&q_member) && q_syndrome_matches(roles),
+        }
+    }
+
+    fn q_syndrome_matches(&self, roles: &RowRoles) -> bool {
+        let data = roles
+            .data_members
+            .iter()
+            .map(|&member| &*self.members[member])
+            .collect_vec();
+        roles
+            .q_member
+            .is_some_and(|q_member| q_syndrome(&data) == *self.members[q_member])
+    }
+}
+
+/// Works out which member plays which part in the row at the given sector
+/// of the devices.
+pub(super) fn row_roles(geometry: &MdGeometry, sector: u64) -> Option<RowRoles> {
+    let format = &geometry.format;
+    let sectors_per_chunk = u64::from(format.chunk_size);
+    let data_device_count = u64::from(format.data_device_count()?);
+    let sector_in_device = sector.checked_sub(geometry.data_offset.into())?;
+    let stripe = sector_in_device.checked_div(sectors_per_chunk)?;
+    let member = |device_number: usize| *geometry.order.get(device_number)?;
+
+    let mut roles = None;
+    let mut data_members = Vec::new();
+    for data_index in 0..data_device_count {
+        let location = format.algorithm.compute_sector(
+            SectorNumber(
+                stripe
+                    .checked_mul(data_device_count)?
+                    .checked_add(data_index)?
+                    .checked_mul(sectors_per_chunk)?
+                    .checked_add(sector_in_device % sectors_per_chunk)?,
+            ),
+            format.chunk_size,
+            format.device_count,
+        )?;
+        data_members.push(member(location.data_device_number.into())?);
+        roles = Some((
+            member(location.p_device_number.into())?,
+            match location.q_device_number {
+                Some(q_device_number) => Some(member(q_device_number.into())?),
+                None => None,
+            },
+        ));
+    }
+    let (p_member, q_member) = roles?;
+    Some(RowRoles {
+        p_member,
+        q_member,
+        data_members,
+    })
+}
+
+/// Describes where a geometry puts parity and data, over enough rows for the
+/// parity of every layout to have come full circle. Geometries with the same
+/// signature are indistinguishable by their parity.
+fn signature(
+    geometry: &MdGeometry,
+    first_sector: u64,
+) -> Option<(SectorCount<u32>, u64, Vec<RowRoles>)> {
+    let sectors_per_chunk = u64::from(geometry.format.chunk_size);
+    let device_count = u64::from(geometry.format.device_count);
+    let rows = (0..device_count * device_count.saturating_sub(1))
+        .map(|stripe| {
+            row_roles(
+                geometry,
+                stripe
+                    .checked_mul(sectors_per_chunk)?
+                    .checked_add(first_sector)?,
+            )
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((
+        geometry.format.chunk_size,
+        u64::from(geometry.data_offset).checked_rem(sectors_per_chunk)?,
+        rows,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::{pseudo_random_data, raid5_fixture, CHUNK_SIZE, DATA_OFFSET};
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::search::MdParityInference;
+    use crate::md::units::{SectorCount, SectorNumber};
+    use itertools::Itertools;
+
+    fn inference() -> MdParityInference {
+        MdParityInference {
+            data_offsets: vec![SectorNumber(DATA_OFFSET)],
+            row_sample_count: 64,
+            ..MdParityInference::default()
+        }
+    }
+
+    #[test]
+    fn infer_raid6_order() -> anyhow::Result<()> {
+        let images = member_images(
+            &pseudo_random_data(256 * 1024, 6),
+            &MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric),
+            4,
+            CHUNK_SIZE,
+            DATA_OFFSET,
+        )?;
+        let devices = [3, 1, 0, 2]
+            .into_iter()
+            .map(|index| InMemoryBlockDevice::new(images[index].clone(), BlockSize(512)))
+            .collect_vec();
+
+        let report = inference().run(&devices)?;
+        assert_eq!(report.rows_checked, 64);
+        assert_eq!(report.confidence, 1.0);
+        let best = &report.candidates[0];
+        assert_eq!(best.rows_consistent, 64);
+        assert!(best
+            .geometries
+            .iter()
+            .any(|geometry| geometry.format.algorithm
+                == MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric)
+                && geometry.format.chunk_size == SectorCount(CHUNK_SIZE)
+                && geometry.order == vec![Some(2), Some(1), Some(3), Some(0)]));
+        assert!(report.candidates[1].rows_consistent < 64);
+
+        let report = MdParityInference {
+            max_results: 2,
+            ..inference()
+        }
+        .run(&devices)?;
+        assert_eq!(report.candidates.len(), 2);
+        assert_eq!(report.candidates[0].rows_consistent, 64);
+        assert_eq!(report.confidence, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn infer_raid5_order() -> anyhow::Result<()> {
+        // Only the first data chunk of each stripe is in use, so each row
+        // holds one data chunk and its parity.
+        let mut data = pseudo_random_data(64 * 1024, 7);
+        for chunk in data
+            .chunks_mut(CHUNK_SIZE as usize * 512)
+            .skip(1)
+            .step_by(2)
+        {
+            chunk.fill(0);
+        }
+        let images = raid5_fixture([7; 16]).present_member_images(&data);
+        let devices = [1, 2, 0]
+            .into_iter()
+            .map(|index| InMemoryBlockDevice::new(images[index].clone(), BlockSize(512)))
+            .collect_vec();
+
+        let report = inference().run(&devices)?;
+        let best = &report.candidates[0];
+        assert_eq!(best.rows_consistent, report.rows_checked);
+        assert!(report.confidence < 1.0);
+        assert!(report
+            .candidates
+            .iter()
+            .take_while(|candidate| candidate.rows_consistent == best.rows_consistent)
+            .flat_map(|candidate| &candidate.geometries)
+            .all(|geometry| matches!(geometry.format.algorithm, MdAlgorithm::Raid5(_))));
+        assert!(report
+            .candidates
+            .iter()
+            .take_while(|candidate| candidate.rows_consistent == best.rows_consistent)
+            .flat_map(|candidate| &candidate.geometries)
+            .any(|geometry| geometry.format.algorithm
+                == MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric)
+                && geometry.format.chunk_size == SectorCount(CHUNK_SIZE)
+                && geometry.order == vec![Some(2), Some(0), Some(1)]));
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt::{Display, Formatter};
+
+/// The evidence gathered for one candidate geometry.
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Debug, Default)]
+pub struct MdGeometryScore {
+    /// Whether an ext4 superblock was found at byte 1024 of the array.
+    pub ext4_superblock: bool,
+    pub ext4_backup_superblocks_found: u32,
+    pub ext4_backup_superblocks_checked: u32,
+
+    /// Whether the chunk size and data device count that the ext4 superblock
+    /// records agree with the candidate, if it records any.
+    pub ext4_raid_hints_match: Option<bool>,
+
+    /// Sampled parity rows whose contents were consistent with the
+    /// candidate's parity. Rows that are entirely zero, or that could not
+    /// be read in full, are not counted.
+    pub parity_rows_consistent: u32,
+    pub parity_rows_checked: u32,
+}
+
+impl MdGeometryScore {
+    /// Orders candidates: filesystem structures found in the right places
+    /// are the strongest evidence, then agreement with the RAID hints of the
+    /// filesystem, and parity consistency breaks ties.
+    pub fn rank(&self) -> (u32, bool, i64) {
+        (
+            u32::from(self.ext4_superblock) + self.ext4_backup_superblocks_found,
+            self.ext4_raid_hints_match == Some(true),
+            2 * i64::from(self.parity_rows_consistent) - i64::from(self.parity_rows_checked),
+        )
+    }
+}
+
+impl Display for MdGeometryScore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ext4 superblock {}, {}/{} ext4 backup superblocks{}, {}/{} parity rows consistent",
+            if self.ext4_superblock {
+                "found"
+            } else {
+                "not found"
+            },
+            self.ext4_backup_superblocks_found,
+            self.ext4_backup_superblocks_checked,
+            match self.ext4_raid_hints_match {
+                Some(true) => ", ext4 RAID hints match",
+                Some(false) => ", ext4 RAID hints differ",
+                None => "",
+            },
+            self.parity_rows_consistent,
+            self.parity_rows_checked
+        )
+    }
+}
@@ -0,0 +1,547 @@
+use crate::block_device::BlockDevice;
+use crate::ext4::Ext4Superblock;
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::format::MdFormat;
+use crate::md::raid5::Raid5Algorithm;
+use crate::md::raid6::q_syndrome;
+use crate::md::raid6::Raid6Algorithm;
+use crate::md::raid_hints::MdExt4RaidHints;
+use crate::md::search::best::BestCandidates;
+use crate::md::search::member_sectors::MemberSectors;
+use crate::md::search::parity::row_roles;
+use crate::md::search::{MdGeometry, MdGeometryScore};
+use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// Enumerates candidate geometries for a set of devices whose superblocks
+/// are lost, and ranks them by how well the data on the devices fits each
+/// one.
+#[derive(Clone, Debug)]
+pub struct MdGeometrySearch {
+    pub algorithms: Vec<MdAlgorithm>,
+    pub chunk_sizes: Vec<SectorCount<u32>>,
+    pub data_offsets: Vec<SectorNumber>,
+
+    /// The number of members of the array that are not among the devices
+    /// searched.
+    pub missing_device_count: u32,
+
+    pub parity_sample_count: u64,
+    pub max_ext4_backup_superblocks: usize,
+    pub max_results: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct MdGeometryCandidate {
+    pub geometry: MdGeometry,
+    pub score: MdGeometryScore,
+}
+
+impl Default for MdGeometrySearch {
+    fn default() -> Self {
+        Self {
+            algorithms: (0..=5)
+                .filter_map(Raid5Algorithm::from_layout)
+                .map(MdAlgorithm::Raid5)
+                .chain(
+                    (0..=20)
+                        .filter_map(Raid6Algorithm::from_layout)
+                        .map(MdAlgorithm::Raid6),
+                )
+                .collect(),
+            // 4KiB to 1MiB.
+            chunk_sizes: (3..=11).map(|shift| SectorCount(1 << shift)).collect(),
+            // The data offsets chosen by the various versions of mdadm.
+            data_offsets: [
+                0, 8, 16, 24, 2048, 4096, 8192, 16384, 32768, 65536, 131072, 262144,
+            ]
+            .into_iter()
+            .map(SectorNumber)
+            .collect(),
+            missing_device_count: 0,
+            parity_sample_count: 64,
+            max_ext4_backup_superblocks: 16,
+            max_results: 20,
+        }
+    }
+}
+
+const EXT4_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_MAGIC_OFFSET: usize = 56;
+
+impl MdGeometrySearch {
+    /// Scores every combination of algorithm, chunk size, data offset and
+    /// member order, and returns the best. The number of orders grows with
+    /// the factorial of the device count, so this is only practical for
+    /// small arrays, though orders whose first row contradicts their parity
+    /// are dropped before any chunk size is tried, as are those that do not
+    /// put the start of an ext4 filesystem found on the devices at the start
+    /// of the array.
+    pub fn run<D: BlockDevice>(&self, devices: &[D]) -> io::Result<Vec<MdGeometryCandidate>> {
+        let mut sectors = MemberSectors::new(devices)?;
+        let member_count = sectors.member_count();
+        let device_count = u32::try_from(member_count)
+            .ok()
+            .and_then(|count| count.checked_add(self.missing_device_count))
+            .map(DeviceCount)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let min_sectors = sectors.min_sectors();
+
+        // If the magic number of an ext4 superblock is found at the start of
+        // the data area of any member, then only geometries that put the
+        // start of the array there are worth scoring.
+        let ext4_starts: HashSet<_> = (0..member_count)
+            .cartesian_product(&self.data_offsets)
+            .filter(|&(member, data_offset)| {
+                u64::from(*data_offset)
+                    .checked_add(EXT4_SUPERBLOCK_OFFSET / 512)
+                    .and_then(|sector| sectors.read(member, sector))
+                    .is_some_and(|buf| has_ext4_magic(&buf))
+            })
+            .map(|(member, data_offset)| (member, *data_offset))
+            .collect();
+
+        // The RAID hints of any filesystem found there suggest a chunk size
+        // that might not otherwise be tried.
+        let chunk_sizes = self
+            .chunk_sizes
+            .iter()
+            .copied()
+            .chain(ext4_starts.iter().filter_map(|&(member, data_offset)| {
+                let sector = u64::from(data_offset) + EXT4_SUPERBLOCK_OFFSET / 512;
+                let buf = (sector..sector + 2)
+                    .map(|sector| sectors.read(member, sector))
+                    .collect::<Option<Vec<_>>>()?
+                    .concat();
+                MdExt4RaidHints::from_superblock(&Ext4Superblock::new(buf))?.chunk_size
+            }))
+            .unique()
+            .collect_vec();
+        let Some(&first_chunk_size) = chunk_sizes.first() else {
+            return Ok(Vec::new());
+        };
+
+        let mut parity_cache = HashMap::new();
+        let mut best = BestCandidates::new(self.max_results);
+        for algorithm in &self.algorithms {
+            // Like mdadm, require at least as many data devices as parity
+            // devices.
+            if algorithm
+                .parity_device_count()
+                .is_none_or(|parity_count| u32::from(parity_count) * 2 > u32::from(device_count))
+            {
+                continue;
+            }
+            for &data_offset in &self.data_offsets {
+                let Some(sectors_per_device) = min_sectors.checked_sub(data_offset.into()) else {
+                    continue;
+                };
+                // Each order as the slot of every device, so that orders
+                // with missing members are not repeated.
+                for slots in (0..usize::from(device_count)).permutations(member_count) {
+                    let mut order = vec![None; usize::from(device_count)];
+                    for (member, slot) in slots.into_iter().enumerate() {
+                        order[slot] = Some(member);
+                    }
+
+                    // The first row is in the first stripe whatever the chunk
+                    // size, so its parity rules out an order for all of them.
+                    let mut geometry = MdGeometry {
+                        format: MdFormat {
+                            algorithm: algorithm.clone(),
+                            device_count,
+                            sectors_per_device: SectorCount(sectors_per_device),
+                            chunk_size: first_chunk_size,
+                        },
+                        order,
+                        data_offset,
+                    };
+                    if row_parity_consistent(&geometry, 0, &mut sectors, &mut parity_cache)
+                        == Some(false)
+                    {
+                        continue;
+                    }
+
+                    for &chunk_size in &chunk_sizes {
+                        let Some(start) = algorithm.compute_sector(
+                            SectorNumber(EXT4_SUPERBLOCK_OFFSET / 512),
+                            chunk_size,
+                            device_count,
+                        ) else {
+                            continue;
+                        };
+                        if let Some(member) = geometry.order[usize::from(start.data_device_number)]
+                        {
+                            if !ext4_starts.is_empty()
+                                && !ext4_starts.contains(&(member, data_offset))
+                            {
+                                continue;
+                            }
+                        }
+                        geometry.format.chunk_size = chunk_size;
+                        let score = self.score(&geometry, &mut sectors, &mut parity_cache);
+                        best.offer(
+                            score.rank(),
+                            MdGeometryCandidate {
+                                geometry: geometry.clone(),
+                                score,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(best.into_sorted_vec())
+    }
+
+    fn score<D: BlockDevice>(
+        &self,
+        geometry: &MdGeometry,
+        sectors: &mut MemberSectors<D>,
+        parity_cache: &mut HashMap<(u64, Vec<usize>), Option<bool>>,
+    ) -> MdGeometryScore {
+        let mut score = MdGeometryScore::default();
+        self.score_ext4(geometry, sectors, &mut score);
+        self.score_parity(geometry, sectors, parity_cache, &mut score);
+        score
+    }
+
+    fn score_ext4<D: BlockDevice>(
+        &self,
+        geometry: &MdGeometry,
+        sectors: &mut MemberSectors<D>,
+        score: &mut MdGeometryScore,
+    ) {
+        let Some(superblock) = geometry
+            .read_bytes(
+                sectors,
+                EXT4_SUPERBLOCK_OFFSET,
+                Ext4Superblock::<&[u8]>::SIZE,
+            )
+            .map(Ext4Superblock::new)
+            .filter(|superblock| superblock.valid_magic())
+        else {
+            return;
+        };
+        score.ext4_superblock = true;
+        score.ext4_raid_hints_match = MdExt4RaidHints::from_superblock(&superblock)
+            .map(|hints| hints.matches(&geometry.format));
+
+        let array_size = geometry
+            .format
+            .data_sector_count()
+            .map_or(0, |count| u64::from(count).saturating_mul(512));
+        let block_size = superblock.block_size_bytes();
+        let blocks_per_group = u64::from(superblock.blocks_per_group());
+        let blocks_count = u64::from(superblock.blocks_count());
+        if block_size == 0 || blocks_per_group == 0 {
+            return;
+        }
+
+        let backups = (1..)
+            .map_while(|group: u32| {
+                let block = u64::from(group)
+                    .checked_mul(blocks_per_group)?
+                    .checked_add(superblock.first_data_block().into())
+                    .filter(|&block| block < blocks_count)?;
+                let offset = block
+                    .checked_mul(block_size)
+                    .filter(|&offset| offset < array_size)?;
+                Some((group, offset))
+            })
+            .filter(|&(group, _)| superblock.has_backup_superblock(group))
+            .take(self.max_ext4_backup_superblocks);
+        for (group, offset) in backups {
+            score.ext4_backup_superblocks_checked += 1;
+            if geometry
+                .read_bytes(sectors, offset, Ext4Superblock::<&[u8]>::SIZE)
+                .map(Ext4Superblock::new)
+                .is_some_and(|backup| {
+                    backup.valid_magic()
+                        && u32::from(backup.block_group_number()) == group & 0xffff
+                        && backup.blocks_per_group() == superblock.blocks_per_group()
+                })
+            {
+                score.ext4_backup_superblocks_found += 1;
+            }
+        }
+    }
+
+    fn score_parity<D: BlockDevice>(
+        &self,
+        geometry: &MdGeometry,
+        sectors: &mut MemberSectors<D>,
+        parity_cache: &mut HashMap<(u64, Vec<usize>), Option<bool>>,
+        score: &mut MdGeometryScore,
+    ) {
+        let sectors_per_device = u64::from(geometry.format.sectors_per_device);
+        if self.parity_sample_count == 0 {
+            return;
+        }
+
+        for sample in 0..self.parity_sample_count {
+            let sector_in_device = (u128::from(sectors_per_device) * u128::from(sample)
+                / u128::from(self.parity_sample_count)) as u64;
+            if let Some(consistent) =
+                row_parity_consistent(geometry, sector_in_device, sectors, parity_cache)
+            {
+                score.parity_rows_checked += 1;
+                if consistent {
+                    score.parity_rows_consistent += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Checks the parity of the row at `sector_in_device`: that the XOR of every
+/// member but Q is zero, and that Q, if any, is the syndrome of the data in
+/// the order the geometry puts it. Returns `None` if the row is entirely
+/// zero, which is consistent with any geometry, or if it cannot be checked
+/// because a member is missing or could not be read.
+fn row_parity_consistent<D: BlockDevice>(
+    geometry: &MdGeometry,
+    sector_in_device: u64,
+    sectors: &mut MemberSectors<D>,
+    parity_cache: &mut HashMap<(u64, Vec<usize>), Option<bool>>,
+) -> Option<bool> {
+    let format = &geometry.format;
+    let data_device_count = u64::from(format.data_device_count()?);
+    let sectors_per_chunk = u64::from(format.chunk_size);
+
+    // Find the array sector at the start of the row, so as to learn which
+    // member holds Q for that row.
+    let location = format.algorithm.compute_sector(
+        SectorNumber(
+            (sector_in_device.checked_div(sectors_per_chunk)?)
+                .checked_mul(data_device_count)?
+                .checked_mul(sectors_per_chunk)?
+                .checked_add(sector_in_device % sectors_per_chunk)?,
+        ),
+        format.chunk_size,
+        format.device_count,
+    )?;
+    let sector = u64::from(geometry.data_offset).checked_add(location.sector_in_device.into())?;
+
+    // The members whose XOR should be zero: every member but Q. If any of
+    // them is missing, the row cannot be checked.
+    let members = geometry
+        .order
+        .iter()
+        .enumerate()
+        .filter(|&(device_number, _)| {
+            Some(device_number) != location.q_device_number.map(usize::from)
+        })
+        .map(|(_, member)| *member)
+        .collect::<Option<Vec<_>>>()?;
+    let consistent = (*parity_cache
+        .entry((sector, members.iter().copied().sorted().collect()))
+        .or_insert_with(|| xor_is_zero(sectors, sector, &members)))?;
+    if !consistent || location.q_device_number.is_none() {
+        return Some(consistent);
+    }
+
+    // P fits, so check Q, which tells apart the layouts that put P in the
+    // same place but the data in a different order. Without every member,
+    // P alone decides.
+    let Some(roles) = row_roles(geometry, sector) else {
+        return Some(true);
+    };
+    let data = roles
+        .data_members
+        .iter()
+        .map(|&member| sectors.read(member, sector))
+        .collect::<Option<Vec<_>>>()?;
+    let q = sectors.read(roles.q_member?, sector)?;
+    Some(q_syndrome(&data.iter().map(|data| &data[..]).collect_vec()) == *q)
+}
+
+/// Checks that the XOR of the given members is zero. Returns `None` if the
+/// row is entirely zero, or if any of the members could not be read.
+fn xor_is_zero<D: BlockDevice>(
+    sectors: &mut MemberSectors<D>,
+    sector: u64,
+    members: &[usize],
+) -> Option<bool> {
+    let mut xor = [0u8; 512];
+    let mut all_zero = true;
+    for &member in members {
+        let buf = sectors.read(member, sector)?;
+        all_zero &= buf.iter().all(|&byte| byte == 0);
+        xor.iter_mut()
+            .zip(buf.iter())
+            .for_each(|(acc, byte)| *acc ^= byte);
+    }
+    if all_zero {
+        None
+    } else {
+        Some(xor.iter().all(|&byte| byte == 0))
+    }
+}
+
+fn has_ext4_magic(sector: &[u8]) -> bool {
+    sector.get(EXT4_MAGIC_OFFSET..EXT4_MAGIC_OFFSET + 2) == Some(&0xef53u16.to_le_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block_device::{BlockSize, InMemoryBlockDevice};
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::fixture::member_images;
+    use crate::md::fixture::test::{pseudo_random_data, raid5_fixture, CHUNK_SIZE, DATA_OFFSET};
+    use crate::md::raid5::Raid5Algorithm;
+    use crate::md::raid6::Raid6Algorithm;
+    use crate::md::search::MdGeometrySearch;
+    use crate::md::units::{SectorCount, SectorNumber};
+    use byteorder::{ByteOrder, LittleEndian};
+    use itertools::Itertools;
+
+    const BLOCKS_PER_GROUP: u32 = 200;
+    const BLOCK_COUNT: u32 = 8192;
+
+    /// Fills an array with random data, overlaid with the superblocks of an
+    /// ext4 filesystem with 1KiB blocks and sparse superblock backups. With
+    /// a group size that is not a power of two, the backups land at
+    /// different offsets within a chunk for each candidate chunk size.
+    fn ext4_array_data(raid_stride: u16, raid_stripe_width: u32) -> Vec<u8> {
+        let mut data = pseudo_random_data(BLOCK_COUNT as usize * 1024, 4);
+        for group in [0, 1, 3, 5, 7, 9, 25, 27] {
+            let superblock = &mut data[(1 + group * BLOCKS_PER_GROUP as usize) * 1024..][..1024];
+            superblock.fill(0);
+            LittleEndian::write_u32(&mut superblock[4..], BLOCK_COUNT);
+            LittleEndian::write_u32(&mut superblock[20..], 1);
+            LittleEndian::write_u32(&mut superblock[32..], BLOCKS_PER_GROUP);
+            LittleEndian::write_u32(&mut superblock[36..], BLOCKS_PER_GROUP);
+            LittleEndian::write_u16(&mut superblock[56..], 0xef53);
+            LittleEndian::write_u16(&mut superblock[90..], group as u16);
+            LittleEndian::write_u32(&mut superblock[100..], 1);
+            LittleEndian::write_u16(&mut superblock[356..], raid_stride);
+            LittleEndian::write_u32(&mut superblock[368..], raid_stripe_width);
+        }
+        data
+    }
+
+    #[test]
+    fn search_finds_raid5_geometry() -> anyhow::Result<()> {
+        let images = raid5_fixture([5; 16]).present_member_images(&ext4_array_data(0, 0));
+        let devices = [2, 0, 1]
+            .into_iter()
+            .map(|index| InMemoryBlockDevice::new(images[index].clone(), BlockSize(512)))
+            .collect_vec();
+
+        let candidates = MdGeometrySearch::default().run(&devices)?;
+        let best = &candidates[0];
+        assert_eq!(
+            best.geometry.format.algorithm,
+            MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric)
+        );
+        assert_eq!(best.geometry.format.chunk_size, SectorCount(CHUNK_SIZE));
+        assert_eq!(best.geometry.data_offset, SectorNumber(DATA_OFFSET));
+        assert_eq!(best.geometry.order, vec![Some(1), Some(2), Some(0)]);
+        assert!(best.score.ext4_superblock);
+        assert_eq!(best.score.ext4_backup_superblocks_found, 7);
+        assert_eq!(best.score.ext4_backup_superblocks_checked, 7);
+        assert_eq!(
+            best.score.parity_rows_consistent,
+            best.score.parity_rows_checked
+        );
+
+        // Every backup superblock lies in the first data chunk of a stripe,
+        // where the mirror image of the layout on three devices puts the same
+        // data, so that geometry is exactly as plausible.
+        let runner_up = &candidates[1];
+        assert_eq!(runner_up.score.rank(), best.score.rank());
+        assert_eq!(
+            runner_up.geometry.format.algorithm,
+            MdAlgorithm::Raid5(Raid5Algorithm::RightSymmetric)
+        );
+        assert_eq!(runner_up.geometry.order, vec![Some(2), Some(1), Some(0)]);
+        assert!(candidates[2].score.rank() < best.score.rank());
+        Ok(())
+    }
+
+    #[test]
+    fn search_tries_hinted_chunk_size() -> anyhow::Result<()> {
+        // A chunk size of 12KiB, which is not among those searched by
+        // default, and which the filesystem records as 12 blocks.
+        let mut data = ext4_array_data(12, 24);
+        data.truncate(data.len() / (24 * 1024) * (24 * 1024));
+        let images = member_images(
+            &data,
+            &MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric),
+            3,
+            24,
+            DATA_OFFSET,
+        )?;
+        let devices = images
+            .iter()
+            .map(|image| InMemoryBlockDevice::new(image.clone(), BlockSize(512)))
+            .collect_vec();
+
+        let search = MdGeometrySearch::default();
+        assert!(!search.chunk_sizes.contains(&SectorCount(24)));
+        let candidates = search.run(&devices)?;
+        let best = &candidates[0];
+        assert_eq!(best.geometry.format.chunk_size, SectorCount(24));
+        assert_eq!(best.geometry.data_offset, SectorNumber(DATA_OFFSET));
+        assert_eq!(best.score.ext4_raid_hints_match, Some(true));
+        assert!(candidates
+            .iter()
+            .filter(|candidate| candidate.geometry.format.chunk_size != SectorCount(24))
+            .all(|candidate| candidate.score.ext4_raid_hints_match != Some(true)));
+        Ok(())
+    }
+
+    #[test]
+    fn search_tells_raid6_layouts_apart_by_q() -> anyhow::Result<()> {
+        // Both layouts put P and Q on the same members, but the data in a
+        // different order from the third stripe on.
+        let algorithm = MdAlgorithm::Raid6(Raid6Algorithm::LeftSymmetric);
+        let images = member_images(
+            &pseudo_random_data(256 * 1024, 9),
+            &algorithm,
+            4,
+            CHUNK_SIZE,
+            DATA_OFFSET,
+        )?;
+        let devices = [3, 1, 0, 2]
+            .into_iter()
+            .map(|index| InMemoryBlockDevice::new(images[index].clone(), BlockSize(512)))
+            .collect_vec();
+
+        let search = MdGeometrySearch {
+            algorithms: vec![
+                MdAlgorithm::Raid6(Raid6Algorithm::LeftAsymmetric),
+                algorithm.clone(),
+            ],
+            chunk_sizes: vec![SectorCount(CHUNK_SIZE)],
+            data_offsets: vec![SectorNumber(DATA_OFFSET)],
+            max_results: 3,
+            ..MdGeometrySearch::default()
+        };
+        // Every other order fails the parity check of the first row.
+        let candidates = search.run(&devices)?;
+        assert_eq!(candidates.len(), 2);
+        let best = &candidates[0];
+        assert_eq!(best.geometry.format.algorithm, algorithm);
+        assert_eq!(
+            best.geometry.order,
+            vec![Some(2), Some(1), Some(3), Some(0)]
+        );
+        assert_eq!(
+            best.score.parity_rows_consistent,
+            best.score.parity_rows_checked
+        );
+        let runner_up = &candidates[1];
+        assert_eq!(
+            runner_up.geometry.format.algorithm,
+            MdAlgorithm::Raid6(Raid6Algorithm::LeftAsymmetric)
+        );
+        assert_eq!(runner_up.geometry.order, best.geometry.order);
+        assert!(runner_up.score.rank() < best.score.rank());
+        Ok(())
+    }
+}
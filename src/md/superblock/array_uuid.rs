@@ -7,6 +7,7 @@ use itertools::Itertools;
 pub enum ArrayUuid {
     Short([u8; 4]),
     Long([u8; 16]),
+    Guid([u8; 24]),
 }
 
 impl ArrayUuid {
@@ -25,6 +26,36 @@ impl ArrayUuid {
     pub fn from_u8_16(value: &[u8; 16]) -> Self {
         Self::Long(*value)
     }
+
+    pub fn from_u8_24(value: &[u8; 24]) -> Self {
+        Self::Guid(*value)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ArrayUuid::Short(uuid) => uuid,
+            ArrayUuid::Long(uuid) => uuid,
+            ArrayUuid::Guid(guid) => guid,
+        }
+    }
+
+    /// The UUID as mdadm writes it, in groups of four bytes separated by
+    /// colons.
+    pub fn to_mdadm_string(&self) -> String {
+        self.as_bytes()
+            .chunks(4)
+            .map(|group| format!("{:02x}", group.iter().format("")))
+            .join(":")
+    }
+
+    /// The UUID of a version 0.90 superblock as mdadm writes it, which keeps
+    /// it as four integers, each written out most significant byte first.
+    pub fn to_mdadm_string_of_words(&self) -> String {
+        self.as_bytes()
+            .chunks(4)
+            .map(|group| format!("{:02x}", group.iter().rev().format("")))
+            .join(":")
+    }
 }
 
 impl Display for ArrayUuid {
@@ -32,6 +63,7 @@ impl Display for ArrayUuid {
         match self {
             ArrayUuid::Short(uuid) => write!(f, "{:02x}", uuid.iter().format("")),
             ArrayUuid::Long(uuid) => write!(f, "{:02x}", uuid.iter().format("")),
+            ArrayUuid::Guid(guid) => write!(f, "{:02x}", guid.iter().format("")),
         }
     }
 }
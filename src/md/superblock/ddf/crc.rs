@@ -0,0 +1,13 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+const DDF_CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Computes the CRC of a DDF structure. Every DDF structure stores its CRC at
+/// byte offset 4, and the CRC is computed with that field set to all ones.
+pub fn ddf_crc32(bytes: &[u8]) -> u32 {
+    let mut digest = DDF_CRC32.digest();
+    digest.update(&bytes[..4]);
+    digest.update(&[0xff; 4]);
+    digest.update(&bytes[8..]);
+    digest.finalize()
+}
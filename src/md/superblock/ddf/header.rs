@@ -0,0 +1,54 @@
+use binary_layout::prelude::*;
+
+pub use layout::View as DdfHeader;
+
+binary_layout!(layout, BigEndian, {
+    magic: u32,
+    crc: u32,
+    guid: [u8; 24],
+    revision: [u8; 8],
+    sequence_number: u32,
+    timestamp: u32,
+    open_flag: u8,
+    foreign_flag: u8,
+    enforce_groups: u8,
+    pad_0: [u8; 13],
+    header_extension: [u8; 32],
+    primary_lba: u64,
+    secondary_lba: u64,
+    header_type: u8,
+    pad_1: [u8; 3],
+    workspace_length: u32,
+    workspace_lba: u64,
+    max_physical_disk_entries: u16,
+    max_virtual_disk_entries: u16,
+    max_partitions: u16,
+    config_record_length: u16,
+    max_primary_element_entries: u16,
+    pad_2: [u8; 54],
+    controller_section_offset: u32,
+    controller_section_length: u32,
+    physical_section_offset: u32,
+    physical_section_length: u32,
+    virtual_section_offset: u32,
+    virtual_section_length: u32,
+    config_section_offset: u32,
+    config_section_length: u32,
+    data_section_offset: u32,
+    data_section_length: u32,
+    bad_block_section_offset: u32,
+    bad_block_section_length: u32,
+    diagnostic_space_offset: u32,
+    diagnostic_space_length: u32,
+    vendor_offset: u32,
+    vendor_length: u32,
+    pad_3: [u8; 256]
+});
+
+impl<S: AsRef<[u8]>> DdfHeader<S> {
+    pub const SIZE: usize = layout::SIZE.unwrap();
+    pub const MAGIC: u32 = 0xde11de11;
+    pub const ANCHOR: u8 = 0x00;
+    pub const PRIMARY: u8 = 0x01;
+    pub const SECONDARY: u8 = 0x02;
+}
@@ -0,0 +1,13 @@
+mod crc;
+mod header;
+mod physical_disk_data;
+mod physical_disk_records;
+mod physical_disk_state;
+mod superblock;
+#[cfg(test)]
+mod tests;
+mod virtual_disk_config;
+mod virtual_disk_records;
+
+#[allow(unused_imports)]
+pub use superblock::SuperblockDdf;
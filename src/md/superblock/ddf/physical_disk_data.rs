@@ -0,0 +1,19 @@
+use binary_layout::prelude::*;
+
+pub use layout::View as DdfPhysicalDiskData;
+
+binary_layout!(layout, BigEndian, {
+    magic: u32,
+    crc: u32,
+    guid: [u8; 24],
+    reference_number: u32,
+    forced_reference: u8,
+    forced_guid: u8,
+    vendor: [u8; 32],
+    pad: [u8; 442]
+});
+
+impl<S: AsRef<[u8]>> DdfPhysicalDiskData<S> {
+    pub const SIZE: usize = layout::SIZE.unwrap();
+    pub const MAGIC: u32 = 0x33333333;
+}
@@ -0,0 +1,39 @@
+use crate::md::superblock::ddf::physical_disk_state::PhysicalDiskState;
+use binary_layout::prelude::*;
+
+pub use self::{entry::View as DdfPhysicalDiskEntry, header::View as DdfPhysicalDiskRecords};
+
+binary_layout!(header, BigEndian, {
+    magic: u32,
+    crc: u32,
+    used_entries: u16,
+    max_entries: u16,
+    pad: [u8; 52],
+    entries: [u8]
+});
+
+binary_layout!(entry, BigEndian, {
+    guid: [u8; 24],
+    reference_number: u32,
+    disk_type: u16,
+    state: PhysicalDiskState as u16,
+    config_size: u64,
+    path: [u8; 18],
+    pad: [u8; 6]
+});
+
+impl<S: AsRef<[u8]>> DdfPhysicalDiskRecords<S> {
+    pub const MAGIC: u32 = 0x22222222;
+    pub const HEADER_SIZE: usize = header::entries::OFFSET;
+
+    pub fn iter_entries(&self) -> impl Iterator<Item = DdfPhysicalDiskEntry<&[u8]>> {
+        self.entries()
+            .chunks_exact(DdfPhysicalDiskEntry::<&[u8]>::SIZE)
+            .take(usize::from(self.max_entries().read()))
+            .map(DdfPhysicalDiskEntry::new)
+    }
+}
+
+impl<S: AsRef<[u8]>> DdfPhysicalDiskEntry<S> {
+    pub const SIZE: usize = entry::SIZE.unwrap();
+}
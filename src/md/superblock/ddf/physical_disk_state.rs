@@ -0,0 +1,28 @@
+use binary_layout::LayoutAs;
+use std::convert::Infallible;
+
+bitflags! {
+    #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Default, Debug)]
+    pub struct PhysicalDiskState: u16 {
+        const ONLINE = 0x01;
+        const FAILED = 0x02;
+        const REBUILDING = 0x08;
+        const TRANSITION = 0x10;
+        const SMART_ERRORS = 0x20;
+        const READ_ERRORS = 0x40;
+        const MISSING = 0x80;
+    }
+}
+
+impl LayoutAs<u16> for PhysicalDiskState {
+    type ReadError = Infallible;
+    type WriteError = Infallible;
+
+    fn try_read(v: u16) -> Result<Self, Self::ReadError> {
+        Ok(Self::from_bits_retain(v))
+    }
+
+    fn try_write(v: Self) -> Result<u16, Self::WriteError> {
+        Ok(v.bits())
+    }
+}
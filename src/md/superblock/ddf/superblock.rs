@@ -0,0 +1,348 @@
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::superblock::ddf::crc::ddf_crc32;
+use crate::md::superblock::ddf::header::DdfHeader;
+use crate::md::superblock::ddf::physical_disk_data::DdfPhysicalDiskData;
+use crate::md::superblock::ddf::physical_disk_records::DdfPhysicalDiskRecords;
+use crate::md::superblock::ddf::physical_disk_state::PhysicalDiskState;
+use crate::md::superblock::ddf::virtual_disk_config::DdfVirtualDiskConfig;
+use crate::md::superblock::ddf::virtual_disk_records::{
+    DdfVirtualDiskEntry, DdfVirtualDiskRecords,
+};
+use crate::md::superblock::reshape_status::ReshapeStatus;
+use crate::md::superblock::{ArrayUuid, Features, MdDeviceRole, Superblock};
+use crate::md::units::{
+    DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
+};
+use std::ffi::OsStr;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+
+/// SNIA Common RAID Disk Data Format metadata, as written by hardware RAID
+/// controllers near the end of each member disk.
+///
+/// Each instance describes the first virtual disk that uses the physical
+/// disk the metadata was read from.
+pub struct SuperblockDdf {
+    header: Vec<u8>,
+    physical_disk_data: Vec<u8>,
+    physical_disk_records: Vec<u8>,
+    virtual_disk_records: Vec<u8>,
+    virtual_disk_config: Vec<u8>,
+}
+
+impl SuperblockDdf {
+    pub const MAX_SECTION_SIZE: usize = 16 << 20;
+
+    const SECTOR_SIZE: usize = 512;
+    const NO_LBA: u64 = u64::MAX;
+    const NO_REFERENCE: u32 = u32::MAX;
+
+    pub fn read<R: Read + Seek>(mut reader: R, device_size: u64) -> io::Result<Self> {
+        let anchor_lba = (device_size >> 9)
+            .checked_sub(1)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let anchor = Self::read_sectors(&mut reader, anchor_lba, 1)?;
+        if !Self::valid_header(&anchor, DdfHeader::<&[u8]>::ANCHOR) {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let anchor = DdfHeader::new(anchor);
+        [
+            (anchor.primary_lba().read(), DdfHeader::<&[u8]>::PRIMARY),
+            (anchor.secondary_lba().read(), DdfHeader::<&[u8]>::SECONDARY),
+        ]
+        .into_iter()
+        .filter(|&(lba, _)| lba != Self::NO_LBA)
+        .find_map(|(lba, header_type)| Self::read_at(&mut reader, lba, header_type).ok())
+        .ok_or(io::ErrorKind::InvalidData.into())
+    }
+
+    fn read_at<R: Read + Seek>(mut reader: R, lba: u64, header_type: u8) -> io::Result<Self> {
+        let header = Self::read_sectors(&mut reader, lba, 1)?;
+        if !Self::valid_header(&header, header_type) {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        // Section offsets are relative to the header they were read from.
+        let view = DdfHeader::new(header.as_slice());
+        let mut read_section = |offset: u32, length: u32| {
+            Self::read_sectors(
+                &mut reader,
+                lba.checked_add(offset.into())
+                    .ok_or(io::ErrorKind::InvalidData)?,
+                length.try_into().or(Err(io::ErrorKind::InvalidData))?,
+            )
+        };
+        let physical_disk_data = read_section(
+            view.data_section_offset().read(),
+            view.data_section_length().read(),
+        )?;
+        let physical_disk_records = read_section(
+            view.physical_section_offset().read(),
+            view.physical_section_length().read(),
+        )?;
+        let virtual_disk_records = read_section(
+            view.virtual_section_offset().read(),
+            view.virtual_section_length().read(),
+        )?;
+        let config_section = read_section(
+            view.config_section_offset().read(),
+            view.config_section_length().read(),
+        )?;
+
+        let reference_number = DdfPhysicalDiskData::new(physical_disk_data.as_slice())
+            .reference_number()
+            .read();
+        let config_record_size =
+            usize::from(view.config_record_length().read()) * Self::SECTOR_SIZE;
+        if config_record_size < DdfVirtualDiskConfig::<&[u8]>::HEADER_SIZE {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let virtual_disk_config = config_section
+            .chunks_exact(config_record_size)
+            .find(|record| {
+                let config = DdfVirtualDiskConfig::new(*record);
+                config.magic().read() == DdfVirtualDiskConfig::<&[u8]>::MAGIC
+                    && config.member_references().contains(&reference_number)
+            })
+            .ok_or(io::ErrorKind::InvalidData)?
+            .to_vec();
+
+        let superblock = Self {
+            header,
+            physical_disk_data,
+            physical_disk_records,
+            virtual_disk_records,
+            virtual_disk_config,
+        };
+        if superblock.valid() {
+            Ok(superblock)
+        } else {
+            Err(io::ErrorKind::InvalidData.into())
+        }
+    }
+
+    fn read_sectors<R: Read + Seek>(mut reader: R, lba: u64, count: usize) -> io::Result<Vec<u8>> {
+        let size = count
+            .checked_mul(Self::SECTOR_SIZE)
+            .filter(|&size| size > 0 && size <= Self::MAX_SECTION_SIZE)
+            .ok_or(io::ErrorKind::InvalidData)?;
+        reader.seek(SeekFrom::Start(
+            lba.checked_mul(Self::SECTOR_SIZE as u64)
+                .ok_or(io::ErrorKind::InvalidData)?,
+        ))?;
+        let mut buf = vec![0u8; size];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn valid_header(header: &[u8], header_type: u8) -> bool {
+        let view = DdfHeader::new(header);
+        view.magic().read() == DdfHeader::<&[u8]>::MAGIC
+            && view.header_type().read() == header_type
+            && view.crc().read() == ddf_crc32(header)
+    }
+
+    fn header(&self) -> DdfHeader<&[u8]> {
+        DdfHeader::new(self.header.as_slice())
+    }
+
+    fn physical_disk_data(&self) -> DdfPhysicalDiskData<&[u8]> {
+        DdfPhysicalDiskData::new(self.physical_disk_data.as_slice())
+    }
+
+    fn physical_disk_records(&self) -> DdfPhysicalDiskRecords<&[u8]> {
+        DdfPhysicalDiskRecords::new(self.physical_disk_records.as_slice())
+    }
+
+    fn virtual_disk_records(&self) -> DdfVirtualDiskRecords<&[u8]> {
+        DdfVirtualDiskRecords::new(self.virtual_disk_records.as_slice())
+    }
+
+    fn virtual_disk_config(&self) -> DdfVirtualDiskConfig<&[u8]> {
+        DdfVirtualDiskConfig::new(self.virtual_disk_config.as_slice())
+    }
+
+    pub fn valid_magic(&self) -> bool {
+        self.header().magic().read() == DdfHeader::<&[u8]>::MAGIC
+            && self.physical_disk_data().magic().read() == DdfPhysicalDiskData::<&[u8]>::MAGIC
+            && self.physical_disk_records().magic().read() == DdfPhysicalDiskRecords::<&[u8]>::MAGIC
+            && self.virtual_disk_records().magic().read() == DdfVirtualDiskRecords::<&[u8]>::MAGIC
+            && self.virtual_disk_config().magic().read() == DdfVirtualDiskConfig::<&[u8]>::MAGIC
+    }
+
+    pub fn valid_checksums(&self) -> bool {
+        [
+            (self.header().crc().read(), self.header.as_slice()),
+            (
+                self.physical_disk_data().crc().read(),
+                &self.physical_disk_data[..DdfPhysicalDiskData::<&[u8]>::SIZE],
+            ),
+            (
+                self.physical_disk_records().crc().read(),
+                self.physical_disk_records.as_slice(),
+            ),
+            (
+                self.virtual_disk_records().crc().read(),
+                self.virtual_disk_records.as_slice(),
+            ),
+            (
+                self.virtual_disk_config().crc().read(),
+                self.virtual_disk_config.as_slice(),
+            ),
+        ]
+        .into_iter()
+        .all(|(crc, bytes)| crc == ddf_crc32(bytes))
+    }
+
+    pub fn reference_number(&self) -> u32 {
+        self.physical_disk_data().reference_number().read()
+    }
+
+    fn max_primary_element_entries(&self) -> usize {
+        self.header().max_primary_element_entries().read().into()
+    }
+
+    fn member_index(&self) -> Option<usize> {
+        let reference_number = self.reference_number();
+        self.virtual_disk_config()
+            .member_references()
+            .iter()
+            .position(|&member| member == reference_number)
+    }
+
+    fn physical_disk_state(&self, reference_number: u32) -> Option<PhysicalDiskState> {
+        self.physical_disk_records()
+            .iter_entries()
+            .find(|entry| entry.reference_number().read() == reference_number)
+            .map(|entry| entry.state().read())
+    }
+
+    fn revision(&self, index: usize) -> u32 {
+        self.header()
+            .revision()
+            .split(|&c| c == b'.')
+            .nth(index)
+            .and_then(|part| std::str::from_utf8(part).ok())
+            .and_then(|part| part.trim_end_matches('\0').parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+impl Superblock for SuperblockDdf {
+    fn valid(&self) -> bool {
+        self.valid_magic() && self.valid_checksums() && self.member_index().is_some()
+    }
+
+    fn major_version(&self) -> u32 {
+        self.revision(0)
+    }
+
+    fn minor_version(&self) -> u32 {
+        self.revision(1)
+    }
+
+    fn array_uuid(&self) -> ArrayUuid {
+        ArrayUuid::from_u8_24(self.virtual_disk_config().guid())
+    }
+
+    fn array_name(&self) -> Option<&OsStr> {
+        let guid = self.virtual_disk_config().into_guid().into_slice();
+        DdfVirtualDiskRecords::new(self.virtual_disk_records.as_slice())
+            .into_entries()
+            .into_slice()
+            .chunks_exact(DdfVirtualDiskEntry::<&[u8]>::SIZE)
+            .map(DdfVirtualDiskEntry::new)
+            .find(|entry| entry.guid() == guid)
+            .map(|entry| {
+                let name = entry.into_name().into_slice();
+                let length = name
+                    .iter()
+                    .rposition(|&c| c != 0 && c != b' ')
+                    .map_or(0, |last| last + 1);
+                OsStr::from_bytes(&name[..length])
+            })
+    }
+
+    fn algorithm(&self) -> MdAlgorithm {
+        self.virtual_disk_config().algorithm()
+    }
+
+    fn sectors_per_device(&self) -> SectorCount<u64> {
+        SectorCount(self.virtual_disk_config().blocks().read())
+    }
+
+    fn chunk_size(&self) -> SectorCount<u32> {
+        self.virtual_disk_config().chunk_size()
+    }
+
+    fn raid_device_count(&self) -> DeviceCount {
+        self.virtual_disk_config().device_count()
+    }
+
+    fn reshape_status(&self) -> Option<ReshapeStatus> {
+        None
+    }
+
+    fn data_offset(&self) -> SectorNumber {
+        self.member_index()
+            .and_then(|index| {
+                self.virtual_disk_config()
+                    .data_offset(self.max_primary_element_entries(), index)
+            })
+            .unwrap_or(SectorNumber(0))
+    }
+
+    fn data_size(&self) -> Option<SectorCount<u64>> {
+        None
+    }
+
+    fn super_offset(&self) -> Option<SectorNumber> {
+        None
+    }
+
+    fn features(&self) -> Option<Features> {
+        None
+    }
+
+    fn device_role_index(&self) -> usize {
+        self.member_index()
+            .unwrap_or(usize::from(self.raid_device_count()))
+    }
+
+    fn event_count(&self) -> MetadataEventCount {
+        MetadataEventCount(self.header().sequence_number().read().into())
+    }
+
+    fn creation_time(&self) -> Option<Timestamp> {
+        None
+    }
+
+    fn update_time(&self) -> Option<Timestamp> {
+        None
+    }
+
+    fn device_roles(&self) -> Vec<MdDeviceRole> {
+        self.virtual_disk_config()
+            .member_references()
+            .into_iter()
+            .zip(0u32..)
+            .map(|(reference_number, index)| {
+                match (reference_number, self.physical_disk_state(reference_number)) {
+                    (Self::NO_REFERENCE, _) | (_, None) => MdDeviceRole::faulty(),
+                    (_, Some(state))
+                        if state.intersects(
+                            PhysicalDiskState::FAILED
+                                | PhysicalDiskState::MISSING
+                                | PhysicalDiskState::REBUILDING,
+                        ) =>
+                    {
+                        MdDeviceRole::faulty()
+                    }
+                    _ => MdDeviceRole::from_device_number(DeviceNumber(index)),
+                }
+            })
+            .collect()
+    }
+}
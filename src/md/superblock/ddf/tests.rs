@@ -0,0 +1,220 @@
+use crate::block_device::{BlockDeviceReader, BlockSize, InMemoryBlockDevice};
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::raid5::Raid5Algorithm;
+use crate::md::superblock::ddf::crc::ddf_crc32;
+use crate::md::superblock::ddf::header::DdfHeader;
+use crate::md::superblock::ddf::physical_disk_data::DdfPhysicalDiskData;
+use crate::md::superblock::ddf::physical_disk_records::{
+    DdfPhysicalDiskEntry, DdfPhysicalDiskRecords,
+};
+use crate::md::superblock::ddf::physical_disk_state::PhysicalDiskState;
+use crate::md::superblock::ddf::virtual_disk_config::DdfVirtualDiskConfig;
+use crate::md::superblock::ddf::virtual_disk_records::{
+    DdfVirtualDiskEntry, DdfVirtualDiskRecords,
+};
+use crate::md::superblock::{ArrayUuid, MdDeviceRole, Superblock, SuperblockDdf};
+use crate::md::units::{DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber};
+use std::ffi::OsStr;
+
+const DEVICE_SECTORS: u64 = 2048;
+const PRIMARY_LBA: u64 = DEVICE_SECTORS - 64;
+const SECONDARY_LBA: u64 = DEVICE_SECTORS - 32;
+const MAX_PRIMARY_ELEMENT_ENTRIES: usize = 16;
+const CONFIG_RECORD_LENGTH: u16 = 2;
+const VIRTUAL_DISK_GUID: [u8; 24] = *b"VIRTUAL DISK GUID 000001";
+const REFERENCES: [u32; 3] = [0x1001, 0x1002, 0x1000];
+
+fn sector(lba: u64) -> usize {
+    (lba * 512) as usize
+}
+
+fn write_crc(bytes: &mut [u8]) {
+    let crc = ddf_crc32(bytes);
+    bytes[4..8].copy_from_slice(&crc.to_be_bytes());
+}
+
+fn write_header(image: &mut [u8], lba: u64, header_type: u8) {
+    let bytes = &mut image[sector(lba)..][..DdfHeader::<&[u8]>::SIZE];
+    let mut header = DdfHeader::new(&mut *bytes);
+    header.magic_mut().write(DdfHeader::<&[u8]>::MAGIC);
+    header.revision_mut().copy_from_slice(b"01.02.00");
+    header.sequence_number_mut().write(7);
+    header.primary_lba_mut().write(PRIMARY_LBA);
+    header.secondary_lba_mut().write(SECONDARY_LBA);
+    header.header_type_mut().write(header_type);
+    header.max_physical_disk_entries_mut().write(7);
+    header.max_virtual_disk_entries_mut().write(7);
+    header.max_partitions_mut().write(2);
+    header
+        .config_record_length_mut()
+        .write(CONFIG_RECORD_LENGTH);
+    header
+        .max_primary_element_entries_mut()
+        .write(MAX_PRIMARY_ELEMENT_ENTRIES as u16);
+    header.physical_section_offset_mut().write(1);
+    header.physical_section_length_mut().write(1);
+    header.virtual_section_offset_mut().write(2);
+    header.virtual_section_length_mut().write(1);
+    header.config_section_offset_mut().write(3);
+    header
+        .config_section_length_mut()
+        .write(2 * u32::from(CONFIG_RECORD_LENGTH));
+    header.data_section_offset_mut().write(7);
+    header.data_section_length_mut().write(1);
+    write_crc(bytes);
+}
+
+fn write_sections(image: &mut [u8], lba: u64, reference_number: u32, failed: u32) {
+    let bytes = &mut image[sector(lba + 1)..][..512];
+    let mut records = DdfPhysicalDiskRecords::new(&mut *bytes);
+    records
+        .magic_mut()
+        .write(DdfPhysicalDiskRecords::<&[u8]>::MAGIC);
+    records.used_entries_mut().write(REFERENCES.len() as u16);
+    records.max_entries_mut().write(7);
+    for (entry, &reference) in records
+        .entries_mut()
+        .chunks_exact_mut(DdfPhysicalDiskEntry::<&[u8]>::SIZE)
+        .zip(&REFERENCES)
+    {
+        let mut entry = DdfPhysicalDiskEntry::new(entry);
+        entry.reference_number_mut().write(reference);
+        entry.state_mut().write(if reference == failed {
+            PhysicalDiskState::FAILED
+        } else {
+            PhysicalDiskState::ONLINE
+        });
+    }
+    write_crc(bytes);
+
+    let bytes = &mut image[sector(lba + 2)..][..512];
+    let mut records = DdfVirtualDiskRecords::new(&mut *bytes);
+    records
+        .magic_mut()
+        .write(DdfVirtualDiskRecords::<&[u8]>::MAGIC);
+    records.populated_entries_mut().write(1);
+    records.max_entries_mut().write(7);
+    let mut entry =
+        DdfVirtualDiskEntry::new(&mut records.entries_mut()[..DdfVirtualDiskEntry::<&[u8]>::SIZE]);
+    entry.guid_mut().copy_from_slice(&VIRTUAL_DISK_GUID);
+    entry.name_mut().copy_from_slice(b"Array0          ");
+    write_crc(bytes);
+
+    let record_size = usize::from(CONFIG_RECORD_LENGTH) * 512;
+    let bytes = &mut image[sector(lba + 3)..][..record_size];
+    let mut config = DdfVirtualDiskConfig::new(&mut *bytes);
+    config
+        .magic_mut()
+        .write(DdfVirtualDiskConfig::<&[u8]>::MAGIC);
+    config.guid_mut().copy_from_slice(&VIRTUAL_DISK_GUID);
+    config
+        .primary_element_count_mut()
+        .write(REFERENCES.len() as u16);
+    config.chunk_shift_mut().write(7);
+    config.primary_raid_level_mut().write(5);
+    config.raid_level_qualifier_mut().write(3);
+    config.blocks_mut().write(1536);
+    let references = config.physical_references_mut();
+    references[..MAX_PRIMARY_ELEMENT_ENTRIES * 4].fill(0xff);
+    for (index, reference) in REFERENCES.iter().enumerate() {
+        references[index * 4..][..4].copy_from_slice(&reference.to_be_bytes());
+        references[MAX_PRIMARY_ELEMENT_ENTRIES * 4 + index * 8..][..8]
+            .copy_from_slice(&(u64::try_from(index).unwrap() * 0x40).to_be_bytes());
+    }
+    write_crc(bytes);
+
+    let bytes = &mut image[sector(lba + 7)..][..DdfPhysicalDiskData::<&[u8]>::SIZE];
+    let mut data = DdfPhysicalDiskData::new(&mut *bytes);
+    data.magic_mut().write(DdfPhysicalDiskData::<&[u8]>::MAGIC);
+    data.reference_number_mut().write(reference_number);
+    write_crc(bytes);
+}
+
+fn image(reference_number: u32, failed: u32) -> Vec<u8> {
+    let mut image = vec![0u8; sector(DEVICE_SECTORS)];
+    write_header(&mut image, DEVICE_SECTORS - 1, DdfHeader::<&[u8]>::ANCHOR);
+    write_header(&mut image, PRIMARY_LBA, DdfHeader::<&[u8]>::PRIMARY);
+    write_header(&mut image, SECONDARY_LBA, DdfHeader::<&[u8]>::SECONDARY);
+    write_sections(&mut image, PRIMARY_LBA, reference_number, failed);
+    write_sections(&mut image, SECONDARY_LBA, reference_number, failed);
+    image
+}
+
+fn read(image: Vec<u8>) -> std::io::Result<SuperblockDdf> {
+    SuperblockDdf::read(
+        BlockDeviceReader::new(InMemoryBlockDevice::new(image, BlockSize(512))),
+        sector(DEVICE_SECTORS) as u64,
+    )
+}
+
+#[test]
+fn ddf_superblock() -> anyhow::Result<()> {
+    let superblock = read(image(0x1000, 0))?;
+    assert!(superblock.valid());
+    assert_eq!(superblock.major_version(), 1);
+    assert_eq!(superblock.minor_version(), 2);
+    assert_eq!(
+        superblock.array_uuid(),
+        ArrayUuid::from_u8_24(&VIRTUAL_DISK_GUID)
+    );
+    assert_eq!(superblock.array_name(), Some(OsStr::new("Array0")));
+    assert_eq!(
+        superblock.algorithm(),
+        MdAlgorithm::Raid5(Raid5Algorithm::LeftSymmetric)
+    );
+    assert_eq!(superblock.sectors_per_device(), SectorCount(1536));
+    assert_eq!(superblock.chunk_size(), SectorCount(128));
+    assert_eq!(superblock.raid_device_count(), DeviceCount(3));
+    assert_eq!(superblock.reshape_status(), None);
+    assert_eq!(superblock.device_role_index(), 2);
+    assert_eq!(superblock.data_offset(), SectorNumber(0x80));
+    assert_eq!(superblock.event_count(), MetadataEventCount(7));
+    assert_eq!(
+        superblock.device_roles(),
+        vec![
+            MdDeviceRole::from_device_number(DeviceNumber(0)),
+            MdDeviceRole::from_device_number(DeviceNumber(1)),
+            MdDeviceRole::from_device_number(DeviceNumber(2)),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn ddf_superblock_with_failed_disk() -> anyhow::Result<()> {
+    let superblock = read(image(0x1001, 0x1002))?;
+    assert_eq!(superblock.device_role_index(), 0);
+    assert_eq!(superblock.data_offset(), SectorNumber(0));
+    assert_eq!(
+        superblock.device_roles(),
+        vec![
+            MdDeviceRole::from_device_number(DeviceNumber(0)),
+            MdDeviceRole::faulty(),
+            MdDeviceRole::from_device_number(DeviceNumber(2)),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn read_ddf_superblock_with_corrupt_primary_header() -> anyhow::Result<()> {
+    let mut image = image(0x1002, 0);
+    image[sector(PRIMARY_LBA) + 0x40] ^= 1;
+    let superblock = read(image)?;
+    assert!(superblock.valid());
+    assert_eq!(superblock.device_role_index(), 1);
+    Ok(())
+}
+
+#[test]
+fn read_ddf_superblock_with_bad_config_checksum() {
+    let mut image = image(0x1000, 0);
+    image[sector(PRIMARY_LBA + 3) + 0x60] ^= 1;
+    image[sector(SECONDARY_LBA + 3) + 0x60] ^= 1;
+    assert!(read(image).is_err());
+}
+
+#[test]
+fn read_ddf_superblock_of_unconfigured_disk() {
+    assert!(read(image(0x2000, 0)).is_err());
+}
@@ -0,0 +1,106 @@
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+use binary_layout::prelude::*;
+
+pub use layout::View as DdfVirtualDiskConfig;
+
+binary_layout!(layout, BigEndian, {
+    magic: u32,
+    crc: u32,
+    guid: [u8; 24],
+    timestamp: u32,
+    sequence_number: u32,
+    pad_0: [u8; 24],
+    primary_element_count: u16,
+    chunk_shift: u8,
+    primary_raid_level: u8,
+    raid_level_qualifier: u8,
+    secondary_element_count: u8,
+    secondary_element_sequence: u8,
+    secondary_raid_level: u8,
+    blocks: u64,
+    array_blocks: u64,
+    pad_1: [u8; 8],
+    spare_references: [u8; 32],
+    cache_policy: [u8; 8],
+    background_rate: u8,
+    pad_2: [u8; 3],
+    pad_3: [u8; 52],
+    pad_4: [u8; 192],
+    reserved: [u8; 96],
+    vendor: [u8; 32],
+    physical_references: [u8]
+});
+
+impl<S: AsRef<[u8]>> DdfVirtualDiskConfig<S> {
+    pub const MAGIC: u32 = 0xeeeeeeee;
+    pub const HEADER_SIZE: usize = layout::physical_references::OFFSET;
+
+    const RAID0: u8 = 0x00;
+    const RAID1: u8 = 0x01;
+    const RAID5: u8 = 0x05;
+    const RAID6: u8 = 0x06;
+
+    const RAID5_0_RESTART: u8 = 0x00;
+    const RAID6_0_RESTART: u8 = 0x01;
+    const RAID5_N_RESTART: u8 = 0x02;
+    const RAID5_N_CONTINUE: u8 = 0x03;
+
+    pub fn algorithm(&self) -> MdAlgorithm {
+        let level = self.primary_raid_level().read();
+        let qualifier = self.raid_level_qualifier().read();
+        match (level, qualifier) {
+            _ if self.secondary_element_count().read() > 1 => None,
+            (Self::RAID0, _) => Some((0, 0)),
+            (Self::RAID1, _) => Some((1, 0)),
+            (Self::RAID5, Self::RAID5_N_RESTART) => Some((5, 0)),
+            (Self::RAID5, Self::RAID5_0_RESTART) => Some((5, 1)),
+            (Self::RAID5, Self::RAID5_N_CONTINUE) => Some((5, 2)),
+            (Self::RAID6, Self::RAID6_0_RESTART) => Some((6, 8)),
+            (Self::RAID6, Self::RAID5_N_RESTART) => Some((6, 9)),
+            (Self::RAID6, Self::RAID5_N_CONTINUE) => Some((6, 10)),
+            _ => None,
+        }
+        .map(|(level, layout)| MdAlgorithm::from_level_and_layout(level, layout))
+        .unwrap_or(MdAlgorithm::Unsupported {
+            level: level.into(),
+            layout: qualifier.into(),
+        })
+    }
+
+    pub fn chunk_size(&self) -> SectorCount<u32> {
+        SectorCount(
+            1u32.checked_shl(self.chunk_shift().read().into())
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn device_count(&self) -> DeviceCount {
+        DeviceCount(self.primary_element_count().read().into())
+    }
+
+    /// Returns the reference numbers of the physical disks that make up the
+    /// virtual disk, in member order.
+    pub fn member_references(&self) -> Vec<u32> {
+        self.physical_references()
+            .chunks_exact(size_of::<u32>())
+            .take(usize::from(self.primary_element_count().read()))
+            .map(|reference| u32::from_be_bytes(reference.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Returns the data offset of the member at `index`. The offsets follow
+    /// the table of physical disk references, which has one entry for each of
+    /// the `max_primary_element_entries` allowed by the DDF header.
+    pub fn data_offset(
+        &self,
+        max_primary_element_entries: usize,
+        index: usize,
+    ) -> Option<SectorNumber> {
+        let offset = (max_primary_element_entries * size_of::<u32>())
+            .checked_add(index.checked_mul(size_of::<u64>())?)?;
+        self.physical_references()
+            .get(offset..offset + size_of::<u64>())
+            .map(|offset| SectorNumber(u64::from_be_bytes(offset.try_into().unwrap())))
+    }
+}
@@ -0,0 +1,40 @@
+use binary_layout::prelude::*;
+
+pub use self::{entry::View as DdfVirtualDiskEntry, header::View as DdfVirtualDiskRecords};
+
+binary_layout!(header, BigEndian, {
+    magic: u32,
+    crc: u32,
+    populated_entries: u16,
+    max_entries: u16,
+    pad: [u8; 52],
+    entries: [u8]
+});
+
+binary_layout!(entry, BigEndian, {
+    guid: [u8; 24],
+    unit: u16,
+    pad_0: u16,
+    guid_crc: u16,
+    disk_type: u16,
+    state: u8,
+    init_state: u8,
+    pad_1: [u8; 14],
+    name: [u8; 16]
+});
+
+impl<S: AsRef<[u8]>> DdfVirtualDiskRecords<S> {
+    pub const MAGIC: u32 = 0xdddddddd;
+    pub const HEADER_SIZE: usize = header::entries::OFFSET;
+
+    pub fn iter_entries(&self) -> impl Iterator<Item = DdfVirtualDiskEntry<&[u8]>> {
+        self.entries()
+            .chunks_exact(DdfVirtualDiskEntry::<&[u8]>::SIZE)
+            .take(usize::from(self.max_entries().read()))
+            .map(DdfVirtualDiskEntry::new)
+    }
+}
+
+impl<S: AsRef<[u8]>> DdfVirtualDiskEntry<S> {
+    pub const SIZE: usize = entry::SIZE.unwrap();
+}
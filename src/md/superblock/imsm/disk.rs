@@ -0,0 +1,32 @@
+use crate::ext::WideUnsigned;
+use crate::md::superblock::imsm::disk_status::DiskStatus;
+use crate::md::units::SectorCount;
+use binary_layout::prelude::*;
+
+pub use layout::View as ImsmDisk;
+
+binary_layout!(layout, LittleEndian, {
+    serial: [u8; 16],
+    total_blocks_low: u32,
+    scsi_id: u32,
+    status: DiskStatus as u32,
+    owner_config_number: u32,
+    total_blocks_high: u32,
+    filler: [u8; 12]
+});
+
+impl<S: AsRef<[u8]>> ImsmDisk<S> {
+    pub const SIZE: usize = layout::SIZE.unwrap();
+
+    pub fn serial_bytes(&self) -> &[u8] {
+        let serial = self.serial();
+        &serial[..serial.iter().position(|&c| c == 0).unwrap_or(serial.len())]
+    }
+
+    pub fn total_sectors(&self) -> SectorCount<u64> {
+        SectorCount(u64::from_low_high(
+            self.total_blocks_low().read(),
+            self.total_blocks_high().read(),
+        ))
+    }
+}
@@ -0,0 +1,25 @@
+use binary_layout::LayoutAs;
+use std::convert::Infallible;
+
+bitflags! {
+    #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Default, Debug)]
+    pub struct DiskStatus: u32 {
+        const SPARE = 0x01;
+        const CONFIGURED = 0x02;
+        const FAILED = 0x04;
+        const JOURNAL = 0x2000000;
+    }
+}
+
+impl LayoutAs<u32> for DiskStatus {
+    type ReadError = Infallible;
+    type WriteError = Infallible;
+
+    fn try_read(v: u32) -> Result<Self, Self::ReadError> {
+        Ok(Self::from_bits_retain(v))
+    }
+
+    fn try_write(v: Self) -> Result<u32, Self::WriteError> {
+        Ok(v.bits())
+    }
+}
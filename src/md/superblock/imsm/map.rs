@@ -0,0 +1,72 @@
+use crate::ext::WideUnsigned;
+use crate::md::units::{DeviceCount, SectorCount, SectorNumber};
+use binary_layout::prelude::*;
+
+pub use layout::View as ImsmMap;
+
+binary_layout!(layout, LittleEndian, {
+    pba_of_lba0_low: u32,
+    blocks_per_member_low: u32,
+    data_stripe_count_low: u32,
+    blocks_per_strip: u16,
+    map_state: u8,
+    raid_level: u8,
+    member_count: u8,
+    domain_count: u8,
+    failed_disk_number: u8,
+    ddf: u8,
+    pba_of_lba0_high: u32,
+    blocks_per_member_high: u32,
+    data_stripe_count_high: u32,
+    filler: [u8; 16],
+    disk_order: [u8]
+});
+
+impl<S: AsRef<[u8]>> ImsmMap<S> {
+    pub const HEADER_SIZE: usize = layout::disk_order::OFFSET;
+
+    const DISK_INDEX_MASK: u32 = 0xffffff;
+    const REBUILD: u32 = 0x1000000;
+
+    pub fn size(&self) -> usize {
+        Self::HEADER_SIZE + usize::from(self.member_count().read()) * size_of::<u32>()
+    }
+
+    pub fn data_offset(&self) -> SectorNumber {
+        SectorNumber(u64::from_low_high(
+            self.pba_of_lba0_low().read(),
+            self.pba_of_lba0_high().read(),
+        ))
+    }
+
+    pub fn sectors_per_member(&self) -> SectorCount<u64> {
+        SectorCount(u64::from_low_high(
+            self.blocks_per_member_low().read(),
+            self.blocks_per_member_high().read(),
+        ))
+    }
+
+    pub fn chunk_size(&self) -> SectorCount<u32> {
+        SectorCount(self.blocks_per_strip().read().into())
+    }
+
+    pub fn member_device_count(&self) -> DeviceCount {
+        DeviceCount(self.member_count().read().into())
+    }
+
+    /// Returns the disk table index of each member, in member order, and
+    /// whether that member is being rebuilt.
+    pub fn members(&self) -> Vec<(usize, bool)> {
+        self.disk_order()
+            .chunks_exact(size_of::<u32>())
+            .take(usize::from(self.member_count().read()))
+            .map(|entry| {
+                let entry = u32::from_le_bytes(entry.try_into().unwrap());
+                (
+                    usize::try_from(entry & Self::DISK_INDEX_MASK).unwrap(),
+                    entry & Self::REBUILD != 0,
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,10 @@
+mod disk;
+mod disk_status;
+mod map;
+mod superblock;
+#[cfg(test)]
+mod tests;
+mod volume;
+
+#[allow(unused_imports)]
+pub use superblock::SuperblockImsm;
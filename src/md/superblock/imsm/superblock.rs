@@ -0,0 +1,385 @@
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::superblock::imsm::disk::ImsmDisk;
+use crate::md::superblock::imsm::disk_status::DiskStatus;
+use crate::md::superblock::imsm::map::ImsmMap;
+use crate::md::superblock::imsm::volume::ImsmVolume;
+use crate::md::superblock::reshape_status::ReshapeStatus;
+use crate::md::superblock::{ArrayUuid, Features, MdDeviceRole, Superblock};
+use crate::md::units::{
+    DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
+};
+use binary_layout::{binary_layout, Field};
+use std::ffi::OsStr;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+
+binary_layout!(layout, LittleEndian, {
+    signature: [u8; 32],
+    checksum: u32,
+    mpb_size: u32,
+    family_number: u32,
+    generation_number: u32,
+    error_log_size: u32,
+    attributes: u32,
+    disk_count: u8,
+    volume_count: u8,
+    error_log_position: u8,
+    fill: u8,
+    cache_size: u32,
+    original_family_number: u32,
+    power_cycle_count: u32,
+    bad_block_log_size: u32,
+    volumes_created: u16,
+    filler_0: u16,
+    creation_time: u64,
+    filler_1: [u8; 128],
+    disks: [u8]
+});
+
+type VolumeAndMap<'a> = (ImsmVolume<&'a [u8]>, ImsmMap<&'a [u8]>);
+
+/// Intel Matrix Storage Manager metadata, as written by Intel RST firmware
+/// near the end of each member disk.
+///
+/// An IMSM container may hold several volumes. Each instance describes one
+/// of them, as seen from one of the member disks. Only RAID5 volumes can be
+/// read; RAID0, RAID1 and RAID10 volumes are recognised, but their
+/// algorithm is unsupported.
+pub struct SuperblockImsm<S: AsRef<[u8]>> {
+    buffer: S,
+    volume_index: usize,
+    disk_index: Option<usize>,
+}
+
+impl SuperblockImsm<Vec<u8>> {
+    pub const MAX_SIZE: usize = 1 << 20;
+
+    /// Reads the metadata from a device of `device_size` bytes, as one
+    /// superblock for each volume in the container.
+    ///
+    /// IMSM does not record which entry in the disk table describes the disk
+    /// the metadata was read from; Intel firmware and mdadm match the drive
+    /// serial number. `serial` is matched against the disk table, and
+    /// without it, or if no entry matches, the disk is left unidentified.
+    pub fn read<R: Read + Seek>(
+        mut reader: R,
+        device_size: u64,
+        serial: Option<&[u8]>,
+    ) -> io::Result<Vec<Self>> {
+        let anchor_offset = device_size
+            .checked_sub(2 * Self::SECTOR_SIZE as u64)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        reader.seek(SeekFrom::Start(anchor_offset))?;
+        let mut buf = vec![0u8; Self::SECTOR_SIZE];
+        reader.read_exact(&mut buf)?;
+
+        let anchor = layout::View::new(buf.as_slice());
+        let mpb_size = usize::try_from(anchor.mpb_size().read())
+            .or(Err(io::Error::from(io::ErrorKind::InvalidData)))?;
+        if !anchor.signature().starts_with(Self::SIGNATURE)
+            || !(Self::HEADER_SIZE..=Self::MAX_SIZE).contains(&mpb_size)
+        {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        // The anchor is the first sector of the metadata, but any further
+        // sectors are stored immediately before it.
+        let extra_sectors = mpb_size.div_ceil(Self::SECTOR_SIZE) - 1;
+        if extra_sectors > 0 {
+            reader.seek(SeekFrom::Start(
+                anchor_offset
+                    .checked_sub((extra_sectors * Self::SECTOR_SIZE) as u64)
+                    .ok_or(io::ErrorKind::InvalidData)?,
+            ))?;
+            buf.resize((extra_sectors + 1) * Self::SECTOR_SIZE, 0);
+            reader.read_exact(&mut buf[Self::SECTOR_SIZE..])?;
+        }
+
+        let superblock = Self::new(buf, 0);
+        let disk_index = serial.and_then(|serial| superblock.find_disk_by_serial(serial));
+        let superblocks = (0..superblock.volume_count())
+            .map(|volume_index| {
+                Self::new(superblock.buffer.clone(), volume_index).with_disk_index(disk_index)
+            })
+            .filter(|superblock| superblock.valid())
+            .collect::<Vec<_>>();
+        if superblocks.is_empty() {
+            Err(io::ErrorKind::InvalidData.into())
+        } else {
+            Ok(superblocks)
+        }
+    }
+}
+
+impl<S: AsRef<[u8]>> SuperblockImsm<S> {
+    pub const SIGNATURE: &'static [u8] = b"Intel Raid ISM Cfg Sig. ";
+
+    const SECTOR_SIZE: usize = 512;
+    const HEADER_SIZE: usize = layout::disks::OFFSET;
+
+    pub fn new(storage: S, volume_index: usize) -> Self {
+        Self {
+            buffer: storage,
+            volume_index,
+            disk_index: None,
+        }
+    }
+
+    pub fn with_disk_index(self, disk_index: Option<usize>) -> Self {
+        Self { disk_index, ..self }
+    }
+
+    fn view(&self) -> layout::View<&[u8]> {
+        layout::View::new(self.buffer.as_ref())
+    }
+
+    fn mpb_size(&self) -> usize {
+        usize::try_from(self.view().mpb_size().read()).unwrap()
+    }
+
+    pub fn valid_signature(&self) -> bool {
+        self.view().signature().starts_with(Self::SIGNATURE)
+    }
+
+    pub fn valid_size(&self) -> bool {
+        (Self::HEADER_SIZE..=self.buffer.as_ref().len()).contains(&self.mpb_size())
+            && self.disks_end().is_some_and(|end| end <= self.mpb_size())
+    }
+
+    pub fn valid_checksum(&self) -> bool {
+        self.checksum() == self.expected_checksum()
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.view().checksum().read()
+    }
+
+    pub fn expected_checksum(&self) -> u32 {
+        self.buffer.as_ref()[..self.mpb_size()]
+            .chunks_exact(size_of::<u32>())
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .fold(0u32, u32::wrapping_add)
+            .wrapping_sub(self.checksum())
+    }
+
+    pub fn family_number(&self) -> u32 {
+        match self.view().original_family_number().read() {
+            0 => self.view().family_number().read(),
+            family_number => family_number,
+        }
+    }
+
+    pub fn disk_count(&self) -> usize {
+        self.view().disk_count().read().into()
+    }
+
+    pub fn volume_count(&self) -> usize {
+        self.view().volume_count().read().into()
+    }
+
+    pub fn volume_index(&self) -> usize {
+        self.volume_index
+    }
+
+    pub fn disk_index(&self) -> Option<usize> {
+        self.disk_index
+    }
+
+    fn disks_end(&self) -> Option<usize> {
+        ImsmDisk::<&[u8]>::SIZE
+            .checked_mul(self.disk_count())?
+            .checked_add(Self::HEADER_SIZE)
+    }
+
+    pub fn disk(&self, index: usize) -> Option<ImsmDisk<&[u8]>> {
+        if index < self.disk_count() {
+            let offset = Self::HEADER_SIZE + index * ImsmDisk::<&[u8]>::SIZE;
+            self.buffer
+                .as_ref()
+                .get(offset..offset + ImsmDisk::<&[u8]>::SIZE)
+                .map(ImsmDisk::new)
+        } else {
+            None
+        }
+    }
+
+    pub fn disks(&self) -> impl Iterator<Item = ImsmDisk<&[u8]>> {
+        (0..self.disk_count()).map_while(|index| self.disk(index))
+    }
+
+    /// Returns the volume at `index` together with its current map.
+    pub fn volume(&self, index: usize) -> Option<VolumeAndMap<'_>> {
+        let buffer = self.buffer.as_ref().get(..self.mpb_size())?;
+        let mut offset = self.disks_end()?;
+        for volume_index in 0..self.volume_count() {
+            let volume = ImsmVolume::new(buffer.get(offset..offset + ImsmVolume::<&[u8]>::SIZE)?);
+            let map_offset = offset + ImsmVolume::<&[u8]>::SIZE;
+            let map = ImsmMap::new(buffer.get(map_offset..)?);
+            if buffer.len() < map_offset + ImsmMap::<&[u8]>::HEADER_SIZE
+                || buffer.len() < map_offset + map.size()
+            {
+                return None;
+            }
+            if volume_index == index {
+                let map_size = map.size();
+                return Some((volume, ImsmMap::new(&buffer[map_offset..][..map_size])));
+            }
+            offset = map_offset;
+            for _ in 0..volume.map_count() {
+                offset += ImsmMap::new(buffer.get(offset..)?).size();
+            }
+        }
+        None
+    }
+
+    fn current_volume(&self) -> Option<VolumeAndMap<'_>> {
+        self.volume(self.volume_index)
+    }
+
+    fn find_disk_by_serial(&self, serial: &[u8]) -> Option<usize> {
+        self.disks()
+            .position(|disk| !disk.serial_bytes().is_empty() && disk.serial_bytes() == serial)
+    }
+}
+
+impl<S: AsRef<[u8]>> Superblock for SuperblockImsm<S> {
+    fn valid(&self) -> bool {
+        self.valid_signature()
+            && self.valid_size()
+            && self.valid_checksum()
+            && self.current_volume().is_some()
+    }
+
+    fn major_version(&self) -> u32 {
+        self.view().signature()[Self::SIGNATURE.len()..]
+            .split(|&c| c == b'.')
+            .next()
+            .and_then(|major| std::str::from_utf8(major).ok())
+            .and_then(|major| major.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn minor_version(&self) -> u32 {
+        self.view().signature()[Self::SIGNATURE.len()..]
+            .split(|&c| c == b'.')
+            .nth(1)
+            .and_then(|minor| std::str::from_utf8(minor).ok())
+            .and_then(|minor| minor.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn array_uuid(&self) -> ArrayUuid {
+        ArrayUuid::from_u32(self.family_number())
+    }
+
+    fn array_name(&self) -> Option<&OsStr> {
+        self.current_volume().map(|(volume, _)| {
+            let name = volume.into_name().into_slice();
+            OsStr::from_bytes(&name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())])
+        })
+    }
+
+    fn algorithm(&self) -> MdAlgorithm {
+        match self.current_volume() {
+            // IMSM RAID10 is recorded as RAID1 with four members, and is
+            // equivalent to md RAID10 with the near=2 layout. Like RAID0 and
+            // RAID1, it is reported as unsupported.
+            Some((_, map)) if map.raid_level().read() == 1 && map.member_count().read() == 4 => {
+                MdAlgorithm::Unsupported {
+                    level: 10,
+                    layout: 0x102,
+                }
+            }
+            // IMSM RAID5 always uses the left-asymmetric layout.
+            Some((_, map)) => MdAlgorithm::from_level_and_layout(map.raid_level().read().into(), 0),
+            None => MdAlgorithm::Unsupported {
+                level: 0,
+                layout: 0,
+            },
+        }
+    }
+
+    fn sectors_per_device(&self) -> SectorCount<u64> {
+        self.current_volume()
+            .map(|(_, map)| map.sectors_per_member())
+            .unwrap_or(SectorCount(0))
+    }
+
+    fn chunk_size(&self) -> SectorCount<u32> {
+        self.current_volume()
+            .map(|(_, map)| map.chunk_size())
+            .unwrap_or(SectorCount(0))
+    }
+
+    fn raid_device_count(&self) -> DeviceCount {
+        self.current_volume()
+            .map(|(_, map)| map.member_device_count())
+            .unwrap_or(DeviceCount(0))
+    }
+
+    fn reshape_status(&self) -> Option<ReshapeStatus> {
+        None
+    }
+
+    fn data_offset(&self) -> SectorNumber {
+        self.current_volume()
+            .map(|(_, map)| map.data_offset())
+            .unwrap_or(SectorNumber(0))
+    }
+
+    fn data_size(&self) -> Option<SectorCount<u64>> {
+        None
+    }
+
+    fn super_offset(&self) -> Option<SectorNumber> {
+        None
+    }
+
+    fn features(&self) -> Option<Features> {
+        None
+    }
+
+    /// Returns the index of this disk in the disk table, or the size of the
+    /// disk table if the disk could not be identified.
+    fn device_role_index(&self) -> usize {
+        self.disk_index.unwrap_or(self.disk_count())
+    }
+
+    fn event_count(&self) -> MetadataEventCount {
+        MetadataEventCount(self.view().generation_number().read().into())
+    }
+
+    fn creation_time(&self) -> Option<Timestamp> {
+        None
+    }
+
+    fn update_time(&self) -> Option<Timestamp> {
+        None
+    }
+
+    fn device_roles(&self) -> Vec<MdDeviceRole> {
+        let members = self
+            .current_volume()
+            .map(|(_, map)| map.members())
+            .unwrap_or_default();
+        self.disks()
+            .enumerate()
+            .map(|(disk_index, disk)| {
+                match members
+                    .iter()
+                    .position(|&(member_disk_index, _)| member_disk_index == disk_index)
+                {
+                    Some(position) if !members[position].1 => MdDeviceRole::from_device_number(
+                        DeviceNumber(u32::try_from(position).unwrap()),
+                    ),
+                    Some(_) => MdDeviceRole::faulty(),
+                    None if disk.status().read().contains(DiskStatus::FAILED) => {
+                        MdDeviceRole::faulty()
+                    }
+                    None => MdDeviceRole::spare(),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,272 @@
+use crate::block_device::{BlockDeviceReader, BlockSize, InMemoryBlockDevice};
+use crate::md::algorithm::MdAlgorithm;
+use crate::md::diagnosis::MdArrayHealth;
+use crate::md::raid5::Raid5Algorithm;
+use crate::md::superblock::{ArrayUuid, MdDeviceRole, Superblock, SuperblockImsm};
+use crate::md::units::{DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber};
+use crate::md::{MdArray, MdDevice, MdSuperblockKind};
+use byteorder::{ByteOrder, LittleEndian};
+use std::ffi::OsStr;
+
+const DATA: [u8; 536] = [
+    0x49, 0x6e, 0x74, 0x65, 0x6c, 0x20, 0x52, 0x61, 0x69, 0x64, 0x20, 0x49, 0x53, 0x4d, 0x20, 0x43,
+    0x66, 0x67, 0x20, 0x53, 0x69, 0x67, 0x2e, 0x20, 0x31, 0x2e, 0x32, 0x2e, 0x30, 0x32, 0x00,
+    0x00, // signature
+    0xff, 0x50, 0x11, 0x81, // checksum
+    0x18, 0x02, 0x00, 0x00, // MPB size
+    0x4d, 0x3c, 0x2b, 0x1a, // family number
+    0x2a, 0x00, 0x00, 0x00, // generation number
+    0x00, 0x00, 0x00, 0x00, // error log size
+    0x00, 0x00, 0x00, 0x00, // attributes
+    0x03, // number of disks
+    0x01, // number of volumes
+    0x00, // error log position
+    0x00, // fill
+    0x00, 0x00, 0x00, 0x00, // cache size
+    0x4d, 0x3c, 0x2b, 0x1a, // original family number
+    0x00, 0x00, 0x00, 0x00, // power cycle count
+    0x00, 0x00, 0x00, 0x00, // bad block log size
+    0x01, 0x00, // number of volumes created
+    0x00, 0x00, // filler
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // creation time
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // filler
+    0x53, 0x45, 0x52, 0x49, 0x41, 0x4c, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // disk 0 serial
+    0x00, 0x00, 0x02, 0x00, // disk 0 total blocks low
+    0x00, 0x00, 0x00, 0x00, // disk 0 SCSI ID
+    0x02, 0x00, 0x00, 0x00, // disk 0 status
+    0x00, 0x00, 0x00, 0x00, // disk 0 owner config number
+    0x00, 0x00, 0x00, 0x00, // disk 0 total blocks high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // disk 0 filler
+    0x53, 0x45, 0x52, 0x49, 0x41, 0x4c, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // disk 1 serial
+    0x00, 0x00, 0x02, 0x00, // disk 1 total blocks low
+    0x01, 0x00, 0x00, 0x00, // disk 1 SCSI ID
+    0x02, 0x00, 0x00, 0x00, // disk 1 status
+    0x00, 0x00, 0x00, 0x00, // disk 1 owner config number
+    0x00, 0x00, 0x00, 0x00, // disk 1 total blocks high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // disk 1 filler
+    0x53, 0x45, 0x52, 0x49, 0x41, 0x4c, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // disk 2 serial
+    0x00, 0x00, 0x02, 0x00, // disk 2 total blocks low
+    0x02, 0x00, 0x00, 0x00, // disk 2 SCSI ID
+    0x02, 0x00, 0x00, 0x00, // disk 2 status
+    0x00, 0x00, 0x00, 0x00, // disk 2 owner config number
+    0x00, 0x00, 0x00, 0x00, // disk 2 total blocks high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // disk 2 filler
+    0x56, 0x6f, 0x6c, 0x75, 0x6d, 0x65, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // volume name
+    0x00, 0xe0, 0x03, 0x00, // volume size low
+    0x00, 0x00, 0x00, 0x00, // volume size high
+    0x00, 0x00, 0x00, 0x00, // volume status
+    0x00, 0x00, 0x00, 0x00, // reserved blocks
+    0x00, // migration priority
+    0x00, // number of sub-volumes
+    0x00, // tid
+    0x00, // CNG master disk
+    0x00, 0x00, // cache policy
+    0x00, // CNG state
+    0x00, // CNG sub-state
+    0x00, 0x00, // volume number
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // filler
+    0x00, 0x00, 0x00, 0x00, // current migration unit low
+    0x00, 0x00, 0x00, 0x00, // checkpoint ID
+    0x00, // migration state
+    0x00, // migration type
+    0x00, // dirty
+    0xff, // fast sync state
+    0x00, 0x00, // verify errors
+    0x00, 0x00, // bad blocks
+    0x00, 0x00, 0x00, 0x00, // current migration unit high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // filler
+    0x00, 0x08, 0x00, 0x00, // PBA of LBA0 low
+    0x00, 0xf0, 0x01, 0x00, // blocks per member low
+    0xf0, 0x01, 0x00, 0x00, // number of data stripes low
+    0x00, 0x01, // blocks per strip
+    0x00, // map state
+    0x05, // RAID level
+    0x03, // number of members
+    0x01, // number of domains
+    0xff, // failed disk number
+    0x01, // DDF
+    0x00, 0x00, 0x00, 0x00, // PBA of LBA0 high
+    0x00, 0x00, 0x00, 0x00, // blocks per member high
+    0x00, 0x00, 0x00, 0x00, // number of data stripes high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, // filler
+    0x01, 0x00, 0x00, 0x00, // member 0 disk index
+    0x02, 0x00, 0x00, 0x00, // member 1 disk index
+    0x00, 0x00, 0x00, 0x00, // member 2 disk index
+];
+
+const DEVICE_SIZE: usize = 0x20000 * 512;
+
+fn device(data: &[u8]) -> BlockDeviceReader<InMemoryBlockDevice> {
+    let mut mem = vec![0u8; DEVICE_SIZE];
+    mem[DEVICE_SIZE - 1024..][..512].copy_from_slice(&data[..512]);
+    mem[DEVICE_SIZE - 1536..][..data.len() - 512].copy_from_slice(&data[512..]);
+    BlockDeviceReader::new(InMemoryBlockDevice::new(mem, BlockSize(512)))
+}
+
+/// The metadata with a copy of its volume, named "Volume1".
+fn two_volumes() -> Vec<u8> {
+    let volume = &DATA[216 + 3 * 48..];
+    let mut data = [&DATA[..], volume].concat();
+    data[DATA.len() + 6] = b'1';
+    data[57] = 2;
+    let size = data.len() as u32;
+    LittleEndian::write_u32(&mut data[36..], size);
+    update_checksum(&mut data);
+    data
+}
+
+fn update_checksum(data: &mut [u8]) {
+    LittleEndian::write_u32(&mut data[32..], 0);
+    let checksum = data
+        .chunks_exact(4)
+        .map(LittleEndian::read_u32)
+        .fold(0u32, u32::wrapping_add);
+    LittleEndian::write_u32(&mut data[32..], checksum);
+}
+
+#[test]
+fn imsm_superblock() {
+    let superblock = SuperblockImsm::new(DATA, 0);
+    assert!(superblock.valid());
+    assert_eq!(superblock.major_version(), 1);
+    assert_eq!(superblock.minor_version(), 2);
+    assert_eq!(superblock.array_uuid(), ArrayUuid::from_u32(0x1a2b3c4d));
+    assert_eq!(superblock.array_name(), Some(OsStr::new("Volume0")));
+    assert_eq!(
+        superblock.algorithm(),
+        MdAlgorithm::Raid5(Raid5Algorithm::LeftAsymmetric)
+    );
+    assert_eq!(superblock.sectors_per_device(), SectorCount(0x1f000));
+    assert_eq!(superblock.chunk_size(), SectorCount(256));
+    assert_eq!(superblock.raid_device_count(), DeviceCount(3));
+    assert_eq!(superblock.reshape_status(), None);
+    assert_eq!(superblock.data_offset(), SectorNumber(0x800));
+    assert_eq!(superblock.event_count(), MetadataEventCount(0x2a));
+    assert_eq!(
+        superblock.device_roles(),
+        vec![
+            MdDeviceRole::from_device_number(DeviceNumber(2)),
+            MdDeviceRole::from_device_number(DeviceNumber(0)),
+            MdDeviceRole::from_device_number(DeviceNumber(1)),
+        ]
+    );
+}
+
+#[test]
+fn imsm_superblock_with_bad_checksum() {
+    let mut data = DATA;
+    data[0x80] ^= 1;
+    assert!(!SuperblockImsm::new(data, 0).valid());
+}
+
+#[test]
+fn imsm_superblock_with_missing_volume() {
+    assert!(!SuperblockImsm::new(DATA, 1).valid());
+}
+
+#[test]
+fn read_imsm_superblock_identified_by_serial() -> anyhow::Result<()> {
+    let superblock =
+        SuperblockImsm::read(device(&DATA), DEVICE_SIZE as u64, Some(b"SERIAL1"))?.remove(0);
+    assert_eq!(superblock.disk_index(), Some(1));
+    assert_eq!(superblock.device_role_index(), 1);
+    assert_eq!(superblock.array_name(), Some(OsStr::new("Volume0")));
+    Ok(())
+}
+
+#[test]
+fn read_imsm_superblock_of_unidentified_disk() -> anyhow::Result<()> {
+    let superblock =
+        SuperblockImsm::read(device(&DATA), DEVICE_SIZE as u64, Some(b"SERIAL9"))?.remove(0);
+    assert_eq!(superblock.disk_index(), None);
+    assert_eq!(superblock.device_role_index(), 3);
+    Ok(())
+}
+
+#[test]
+fn read_imsm_superblock_of_each_volume() -> anyhow::Result<()> {
+    let data = two_volumes();
+    let superblocks = SuperblockImsm::read(device(&data), DEVICE_SIZE as u64, Some(b"SERIAL2"))?;
+    assert_eq!(
+        superblocks
+            .iter()
+            .map(|superblock| (
+                superblock.volume_index(),
+                superblock.array_name(),
+                superblock.device_role_index()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (0, Some(OsStr::new("Volume0")), 2),
+            (1, Some(OsStr::new("Volume1")), 2)
+        ]
+    );
+
+    let mut device = MdDevice::from_block_device_with_serial(
+        device(&data).into_inner(),
+        Some("disk"),
+        Some(b"SERIAL2"),
+    )?;
+    assert_eq!(
+        device
+            .superblock_candidates
+            .iter()
+            .map(|candidate| candidate.location.to_string())
+            .collect::<Vec<_>>(),
+        [
+            format!("imsm volume 0 at byte {}", DEVICE_SIZE - 1024),
+            format!("imsm volume 1 at byte {}", DEVICE_SIZE - 1024)
+        ]
+    );
+    device.select_superblock(MdSuperblockKind::Imsm, Some(1))?;
+    assert_eq!(
+        device.superblock.as_option().unwrap().array_name(),
+        Some(OsStr::new("Volume1"))
+    );
+    Ok(())
+}
+
+#[test]
+fn diagnose_unsupported_imsm_volume() -> anyhow::Result<()> {
+    // A RAID1 volume.
+    let mut data = DATA;
+    data[DATA.len() - 45] = 1;
+    update_checksum(&mut data);
+    let superblock = SuperblockImsm::new(data, 0);
+    assert!(superblock.valid());
+    assert_eq!(
+        superblock.algorithm(),
+        MdAlgorithm::Unsupported {
+            level: 1,
+            layout: 0
+        }
+    );
+
+    let device = MdDevice::from_block_device_with_serial(
+        device(&data).into_inner(),
+        Some("disk"),
+        Some(b"SERIAL0"),
+    )?;
+    let diagnosis = MdArray::open([device]).diagnose();
+    assert_eq!(diagnosis.health, MdArrayHealth::NotAssemblable);
+    assert!(diagnosis.to_string().contains(
+        "- The array is RAID1, which cannot be read. Only RAID5 and RAID6 arrays are supported.\n"
+    ));
+    Ok(())
+}
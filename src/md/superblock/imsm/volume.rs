@@ -0,0 +1,42 @@
+use binary_layout::prelude::*;
+
+pub use layout::View as ImsmVolume;
+
+binary_layout!(layout, LittleEndian, {
+    name: [u8; 16],
+    size_low: u32,
+    size_high: u32,
+    status: u32,
+    reserved_blocks: u32,
+    migration_priority: u8,
+    sub_volume_count: u8,
+    tid: u8,
+    cng_master_disk: u8,
+    cache_policy: u16,
+    cng_state: u8,
+    cng_sub_state: u8,
+    volume_number: u16,
+    filler_0: [u8; 38],
+    current_migration_unit_low: u32,
+    checkpoint_id: u32,
+    migration_state: u8,
+    migration_type: u8,
+    dirty: u8,
+    fs_state: u8,
+    verify_errors: u16,
+    bad_blocks: u16,
+    current_migration_unit_high: u32,
+    filler_1: [u8; 16]
+});
+
+impl<S: AsRef<[u8]>> ImsmVolume<S> {
+    pub const SIZE: usize = layout::SIZE.unwrap();
+
+    pub fn map_count(&self) -> usize {
+        if self.migration_state().read() == 0 {
+            1
+        } else {
+            2
+        }
+    }
+}
@@ -1,4 +1,6 @@
 mod array_uuid;
+mod ddf;
+mod imsm;
 mod reshape_status;
 mod role;
 mod superblock;
@@ -7,6 +9,12 @@ mod version_1;
 
 #[allow(unused_imports)]
 pub use self::{
-    array_uuid::ArrayUuid, reshape_status::ReshapeStatus, role::MdDeviceRole,
-    superblock::Superblock, version_0::SuperblockVersion0, version_1::SuperblockVersion1,
+    array_uuid::ArrayUuid,
+    ddf::SuperblockDdf,
+    imsm::SuperblockImsm,
+    reshape_status::ReshapeStatus,
+    role::MdDeviceRole,
+    superblock::{MdSuperblockVersion, NewSuperblock, Superblock, SuperblockMut},
+    version_0::SuperblockVersion0,
+    version_1::{Features, SuperblockVersion1},
 };
@@ -1,6 +1,7 @@
 use crate::md::units::DeviceNumber;
 use binary_layout::LayoutAs;
 use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Debug)]
 pub struct MdDeviceRole(u32);
@@ -15,6 +16,22 @@ impl MdDeviceRole {
         Self(value.into())
     }
 
+    pub(super) fn to_u16(self) -> Option<u16> {
+        u16::try_from(self.0).ok()
+    }
+
+    pub fn from_device_number(device_number: DeviceNumber) -> Self {
+        Self(device_number.into())
+    }
+
+    pub fn spare() -> Self {
+        Self(Self::SPARE)
+    }
+
+    pub fn faulty() -> Self {
+        Self(Self::FAULTY)
+    }
+
     pub fn is_valid(&self) -> bool {
         !(Self::MAX_POSITION..Self::JOURNAL).contains(&self.0)
     }
@@ -44,6 +61,18 @@ impl MdDeviceRole {
     }
 }
 
+impl Display for MdDeviceRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.device_number() {
+            Some(device_number) => write!(f, "{device_number}"),
+            None if self.is_spare() => write!(f, "spare"),
+            None if self.is_faulty() => write!(f, "faulty"),
+            None if self.is_journal() => write!(f, "journal"),
+            None => write!(f, "invalid role {:#x}", self.0),
+        }
+    }
+}
+
 impl LayoutAs<u32> for MdDeviceRole {
     type ReadError = Infallible;
     type WriteError = Infallible;
@@ -1,9 +1,24 @@
 use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::io;
 
-use super::{ArrayUuid, MdDeviceRole};
+use super::{ArrayUuid, Features, MdDeviceRole};
 use crate::md::algorithm::MdAlgorithm;
+use crate::md::format::MdFormat;
 use crate::md::superblock::reshape_status::ReshapeStatus;
-use crate::md::units::{DeviceCount, MetadataEventCount, SectorCount, SectorNumber};
+use crate::md::units::{DeviceCount, MetadataEventCount, SectorCount, SectorNumber, Timestamp};
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Debug)]
+pub struct MdSuperblockVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Display for MdSuperblockVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
 
 pub trait Superblock {
     fn valid(&self) -> bool;
@@ -17,11 +32,72 @@ pub trait Superblock {
     fn raid_device_count(&self) -> DeviceCount;
     fn reshape_status(&self) -> Option<ReshapeStatus>;
     fn data_offset(&self) -> SectorNumber;
+
+    /// The size of the data area of this device, if recorded.
+    fn data_size(&self) -> Option<SectorCount<u64>>;
+
+    /// Where the superblock is on this device, if recorded.
+    fn super_offset(&self) -> Option<SectorNumber>;
+
+    /// The optional features of the array, for superblocks that have any.
+    fn features(&self) -> Option<Features>;
     fn device_role_index(&self) -> usize;
     fn event_count(&self) -> MetadataEventCount;
+
+    /// When the array was created, for superblocks that record it.
+    fn creation_time(&self) -> Option<Timestamp>;
+
+    /// When this superblock was last written, for superblocks that record it.
+    fn update_time(&self) -> Option<Timestamp>;
     fn device_roles(&self) -> Vec<MdDeviceRole>;
 }
 
+/// A superblock that can be changed, and written back as it is on disk.
+/// Every change brings the checksum up to date.
+pub trait SuperblockMut: Superblock {
+    fn set_event_count(&mut self, event_count: MetadataEventCount);
+
+    /// Sets the role of each device, by device role index.
+    fn set_device_roles(&mut self, roles: &[MdDeviceRole]) -> io::Result<()>;
+    fn set_device_role_index(&mut self, index: usize) -> io::Result<()>;
+
+    /// Forgets a reshape in progress, so that the array is assembled as it
+    /// was laid out before the reshape began.
+    fn clear_reshape(&mut self);
+
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+/// What a superblock written from scratch holds, for one member of an
+/// array.
+#[derive(PartialEq, Clone, Debug)]
+pub struct NewSuperblock {
+    pub array_uuid: [u8; 16],
+
+    /// Only kept by version 1 superblocks, and cut to 32 bytes.
+    pub array_name: Vec<u8>,
+    pub format: MdFormat,
+    pub creation_time: Timestamp,
+    pub update_time: Timestamp,
+    pub event_count: MetadataEventCount,
+
+    /// A reshape in progress, if any. Version 0.90 superblocks cannot move
+    /// the data to a new offset.
+    pub reshape_status: Option<ReshapeStatus>,
+
+    /// The role of each device, by device role index.
+    pub device_roles: Vec<MdDeviceRole>,
+    pub device_role_index: usize,
+
+    /// The fields below are only kept by version 1 superblocks, as version
+    /// 0.90 has the data at the start of each member, and the superblock at
+    /// a fixed place near its end.
+    pub device_uuid: [u8; 16],
+    pub data_offset: SectorNumber,
+    pub data_size: SectorCount<u64>,
+    pub super_offset: SectorNumber,
+}
+
 impl Superblock for Box<dyn Superblock> {
     fn valid(&self) -> bool {
         (**self).valid()
@@ -67,6 +143,18 @@ impl Superblock for Box<dyn Superblock> {
         (**self).data_offset()
     }
 
+    fn data_size(&self) -> Option<SectorCount<u64>> {
+        (**self).data_size()
+    }
+
+    fn super_offset(&self) -> Option<SectorNumber> {
+        (**self).super_offset()
+    }
+
+    fn features(&self) -> Option<Features> {
+        (**self).features()
+    }
+
     fn device_role_index(&self) -> usize {
         (**self).device_role_index()
     }
@@ -75,7 +163,82 @@ impl Superblock for Box<dyn Superblock> {
         (**self).event_count()
     }
 
+    fn creation_time(&self) -> Option<Timestamp> {
+        (**self).creation_time()
+    }
+
+    fn update_time(&self) -> Option<Timestamp> {
+        (**self).update_time()
+    }
+
     fn device_roles(&self) -> Vec<MdDeviceRole> {
         (**self).device_roles()
     }
 }
+
+#[cfg(test)]
+pub(in crate::md::superblock) mod test {
+    use crate::md::algorithm::MdAlgorithm;
+    use crate::md::format::MdFormat;
+    use crate::md::superblock::{MdDeviceRole, NewSuperblock, Superblock};
+    use crate::md::units::{
+        DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
+    };
+
+    /// The second member of a four-device RAID5 with a spare.
+    pub(in crate::md::superblock) fn new_superblock() -> NewSuperblock {
+        NewSuperblock {
+            array_uuid: *b"0123456789abcdef",
+            array_name: b"host:data".to_vec(),
+            format: MdFormat {
+                algorithm: MdAlgorithm::from_level_and_layout(5, 2),
+                device_count: DeviceCount(4),
+                sectors_per_device: SectorCount(2048),
+                chunk_size: SectorCount(128),
+            },
+            creation_time: Timestamp {
+                seconds: 1_600_000_000,
+                microseconds: 0,
+            },
+            update_time: Timestamp {
+                seconds: 1_700_000_000,
+                microseconds: 0,
+            },
+            event_count: MetadataEventCount(42),
+            reshape_status: None,
+            device_roles: vec![
+                MdDeviceRole::from_device_number(DeviceNumber(0)),
+                MdDeviceRole::from_device_number(DeviceNumber(1)),
+                MdDeviceRole::from_device_number(DeviceNumber(2)),
+                MdDeviceRole::from_device_number(DeviceNumber(3)),
+                MdDeviceRole::spare(),
+            ],
+            device_role_index: 1,
+            device_uuid: *b"fedcba9876543210",
+            data_offset: SectorNumber(2048),
+            data_size: SectorCount(2048),
+            super_offset: SectorNumber(8),
+        }
+    }
+
+    /// Checks that a superblock reads back what it was created from.
+    pub(in crate::md::superblock) fn assert_created(superblock: &dyn Superblock) {
+        let new = new_superblock();
+        assert!(superblock.valid());
+        assert_eq!(
+            superblock.array_uuid().to_mdadm_string(),
+            "30313233:34353637:38396162:63646566"
+        );
+        assert_eq!(superblock.algorithm(), new.format.algorithm);
+        assert_eq!(superblock.raid_device_count(), new.format.device_count);
+        assert_eq!(
+            superblock.sectors_per_device(),
+            new.format.sectors_per_device
+        );
+        assert_eq!(superblock.chunk_size(), new.format.chunk_size);
+        assert_eq!(superblock.event_count(), new.event_count);
+        assert_eq!(superblock.device_role_index(), new.device_role_index);
+        assert_eq!(superblock.device_roles()[..5], new.device_roles);
+        assert_eq!(superblock.reshape_status(), None);
+    }
+}
@@ -7,5 +7,5 @@ mod tests;
 #[allow(unused_imports)]
 pub use self::{
     device_descriptor::DeviceDescriptorBigEndian,
-    superblock::{View, SIZE},
+    superblock::{write, View, SIZE},
 };
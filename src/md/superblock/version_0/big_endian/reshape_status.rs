@@ -26,8 +26,20 @@ impl<S: AsRef<[u8]>> From<ReshapeStatusVersion0<S>> for ReshapeStatus {
             ),
             reshape_position: value.reshape_position().read(),
             delta_devices: value.delta_devices().read(),
-            new_chunk_size: value.new_chunk_size().read(),
+            // Kept in bytes.
+            new_chunk_size: SectorCount(value.new_chunk_size().read().0 / 512),
             new_offset: 0,
         }
     }
 }
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> ReshapeStatusVersion0<S> {
+    pub fn write(&mut self, status: &ReshapeStatus) {
+        self.reshape_position_mut().write(status.reshape_position);
+        self.new_level_mut().write(status.new_algorithm.level());
+        self.delta_devices_mut().write(status.delta_devices);
+        self.new_layout_mut().write(status.new_algorithm.layout());
+        self.new_chunk_size_mut()
+            .write(SectorCount(status.new_chunk_size.0.saturating_mul(512)));
+    }
+}
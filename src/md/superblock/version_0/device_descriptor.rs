@@ -1,5 +1,6 @@
 use crate::md::superblock::MdDeviceRole;
 
+#[derive(Clone)]
 pub struct DeviceDescriptor {
     pub index: u32,
     pub major: u32,
@@ -19,6 +19,16 @@ impl<S: AsRef<[u8]>> DeviceDescriptorLittleEndian<S> {
     pub const SIZE: usize = layout::SIZE.unwrap();
 }
 
+impl<S: AsRef<[u8]> + AsMut<[u8]>> DeviceDescriptorLittleEndian<S> {
+    pub fn write(&mut self, descriptor: &DeviceDescriptor) {
+        self.index_mut().write(descriptor.index);
+        self.major_mut().write(descriptor.major);
+        self.minor_mut().write(descriptor.minor);
+        self.role_mut().write(descriptor.role);
+        self.state_mut().write(descriptor.state);
+    }
+}
+
 impl<S: AsRef<[u8]>> From<DeviceDescriptorLittleEndian<S>> for DeviceDescriptor {
     fn from(value: DeviceDescriptorLittleEndian<S>) -> Self {
         Self {
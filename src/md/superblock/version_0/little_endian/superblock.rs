@@ -3,7 +3,7 @@ use super::device_descriptor::{DeviceDescriptorLittleEndian, NestedDeviceDescrip
 use super::reshape_status::NestedReshapeStatusVersion0;
 use crate::md::superblock::SuperblockVersion0;
 use crate::md::units::{CheckpointEventCount, DeviceCount, MetadataEventCount, SectorCount};
-use binary_layout::binary_layout;
+use binary_layout::{binary_layout, Field};
 
 binary_layout!(layout, LittleEndian, {
     magic: u32,
@@ -48,6 +48,13 @@ pub const SIZE: usize = layout::SIZE.unwrap();
 
 impl<S: AsRef<[u8]>> From<View<S>> for SuperblockVersion0 {
     fn from(value: View<S>) -> Self {
+        let storage = value.into_storage();
+        let expected_checksum = SuperblockVersion0::compute_checksum(
+            storage.as_ref(),
+            layout::superblock_checksum::OFFSET,
+            u32::from_le_bytes,
+        );
+        let value = View::new(storage.as_ref());
         let device_descriptor_buffer = value.devices().as_slice();
         let devices = Vec::from_iter((0..SuperblockVersion0::MAX_DEVICES).map(|i| {
             DeviceDescriptorLittleEndian::new(array_ref![
@@ -82,6 +89,7 @@ impl<S: AsRef<[u8]>> From<View<S>> for SuperblockVersion0 {
             failed_device_count: value.failed_device_count().read(),
             spare_device_count: value.spare_device_count().read(),
             superblock_checksum: value.superblock_checksum().read(),
+            expected_checksum,
             event_count: value.event_count().read(),
             checkpoint_event_count: value.checkpoint_event_count().read(),
             recovery_checkpoint: value.recovery_checkpoint().read(),
@@ -92,6 +100,70 @@ impl<S: AsRef<[u8]>> From<View<S>> for SuperblockVersion0 {
             root_block: value.root_block().read(),
             devices,
             this_device: value.this_device().into(),
+            big_endian: false,
+            buffer: storage.as_ref().to_vec(),
         }
     }
 }
+
+/// Lays out `superblock` over `buffer`, leaving the bytes its fields do not
+/// cover as they are, with the checksum worked out anew, and returns the
+/// checksum.
+pub fn write(superblock: &SuperblockVersion0, buffer: &mut [u8]) -> u32 {
+    let mut view = View::new(&mut *buffer);
+    view.magic_mut().write(superblock.magic);
+    view.major_version_mut().write(superblock.major_version);
+    view.minor_version_mut().write(superblock.minor_version);
+    view.patch_version_mut().write(superblock.patch_version);
+    view.gvalid_words_mut().write(superblock.gvalid_words);
+    view.array_uuid_0_mut().write(superblock.array_uuid_0);
+    view.ctime_mut().write(superblock.ctime);
+    view.level_mut().write(superblock.level);
+    view.sectors_per_device_mut()
+        .write(superblock.sectors_per_device);
+    view.total_device_count_mut()
+        .write(superblock.total_device_count);
+    view.raid_device_count_mut()
+        .write(superblock.raid_device_count);
+    view.md_minor_mut().write(superblock.md_minor);
+    view.not_persistent_mut().write(superblock.not_persistent);
+    view.array_uuid_1_mut().write(superblock.array_uuid_1);
+    view.array_uuid_2_mut().write(superblock.array_uuid_2);
+    view.array_uuid_3_mut().write(superblock.array_uuid_3);
+    view.utime_mut().write(superblock.utime);
+    view.state_mut().write(superblock.state);
+    view.active_device_count_mut()
+        .write(superblock.active_device_count);
+    view.working_device_count_mut()
+        .write(superblock.working_device_count);
+    view.failed_device_count_mut()
+        .write(superblock.failed_device_count);
+    view.spare_device_count_mut()
+        .write(superblock.spare_device_count);
+    view.event_count_mut().write(superblock.event_count);
+    view.checkpoint_event_count_mut()
+        .write(superblock.checkpoint_event_count);
+    view.recovery_checkpoint_mut()
+        .write(superblock.recovery_checkpoint);
+    view.reshape_status_mut().write(&superblock.reshape_status);
+    view.layout_mut().write(superblock.layout);
+    view.chunk_size_mut().write(superblock.chunk_size);
+    view.root_pv_mut().write(superblock.root_pv);
+    view.root_block_mut().write(superblock.root_block);
+    for (index, descriptor) in superblock.devices.iter().enumerate() {
+        DeviceDescriptorLittleEndian::new(
+            &mut view.devices_mut()[index * DeviceDescriptorLittleEndian::<&[u8]>::SIZE..]
+                [..DeviceDescriptorLittleEndian::<&[u8]>::SIZE],
+        )
+        .write(descriptor);
+    }
+    view.this_device_mut().write(&superblock.this_device);
+
+    let checksum = SuperblockVersion0::compute_checksum(
+        buffer,
+        layout::superblock_checksum::OFFSET,
+        u32::from_le_bytes,
+    );
+    View::new(buffer).superblock_checksum_mut().write(checksum);
+    checksum
+}
@@ -1,5 +1,8 @@
+use crate::md::superblock::superblock::test::{assert_created, new_superblock};
 use crate::md::superblock::version_0::little_endian;
 use crate::md::superblock::version_0::little_endian::device_descriptor::DeviceDescriptorLittleEndian;
+use crate::md::superblock::SuperblockMut;
+use crate::md::superblock::SuperblockVersion0;
 use crate::md::units::{
     CheckpointEventCount, DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber,
 };
@@ -520,3 +523,30 @@ fn view_of_little_endian_superblock_version_0_device_2() {
     );
     assert_eq!(descriptor.state().read(), 0);
 }
+
+#[test]
+fn checksum_of_little_endian_superblock_version_0() {
+    let superblock = SuperblockVersion0::from(little_endian::View::new(DATA));
+    assert_eq!(superblock.expected_checksum, 0xa901f9de);
+    assert!(!superblock.valid_checksum());
+
+    let mut data = DATA;
+    let mut view = little_endian::View::new(&mut data);
+    view.superblock_checksum_mut().write(0xa901f9de);
+    assert!(SuperblockVersion0::from(little_endian::View::new(data)).valid_checksum());
+}
+
+#[test]
+fn create_little_endian_superblock_version_0() {
+    let bytes = SuperblockVersion0::create(&new_superblock(), false)
+        .unwrap()
+        .to_bytes();
+    assert_eq!(bytes.len(), SuperblockVersion0::SIZE_ON_DISK);
+    assert_created(&SuperblockVersion0::read(&bytes[..]).unwrap());
+    let superblock = SuperblockVersion0::read(&bytes[..]).unwrap();
+    assert_eq!(superblock.total_device_count, DeviceCount(5));
+    assert_eq!(superblock.active_device_count, DeviceCount(4));
+    assert_eq!(superblock.working_device_count, DeviceCount(5));
+    assert_eq!(superblock.failed_device_count, DeviceCount(0));
+    assert_eq!(superblock.spare_device_count, DeviceCount(1));
+}
@@ -2,10 +2,13 @@ use crate::md::algorithm::MdAlgorithm;
 use crate::md::superblock::reshape_status::ReshapeStatus;
 use crate::md::superblock::version_0::device_descriptor::DeviceDescriptor;
 use crate::md::superblock::version_0::{big_endian, little_endian};
-use crate::md::superblock::{ArrayUuid, MdDeviceRole, Superblock};
+use crate::md::superblock::{
+    ArrayUuid, Features, MdDeviceRole, NewSuperblock, Superblock, SuperblockMut,
+};
 use crate::md::units::{
-    CheckpointEventCount, DeviceCount, MetadataEventCount, SectorCount, SectorNumber,
+    CheckpointEventCount, DeviceCount, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
 };
+use byteorder::{ByteOrder, LittleEndian};
 use std::ffi::OsStr;
 use std::io;
 use std::io::Read;
@@ -34,6 +37,7 @@ pub struct SuperblockVersion0 {
     pub(super) failed_device_count: DeviceCount,
     pub(super) spare_device_count: DeviceCount,
     pub(super) superblock_checksum: u32,
+    pub(super) expected_checksum: u32,
     pub(super) event_count: MetadataEventCount,
     pub(super) checkpoint_event_count: CheckpointEventCount,
     pub(super) recovery_checkpoint: u32,
@@ -44,6 +48,11 @@ pub struct SuperblockVersion0 {
     pub(super) root_block: u32,
     pub(super) devices: Vec<DeviceDescriptor>,
     pub(super) this_device: DeviceDescriptor,
+    pub(super) big_endian: bool,
+
+    /// The superblock as it was read, so that what the fields above do not
+    /// cover is written back as it was.
+    pub(super) buffer: Vec<u8>,
 }
 
 impl SuperblockVersion0 {
@@ -59,6 +68,131 @@ impl SuperblockVersion0 {
 
     pub const MAJOR_VERSION: u32 = 0;
 
+    const STATE_CLEAN: u32 = 1;
+    const DEVICE_FAULTY: u32 = 1;
+    const DEVICE_ACTIVE_SYNC: u32 = 6;
+
+    /// Writes a superblock from scratch, for an array that is clean, in the
+    /// byte order of a little or big endian machine.
+    pub fn create(new: &NewSuperblock, big_endian: bool) -> io::Result<Self> {
+        let mut array_uuid = [0u32; 4];
+        LittleEndian::read_u32_into(&new.array_uuid, &mut array_uuid);
+        let seconds = |timestamp: Timestamp| {
+            u32::try_from(timestamp.seconds).or(Err(io::ErrorKind::InvalidInput))
+        };
+        // The size is kept in KiB, and the chunk size in bytes.
+        let size = u32::try_from(u64::from(new.format.sectors_per_device) / 2)
+            .or(Err(io::ErrorKind::InvalidInput))?;
+        let chunk_size = u32::from(new.format.chunk_size)
+            .checked_mul(512)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        if new
+            .reshape_status
+            .as_ref()
+            .is_some_and(|status| status.new_offset != 0)
+        {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        let (total, active, working, failed, spare) = Self::count_devices(&new.device_roles);
+        let descriptor = DeviceDescriptor {
+            index: 0,
+            major: 0,
+            minor: 0,
+            role: MdDeviceRole::spare(),
+            state: 0,
+        };
+        let mut superblock = Self {
+            magic: Self::MAGIC,
+            major_version: Self::MAJOR_VERSION,
+            // The reshape status is only read from version 0.91 on.
+            minor_version: if new.reshape_status.is_some() { 91 } else { 90 },
+            patch_version: 0,
+            gvalid_words: 0,
+            array_uuid_0: array_uuid[0],
+            ctime: seconds(new.creation_time)?,
+            level: new.format.algorithm.level(),
+            sectors_per_device: SectorCount(size),
+            total_device_count: total,
+            raid_device_count: new.format.device_count,
+            md_minor: 0,
+            not_persistent: 0,
+            array_uuid_1: array_uuid[1],
+            array_uuid_2: array_uuid[2],
+            array_uuid_3: array_uuid[3],
+            utime: seconds(new.update_time)?,
+            state: Self::STATE_CLEAN,
+            active_device_count: active,
+            working_device_count: working,
+            failed_device_count: failed,
+            spare_device_count: spare,
+            superblock_checksum: 0,
+            expected_checksum: 0,
+            event_count: new.event_count,
+            checkpoint_event_count: CheckpointEventCount(new.event_count.into()),
+            recovery_checkpoint: 0,
+            reshape_status: new.reshape_status.clone().unwrap_or_else(Self::no_reshape),
+            layout: new.format.algorithm.layout(),
+            chunk_size: SectorCount(chunk_size),
+            root_pv: 0,
+            root_block: 0,
+            devices: vec![descriptor.clone(); Self::MAX_DEVICES],
+            this_device: descriptor,
+            big_endian,
+            buffer: vec![0u8; Self::SIZE_ON_DISK],
+        };
+        superblock.set_device_roles(&new.device_roles)?;
+        superblock.set_device_role_index(new.device_role_index)?;
+        Ok(superblock)
+    }
+
+    /// Whether the superblock was written by a big endian machine.
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Counts all the devices, then those that are active, working, failed
+    /// and spare, as mdadm keeps them in the superblock.
+    fn count_devices(
+        roles: &[MdDeviceRole],
+    ) -> (
+        DeviceCount,
+        DeviceCount,
+        DeviceCount,
+        DeviceCount,
+        DeviceCount,
+    ) {
+        let count = |filter: fn(&MdDeviceRole) -> bool| {
+            DeviceCount(roles.iter().filter(|role| filter(role)).count() as u32)
+        };
+        (
+            DeviceCount(roles.len() as u32),
+            count(|role| role.device_number().is_some()),
+            count(|role| role.device_number().is_some() || role.is_spare()),
+            count(MdDeviceRole::is_faulty),
+            count(MdDeviceRole::is_spare),
+        )
+    }
+
+    fn no_reshape() -> ReshapeStatus {
+        ReshapeStatus {
+            new_algorithm: MdAlgorithm::from_level_and_layout(0, 0),
+            reshape_position: SectorNumber(0),
+            delta_devices: DeviceCount(0),
+            new_chunk_size: SectorCount(0),
+            new_offset: 0,
+        }
+    }
+
+    fn update_checksum(&mut self) {
+        let checksum = if self.big_endian {
+            big_endian::write(self, &mut self.buffer.clone())
+        } else {
+            little_endian::write(self, &mut self.buffer.clone())
+        };
+        self.superblock_checksum = checksum;
+        self.expected_checksum = checksum;
+    }
+
     pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
         let mut buffer = [0u8; Self::SIZE_ON_DISK];
         reader.read_exact(&mut buffer)?;
@@ -75,6 +209,26 @@ impl SuperblockVersion0 {
         }
     }
 
+    /// Sums the superblock as 32-bit words, with the checksum field counted
+    /// as zero, and folds the carry back into the low 32 bits.
+    pub(super) fn compute_checksum(
+        buffer: &[u8],
+        checksum_offset: usize,
+        read_word: fn([u8; 4]) -> u32,
+    ) -> u32 {
+        let sum = buffer
+            .chunks_exact(size_of::<u32>())
+            .enumerate()
+            .filter(|(index, _)| index * size_of::<u32>() != checksum_offset)
+            .map(|(_, word)| u64::from(read_word(word.try_into().unwrap())))
+            .sum::<u64>();
+        ((sum & 0xffffffff) + (sum >> 32)) as u32
+    }
+
+    pub fn valid_checksum(&self) -> bool {
+        self.superblock_checksum == self.expected_checksum
+    }
+
     fn valid_magic(&self) -> bool {
         self.magic == Self::MAGIC
     }
@@ -125,26 +279,41 @@ impl Superblock for SuperblockVersion0 {
         MdAlgorithm::from_level_and_layout(self.level, self.layout)
     }
 
+    /// Kept in KiB.
     fn sectors_per_device(&self) -> SectorCount<u64> {
-        self.sectors_per_device.into()
+        SectorCount(u64::from(self.sectors_per_device.0) * 2)
     }
 
+    /// Kept in bytes.
     fn chunk_size(&self) -> SectorCount<u32> {
-        self.chunk_size
+        SectorCount(self.chunk_size.0 / 512)
     }
 
     fn raid_device_count(&self) -> DeviceCount {
         self.raid_device_count
     }
 
+    /// Version 0.91 marks a reshape in progress.
     fn reshape_status(&self) -> Option<ReshapeStatus> {
-        Some(self.reshape_status.clone())
+        (self.minor_version >= 91).then(|| self.reshape_status.clone())
     }
 
     fn data_offset(&self) -> SectorNumber {
         SectorNumber(0)
     }
 
+    fn data_size(&self) -> Option<SectorCount<u64>> {
+        None
+    }
+
+    fn super_offset(&self) -> Option<SectorNumber> {
+        None
+    }
+
+    fn features(&self) -> Option<Features> {
+        None
+    }
+
     fn device_role_index(&self) -> usize {
         self.this_device.index.try_into().unwrap()
     }
@@ -153,6 +322,14 @@ impl Superblock for SuperblockVersion0 {
         self.event_count
     }
 
+    fn creation_time(&self) -> Option<Timestamp> {
+        Some(Timestamp::from_seconds(self.ctime.into()))
+    }
+
+    fn update_time(&self) -> Option<Timestamp> {
+        Some(Timestamp::from_seconds(self.utime.into()))
+    }
+
     fn device_roles(&self) -> Vec<MdDeviceRole> {
         self.devices
             .iter()
@@ -161,3 +338,68 @@ impl Superblock for SuperblockVersion0 {
             .collect()
     }
 }
+
+impl SuperblockMut for SuperblockVersion0 {
+    fn set_event_count(&mut self, event_count: MetadataEventCount) {
+        self.event_count = event_count;
+        self.checkpoint_event_count = CheckpointEventCount(event_count.into());
+        self.update_checksum();
+    }
+
+    /// Marks the devices with a device number as active and in sync, and
+    /// the others as faulty or spare, and counts them again.
+    fn set_device_roles(&mut self, roles: &[MdDeviceRole]) -> io::Result<()> {
+        if roles.len() > Self::MAX_DEVICES {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        for (index, (descriptor, role)) in self.devices.iter_mut().zip(roles).enumerate() {
+            descriptor.index = index as u32;
+            descriptor.role = *role;
+            descriptor.state = if role.device_number().is_some() {
+                Self::DEVICE_ACTIVE_SYNC
+            } else if role.is_faulty() {
+                Self::DEVICE_FAULTY
+            } else {
+                0
+            };
+        }
+        (
+            self.total_device_count,
+            self.active_device_count,
+            self.working_device_count,
+            self.failed_device_count,
+            self.spare_device_count,
+        ) = Self::count_devices(roles);
+        if let Some(descriptor) = self.devices.get(self.device_role_index()) {
+            self.this_device = descriptor.clone();
+        }
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn set_device_role_index(&mut self, index: usize) -> io::Result<()> {
+        let descriptor = self.devices.get(index).ok_or(io::ErrorKind::InvalidInput)?;
+        self.this_device = DeviceDescriptor {
+            index: index as u32,
+            ..descriptor.clone()
+        };
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn clear_reshape(&mut self) {
+        self.minor_version = self.minor_version.min(90);
+        self.reshape_status = Self::no_reshape();
+        self.update_checksum();
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = self.buffer.clone();
+        if self.big_endian {
+            big_endian::write(self, &mut buffer);
+        } else {
+            little_endian::write(self, &mut buffer);
+        }
+        buffer
+    }
+}
@@ -1,5 +1,7 @@
 use binary_layout::LayoutAs;
+use itertools::Itertools;
 use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
 
 bitflags! {
     #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Default, Debug)]
@@ -19,6 +21,42 @@ bitflags! {
     }
 }
 
+impl Features {
+    /// Features that md enables on every member of an array alike, when the
+    /// array is created or grown.
+    pub const ARRAY_WIDE: Self = Self::BITMAP_OFFSET
+        .union(Self::BAD_BLOCKS)
+        .union(Self::BITMAP_VERSIONED)
+        .union(Self::JOURNAL)
+        .union(Self::PPL)
+        .union(Self::MULTIPLE_PPLS)
+        .union(Self::RAID0_LAYOUT);
+}
+
+impl Display for Features {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names = self.iter().map(|feature| match feature {
+            Self::BITMAP_OFFSET => "bitmap".to_string(),
+            Self::RECOVERY_OFFSET => "recovery offset".to_string(),
+            Self::RESHAPE_ACTIVE => "reshape".to_string(),
+            Self::BAD_BLOCKS => "bad block log".to_string(),
+            Self::REPLACEMENT => "replacement".to_string(),
+            Self::RESHAPE_BACKWARDS => "backwards reshape".to_string(),
+            Self::NEW_OFFSET => "new offset".to_string(),
+            Self::BITMAP_VERSIONED => "versioned bitmap".to_string(),
+            Self::JOURNAL => "journal".to_string(),
+            Self::PPL => "partial parity log".to_string(),
+            Self::MULTIPLE_PPLS => "multiple partial parity logs".to_string(),
+            Self::RAID0_LAYOUT => "RAID0 layout".to_string(),
+            unknown => format!("unknown feature {:#x}", unknown.bits()),
+        });
+        write!(f, "{}", names.format(", "))
+    }
+}
+
 impl LayoutAs<u32> for Features {
     type ReadError = Infallible;
     type WriteError = Infallible;
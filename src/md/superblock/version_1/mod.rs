@@ -7,4 +7,4 @@ mod superblock;
 mod tests;
 
 #[allow(unused_imports)]
-pub use superblock::SuperblockVersion1;
+pub use self::{features::Features, superblock::SuperblockVersion1};
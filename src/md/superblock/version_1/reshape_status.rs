@@ -32,3 +32,14 @@ impl<S: AsRef<[u8]>> From<ReshapeStatusVersion1<S>> for ReshapeStatus {
         }
     }
 }
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> ReshapeStatusVersion1<S> {
+    pub fn write(&mut self, status: &ReshapeStatus) {
+        self.new_level_mut().write(status.new_algorithm.level());
+        self.reshape_position_mut().write(status.reshape_position);
+        self.delta_devices_mut().write(status.delta_devices);
+        self.new_layout_mut().write(status.new_algorithm.layout());
+        self.new_chunk_size_mut().write(status.new_chunk_size);
+        self.new_offset_mut().write(status.new_offset);
+    }
+}
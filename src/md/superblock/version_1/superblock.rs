@@ -4,9 +4,11 @@ use crate::md::superblock::version_1::device_flags::DeviceFlags;
 use crate::md::superblock::version_1::features::Features;
 use crate::md::superblock::version_1::ppl_info::PplInfo;
 use crate::md::superblock::version_1::reshape_status::NestedReshapeStatusVersion1;
-use crate::md::superblock::{ArrayUuid, MdDeviceRole, Superblock};
-use crate::md::units::{DeviceCount, MetadataEventCount, SectorCount, SectorNumber};
-use binary_layout::binary_layout;
+use crate::md::superblock::{ArrayUuid, MdDeviceRole, NewSuperblock, Superblock, SuperblockMut};
+use crate::md::units::{
+    DeviceCount, DeviceNumber, MetadataEventCount, SectorCount, SectorNumber, Timestamp,
+};
+use binary_layout::{binary_layout, Field};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use std::cmp::min;
 use std::ffi::OsStr;
@@ -50,13 +52,50 @@ binary_layout!(layout, LittleEndian, {
 });
 
 pub struct SuperblockVersion1<S: AsRef<[u8]>> {
-    buffer: layout::View<S>,
+    buffer: S,
     minor_version: u32,
 }
 
 impl SuperblockVersion1<Vec<u8>> {
     pub const MAX_SIZE: usize = 4096;
 
+    /// Writes a superblock from scratch, for an array that is in sync.
+    pub fn create(new: &NewSuperblock, minor_version: u32) -> io::Result<Self> {
+        let mut superblock = Self::new(vec![0u8; Self::MAX_SIZE], minor_version);
+        let mut view = layout::View::new(superblock.buffer.as_mut_slice());
+        view.magic_mut().write(0xa92b4efc);
+        view.major_version_mut().write(1);
+        view.array_uuid_mut().copy_from_slice(&new.array_uuid);
+        let array_name = &new.array_name[..new.array_name.len().min(32)];
+        view.array_name_mut()[..array_name.len()].copy_from_slice(array_name);
+        view.ctime_mut().write(new.creation_time.to_version_1());
+        view.level_mut().write(new.format.algorithm.level());
+        view.layout_mut().write(new.format.algorithm.layout());
+        view.sectors_per_device_mut()
+            .write(new.format.sectors_per_device);
+        view.chunk_size_mut().write(new.format.chunk_size);
+        view.raid_device_count_mut().write(new.format.device_count);
+        view.data_offset_mut().write(new.data_offset);
+        view.data_size_mut().write(new.data_size.into());
+        view.super_offset_mut().write(new.super_offset.into());
+        view.device_uuid_mut().copy_from_slice(&new.device_uuid);
+        view.utime_mut().write(new.update_time.to_version_1());
+        view.event_count_mut().write(new.event_count);
+        if let Some(status) = &new.reshape_status {
+            let mut features = Features::RESHAPE_ACTIVE;
+            if status.new_offset != 0 {
+                features |= Features::NEW_OFFSET;
+            }
+            view.features_mut().write(features);
+            view.reshape_status_mut().write(status);
+        }
+        // Nothing is left to resync.
+        view.resync_offset_mut().write(u64::MAX);
+        superblock.set_device_roles(&new.device_roles)?;
+        superblock.set_device_role_index(new.device_role_index)?;
+        Ok(superblock)
+    }
+
     pub fn read<R: Read>(mut reader: R, minor_version: u32) -> io::Result<Self> {
         let mut buf = vec![0u8; Self::MAX_SIZE];
         reader.read_exact(&mut buf)?;
@@ -72,43 +111,51 @@ impl SuperblockVersion1<Vec<u8>> {
 impl<S: AsRef<[u8]>> SuperblockVersion1<S> {
     pub fn new(storage: S, minor_version: u32) -> Self {
         Self {
-            buffer: layout::View::new(storage),
+            buffer: storage,
             minor_version,
         }
     }
 
+    pub fn into_buffer(self) -> S {
+        self.buffer
+    }
+
+    fn view(&self) -> layout::View<&[u8]> {
+        layout::View::new(self.buffer.as_ref())
+    }
+
     pub fn valid_magic(&self) -> bool {
-        self.buffer.magic().read() == 0xa92b4efc
+        self.view().magic().read() == 0xa92b4efc
     }
 
     pub fn valid_major_version(&self) -> bool {
         self.major_version() == 1
     }
 
-    fn features(&self) -> Features {
-        self.buffer.features().read()
+    fn feature_map(&self) -> Features {
+        self.view().features().read()
     }
 
     fn has_bitmap_offset(&self) -> bool {
-        self.features().contains(Features::BITMAP_OFFSET)
+        self.feature_map().contains(Features::BITMAP_OFFSET)
     }
 
     fn has_recovery_offset(&self) -> bool {
-        self.features().contains(Features::RECOVERY_OFFSET)
+        self.feature_map().contains(Features::RECOVERY_OFFSET)
     }
 
     fn has_journal(&self) -> bool {
-        self.features().contains(Features::JOURNAL)
+        self.feature_map().contains(Features::JOURNAL)
     }
 
     fn has_ppl(&self) -> bool {
-        self.features().contains(Features::PPL)
+        self.feature_map().contains(Features::PPL)
     }
 
     pub fn bitmap_offset(&self) -> Option<u32> {
         if self.has_bitmap_offset() {
             Some(LittleEndian::read_u32(
-                self.buffer.bitmap_offset_or_ppl_info(),
+                self.view().bitmap_offset_or_ppl_info(),
             ))
         } else {
             None
@@ -117,7 +164,9 @@ impl<S: AsRef<[u8]>> SuperblockVersion1<S> {
 
     pub fn ppl_info(&self) -> Option<PplInfo<&[u8]>> {
         if self.has_ppl() {
-            Some(PplInfo::new(self.buffer.bitmap_offset_or_ppl_info()))
+            Some(PplInfo::new(
+                self.view().into_bitmap_offset_or_ppl_info().into_slice(),
+            ))
         } else {
             None
         }
@@ -125,7 +174,7 @@ impl<S: AsRef<[u8]>> SuperblockVersion1<S> {
 
     pub fn recovery_offset(&self) -> Option<u64> {
         if self.has_recovery_offset() {
-            Some(self.buffer.recovery_offset_or_journal_tail().read())
+            Some(self.view().recovery_offset_or_journal_tail().read())
         } else {
             None
         }
@@ -133,18 +182,146 @@ impl<S: AsRef<[u8]>> SuperblockVersion1<S> {
 
     pub fn journal_tail(&self) -> Option<u64> {
         if self.has_journal() {
-            Some(self.buffer.recovery_offset_or_journal_tail().read())
+            Some(self.view().recovery_offset_or_journal_tail().read())
         } else {
             None
         }
     }
 
+    pub fn super_offset(&self) -> SectorNumber {
+        SectorNumber(self.view().super_offset().read())
+    }
+
+    pub fn data_size(&self) -> SectorCount<u64> {
+        SectorCount(self.view().data_size().read())
+    }
+
+    pub fn valid_checksum(&self) -> bool {
+        self.view().superblock_checksum().read() == self.expected_checksum()
+    }
+
+    /// The checksum is the sum of the superblock as little-endian words,
+    /// including one device role for each of `max_devices`, with the
+    /// checksum field itself counted as zero. The carry is folded back into
+    /// the low 32 bits.
+    pub fn expected_checksum(&self) -> u32 {
+        let size = min(
+            layout::dev_roles::OFFSET
+                + usize::try_from(u32::from(self.view().max_devices().read()))
+                    .unwrap_or(usize::MAX)
+                    * size_of::<u16>(),
+            self.buffer.as_ref().len(),
+        );
+        let sum = self.buffer.as_ref()[..size]
+            .chunks(size_of::<u32>())
+            .map(|word| {
+                let mut bytes = [0u8; 4];
+                bytes[..word.len()].copy_from_slice(word);
+                u64::from(u32::from_le_bytes(bytes))
+            })
+            .sum::<u64>()
+            - u64::from(self.view().superblock_checksum().read());
+        ((sum & 0xffffffff) + (sum >> 32)) as u32
+    }
+
     fn level(&self) -> u32 {
-        self.buffer.level().read()
+        self.view().level().read()
     }
 
     fn layout(&self) -> u32 {
-        self.buffer.layout().read()
+        self.view().layout().read()
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> SuperblockVersion1<S> {
+    /// Turns the superblock of one member into one for a replacement holding
+    /// `device_number`, in the slot that member had in the device roles, or
+    /// else in the first faulty or spare slot. The state belonging to the
+    /// original device, such as its recovery offset and bad block log, is
+    /// dropped.
+    pub fn make_replacement(
+        &mut self,
+        device_number: DeviceNumber,
+        device_uuid: [u8; 16],
+    ) -> io::Result<()> {
+        let roles = self.device_roles();
+        let slot = roles
+            .iter()
+            .position(|role| role.device_number() == Some(device_number))
+            .or_else(|| roles.iter().position(MdDeviceRole::is_faulty))
+            .or_else(|| roles.iter().position(MdDeviceRole::is_spare))
+            .ok_or(io::ErrorKind::StorageFull)?;
+        let role = u16::try_from(u32::from(device_number)).or(Err(io::ErrorKind::InvalidInput))?;
+
+        let had_recovery_offset = self.has_recovery_offset();
+        let mut view = layout::View::new(self.buffer.as_mut());
+        LittleEndian::write_u16(&mut view.dev_roles_mut()[slot * size_of::<u16>()..], role);
+        view.device_role_index_mut().write(slot as u32);
+        view.device_uuid_mut().copy_from_slice(&device_uuid);
+        view.device_flags_mut().write(DeviceFlags::empty());
+        view.count_corrected_read_mut().write(0);
+        if had_recovery_offset {
+            view.recovery_offset_or_journal_tail_mut().write(0);
+        }
+        let features = view.features().read()
+            - (Features::RECOVERY_OFFSET | Features::BAD_BLOCKS | Features::REPLACEMENT);
+        view.features_mut().write(features);
+
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn update_checksum(&mut self) {
+        let checksum = self.expected_checksum();
+        layout::View::new(self.buffer.as_mut())
+            .superblock_checksum_mut()
+            .write(checksum);
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> SuperblockMut for SuperblockVersion1<S> {
+    fn set_event_count(&mut self, event_count: MetadataEventCount) {
+        layout::View::new(self.buffer.as_mut())
+            .event_count_mut()
+            .write(event_count);
+        self.update_checksum();
+    }
+
+    fn set_device_roles(&mut self, roles: &[MdDeviceRole]) -> io::Result<()> {
+        let mut view = layout::View::new(self.buffer.as_mut());
+        if roles.len() * size_of::<u16>() > view.dev_roles().len() {
+            Err(io::ErrorKind::InvalidInput)?;
+        }
+        for (slot, role) in roles.iter().enumerate() {
+            let role = role.to_u16().ok_or(io::ErrorKind::InvalidInput)?;
+            LittleEndian::write_u16(&mut view.dev_roles_mut()[slot * size_of::<u16>()..], role);
+        }
+        view.max_devices_mut()
+            .write(DeviceCount(roles.len() as u32));
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn set_device_role_index(&mut self, index: usize) -> io::Result<()> {
+        let index = u32::try_from(index).or(Err(io::ErrorKind::InvalidInput))?;
+        layout::View::new(self.buffer.as_mut())
+            .device_role_index_mut()
+            .write(index);
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn clear_reshape(&mut self) {
+        let mut view = layout::View::new(self.buffer.as_mut());
+        let features = view.features().read()
+            - (Features::RESHAPE_ACTIVE | Features::RESHAPE_BACKWARDS | Features::NEW_OFFSET);
+        view.features_mut().write(features);
+        self.buffer.as_mut()[layout::reshape_status::OFFSET..layout::data_offset::OFFSET].fill(0);
+        self.update_checksum();
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.buffer.as_ref().to_vec()
     }
 }
 
@@ -154,7 +331,7 @@ impl<S: AsRef<[u8]>> Superblock for SuperblockVersion1<S> {
     }
 
     fn major_version(&self) -> u32 {
-        self.buffer.major_version().read()
+        self.view().major_version().read()
     }
 
     fn minor_version(&self) -> u32 {
@@ -162,11 +339,13 @@ impl<S: AsRef<[u8]>> Superblock for SuperblockVersion1<S> {
     }
 
     fn array_uuid(&self) -> ArrayUuid {
-        ArrayUuid::from_u8_16(self.buffer.array_uuid())
+        ArrayUuid::from_u8_16(self.view().array_uuid())
     }
 
     fn array_name(&self) -> Option<&OsStr> {
-        Some(OsStr::from_bytes(self.buffer.array_name()))
+        Some(OsStr::from_bytes(
+            self.view().into_array_name().into_slice(),
+        ))
     }
 
     fn algorithm(&self) -> MdAlgorithm {
@@ -174,44 +353,64 @@ impl<S: AsRef<[u8]>> Superblock for SuperblockVersion1<S> {
     }
 
     fn sectors_per_device(&self) -> SectorCount<u64> {
-        self.buffer.sectors_per_device().read()
+        self.view().sectors_per_device().read()
     }
 
     fn chunk_size(&self) -> SectorCount<u32> {
-        self.buffer.chunk_size().read()
+        self.view().chunk_size().read()
     }
 
     fn raid_device_count(&self) -> DeviceCount {
-        self.buffer.raid_device_count().read()
+        self.view().raid_device_count().read()
     }
 
     fn reshape_status(&self) -> Option<ReshapeStatus> {
-        if self.features().contains(Features::RESHAPE_ACTIVE) {
-            Some(self.buffer.reshape_status().into())
+        if self.feature_map().contains(Features::RESHAPE_ACTIVE) {
+            Some(self.view().reshape_status().into())
         } else {
             None
         }
     }
 
     fn data_offset(&self) -> SectorNumber {
-        self.buffer.data_offset().read()
+        self.view().data_offset().read()
+    }
+
+    fn data_size(&self) -> Option<SectorCount<u64>> {
+        Some(SectorCount(self.view().data_size().read()))
+    }
+
+    fn super_offset(&self) -> Option<SectorNumber> {
+        Some(SectorNumber(self.view().super_offset().read()))
+    }
+
+    fn features(&self) -> Option<Features> {
+        Some(self.feature_map())
     }
 
     fn device_role_index(&self) -> usize {
-        self.buffer.device_role_index().read().try_into().unwrap()
+        self.view().device_role_index().read().try_into().unwrap()
     }
 
     fn event_count(&self) -> MetadataEventCount {
-        self.buffer.event_count().read()
+        self.view().event_count().read()
+    }
+
+    fn creation_time(&self) -> Option<Timestamp> {
+        Some(Timestamp::from_version_1(self.view().ctime().read()))
+    }
+
+    fn update_time(&self) -> Option<Timestamp> {
+        Some(Timestamp::from_version_1(self.view().utime().read()))
     }
 
     fn device_roles(&self) -> Vec<MdDeviceRole> {
         let count = min(
-            self.buffer.max_devices().read().into(),
-            self.buffer.dev_roles().len() / size_of::<u16>(),
+            self.view().max_devices().read().into(),
+            self.view().dev_roles().len() / size_of::<u16>(),
         );
         let mut buffer = vec![0u16; count];
-        self.buffer
+        self.view()
             .dev_roles()
             .read_u16_into::<LittleEndian>(&mut buffer)
             .unwrap();
@@ -1,7 +1,11 @@
+use crate::md::superblock::superblock::test::{assert_created, new_superblock};
 use crate::md::superblock::version_1::device_flags::DeviceFlags;
 use crate::md::superblock::version_1::features::Features;
 use crate::md::superblock::version_1::superblock::layout;
+use crate::md::superblock::SuperblockVersion1;
+use crate::md::superblock::{Superblock, SuperblockMut};
 use crate::md::units::{DeviceCount, MetadataEventCount, SectorCount, SectorNumber};
+use std::ffi::OsStr;
 
 const DATA: [u8; 4096] = [
     0xfc, 0x4e, 0x2b, 0xa9, // magic
@@ -368,3 +372,31 @@ fn view_of_superblock_version_1() {
         &[0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00]
     );
 }
+
+#[test]
+fn checksum_of_superblock_version_1() {
+    let superblock = SuperblockVersion1::new(DATA, 2);
+    assert_eq!(superblock.expected_checksum(), 0x0194d5f1);
+    assert!(!superblock.valid_checksum());
+
+    let mut data = DATA;
+    data[216..220].copy_from_slice(&0x0194d5f1u32.to_le_bytes());
+    assert!(SuperblockVersion1::new(data, 2).valid_checksum());
+}
+
+#[test]
+fn create_superblock_version_1() {
+    let bytes = SuperblockVersion1::create(&new_superblock(), 2)
+        .unwrap()
+        .to_bytes();
+    let superblock = SuperblockVersion1::read(&bytes[..], 2).unwrap();
+    assert!(superblock.valid_checksum());
+    assert_created(&superblock);
+    assert_eq!(superblock.data_offset(), SectorNumber(2048));
+    assert_eq!(
+        superblock.array_name(),
+        Some(OsStr::new(
+            "host:data\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"
+        ))
+    );
+}
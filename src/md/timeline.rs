@@ -0,0 +1,153 @@
+use crate::block_device::BlockDevice;
+use crate::ext::MultiMap;
+use crate::md::superblock::MdDeviceRole;
+use crate::md::units::{MetadataEventCount, Timestamp};
+use crate::md::{MdDevice, MdDeviceId};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// When the array was created, and when each device last wrote its
+/// superblock. A device stops being written when it drops out of the array,
+/// so the devices that dropped out first are the earliest here.
+#[derive(Clone, Debug)]
+pub struct MdTimeline {
+    /// Each creation time found, earliest first, with the devices that
+    /// record it. Devices that record different creation times were not
+    /// part of the same array when it was created.
+    pub creation_times: Vec<(Timestamp, Vec<Rc<MdDeviceId>>)>,
+
+    /// Earliest first.
+    pub updates: Vec<MdTimelineUpdate>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MdTimelineUpdate {
+    pub id: Rc<MdDeviceId>,
+    pub time: Timestamp,
+    pub event_count: MetadataEventCount,
+
+    /// The role of the device, according to its own superblock.
+    pub role: Option<MdDeviceRole>,
+}
+
+impl MdTimeline {
+    pub fn new<'a, D: BlockDevice + 'a>(
+        devices: impl IntoIterator<Item = &'a MdDevice<D>>,
+    ) -> Self {
+        let superblocks = devices
+            .into_iter()
+            .filter_map(|device| Some((device.id.clone(), device.superblock.as_option()?)))
+            .collect_vec();
+        let creation_times: HashMap<_, _> = HashMap::from_multi_iter(
+            superblocks
+                .iter()
+                .filter_map(|(id, superblock)| Some((superblock.creation_time()?, id.clone()))),
+        );
+        let updates = superblocks
+            .iter()
+            .filter_map(|(id, superblock)| {
+                Some(MdTimelineUpdate {
+                    id: id.clone(),
+                    time: superblock.update_time()?,
+                    event_count: superblock.event_count(),
+                    role: superblock
+                        .device_roles()
+                        .get(superblock.device_role_index())
+                        .copied(),
+                })
+            })
+            .sorted_by(|a, b| {
+                (a.time, a.event_count)
+                    .cmp(&(b.time, b.event_count))
+                    .then_with(|| a.id.cmp(&b.id))
+            })
+            .collect();
+        Self {
+            creation_times: creation_times
+                .into_iter()
+                .map(|(time, ids)| (time, ids.into_iter().sorted().collect()))
+                .sorted()
+                .collect(),
+            updates,
+        }
+    }
+}
+
+impl Display for MdTimeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.creation_times[..] {
+            [] => writeln!(f, "No creation time recorded.")?,
+            [(time, _)] => writeln!(f, "Array created {time}.")?,
+            creation_times => {
+                writeln!(f, "The devices record different creation times:")?;
+                for (time, ids) in creation_times {
+                    writeln!(f, "    {time}: {}", ids.iter().join(", "))?;
+                }
+            }
+        }
+
+        if self.updates.is_empty() {
+            return Ok(());
+        }
+        let newest = self
+            .updates
+            .iter()
+            .map(|update| update.event_count)
+            .max()
+            .unwrap_or(MetadataEventCount(0));
+        writeln!(f, "Superblocks last written, earliest first:")?;
+        for update in &self.updates {
+            write!(f, "    {}: {}", update.time, update.id)?;
+            if let Some(role) = update.role {
+                write!(f, " as {role}")?;
+            }
+            write!(f, ", {}", update.event_count)?;
+            if update.event_count < newest {
+                write!(
+                    f,
+                    ", {} behind",
+                    u64::from(newest) - u64::from(update.event_count)
+                )?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::fixture::test::{
+        open_member_images, pseudo_random_data, raid5_fixture, update_superblock,
+    };
+    use crate::md::MdArray;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn timeline() {
+        let data = pseudo_random_data(64 * 1024, 13);
+        let mut images = raid5_fixture([13; 16]).present_member_images(&data);
+        for (image, (utime, event_count)) in
+            images
+                .iter_mut()
+                .zip([(1700000600, 9), (1700000100, 5), (1700000600, 9)])
+        {
+            update_superblock(image, |superblock| {
+                LittleEndian::write_u64(&mut superblock[64..], 1700000000);
+                LittleEndian::write_u64(&mut superblock[192..], utime);
+                LittleEndian::write_u64(&mut superblock[200..], event_count);
+            });
+        }
+        let timeline = MdArray::open(open_member_images(images)).timeline();
+        assert_eq!(
+            timeline.to_string(),
+            "Array created 2023-11-14 22:13:20 UTC.\n\
+             Superblocks last written, earliest first:\n    \
+             2023-11-14 22:15:00 UTC: member1 as md device #1, 5 metadata events, 4 behind\n    \
+             2023-11-14 22:23:20 UTC: member0 as md device #0, 9 metadata events\n    \
+             2023-11-14 22:23:20 UTC: member2 as md device #2, 9 metadata events\n"
+        );
+    }
+}
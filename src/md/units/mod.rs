@@ -6,11 +6,12 @@ mod metadata_event_count;
 mod sector_count;
 mod sector_number;
 mod stripe_number;
+mod timestamp;
 
 #[allow(unused_imports)]
 pub use self::{
     checkpoint_event_count::CheckpointEventCount, chunk_number::ChunkNumber,
     device_count::DeviceCount, device_number::DeviceNumber,
     metadata_event_count::MetadataEventCount, sector_count::SectorCount,
-    sector_number::SectorNumber, stripe_number::StripeNumber,
+    sector_number::SectorNumber, stripe_number::StripeNumber, timestamp::Timestamp,
 };
@@ -0,0 +1,83 @@
+use std::fmt::{Display, Formatter};
+
+/// A time recorded in a superblock, since the Unix epoch.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Debug)]
+pub struct Timestamp {
+    pub seconds: u64,
+    pub microseconds: u32,
+}
+
+impl Timestamp {
+    pub fn from_seconds(seconds: u64) -> Self {
+        Self {
+            seconds,
+            microseconds: 0,
+        }
+    }
+
+    /// Decodes a time as version 1 superblocks store it, with the seconds in
+    /// the low 40 bits and the microseconds in the high 24 bits.
+    pub fn from_version_1(value: u64) -> Self {
+        Self {
+            seconds: value & 0xff_ffff_ffff,
+            microseconds: (value >> 40) as u32,
+        }
+    }
+
+    pub fn to_version_1(self) -> u64 {
+        (self.seconds & 0xff_ffff_ffff) | (u64::from(self.microseconds) << 40)
+    }
+}
+
+impl Display for Timestamp {
+    /// Writes the time in UTC, to the second.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let days = self.seconds / 86400;
+        let time_of_day = self.seconds % 86400;
+
+        // Converts days since 1970-01-01 to a date in the proleptic Gregorian
+        // calendar, counting in 400-year eras that start on March 1st.
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days % 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+            time_of_day / 3600,
+            time_of_day / 60 % 60,
+            time_of_day % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::md::units::Timestamp;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Timestamp::from_seconds(0).to_string(),
+            "1970-01-01 00:00:00 UTC"
+        );
+        assert_eq!(
+            Timestamp::from_seconds(951782400).to_string(),
+            "2000-02-29 00:00:00 UTC"
+        );
+        let timestamp = Timestamp::from_version_1((123456 << 40) | 1700000000);
+        assert_eq!(timestamp.microseconds, 123456);
+        assert_eq!(timestamp.to_string(), "2023-11-14 22:13:20 UTC");
+    }
+}